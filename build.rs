@@ -0,0 +1,25 @@
+//! Embeds the archive at the path in `SABA_REPLAY_ARCHIVE`, if set, so that
+//! the browser starts by replaying it instead of using the network. The
+//! archive is checked here so that a broken one fails the build.
+
+use std::{env, fs, path::PathBuf};
+
+use saba_core::transport::ReplayTransport;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SABA_REPLAY_ARCHIVE");
+
+    let archive = match env::var("SABA_REPLAY_ARCHIVE") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            if let Err(e) = ReplayTransport::load(path.as_ref()) {
+                panic!("cannot replay {}: {:?}", path, e);
+            }
+            fs::read_to_string(&path).expect("the archive was just loaded")
+        }
+        Err(_) => String::new(),
+    };
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("cargo sets OUT_DIR"));
+    fs::write(out_dir.join("replay_archive"), archive).expect("failed to write OUT_DIR");
+}
@@ -0,0 +1,48 @@
+//! A coarse millisecond clock for measuring request phases. It counts from
+//! when the processor was reset, so it tells durations but not the date.
+
+use core::{
+    arch::x86_64::{__cpuid, __get_cpuid_max, _rdtsc},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Used when the processor doesn't report its TSC frequency. Durations are
+/// then only approximate, but phases measured with the same clock are still
+/// comparable with each other.
+const FALLBACK_TSC_TICKS_PER_MS: u64 = 2_000_000;
+
+/// The TSC frequency once it has been looked up, or 0 before that.
+static TICKS_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the TSC frequency reported by CPUID, from the TSC to crystal clock
+/// ratio (leaf 0x15) or else the processor base frequency (leaf 0x16).
+fn tsc_ticks_per_ms() -> Option<u64> {
+    // SAFETY: `cpuid` is available on every x86_64 processor, and only leaves
+    // up to the reported maximum are queried.
+    let (max_leaf, _) = unsafe { __get_cpuid_max(0) };
+    if max_leaf >= 0x15 {
+        let leaf = unsafe { __cpuid(0x15) };
+        if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+            return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64 / 1000);
+        }
+    }
+    if max_leaf >= 0x16 {
+        let leaf = unsafe { __cpuid(0x16) };
+        if leaf.eax & 0xffff != 0 {
+            return Some((leaf.eax & 0xffff) as u64 * 1000);
+        }
+    }
+    None
+}
+
+/// Returns milliseconds elapsed since the processor was reset.
+pub fn now_ms() -> u64 {
+    let mut ticks_per_ms = TICKS_PER_MS.load(Ordering::Relaxed);
+    if ticks_per_ms == 0 {
+        ticks_per_ms = tsc_ticks_per_ms().unwrap_or(FALLBACK_TSC_TICKS_PER_MS);
+        TICKS_PER_MS.store(ticks_per_ms, Ordering::Relaxed);
+    }
+    // SAFETY: `rdtsc` has no side effects and is available on every x86_64
+    // processor.
+    unsafe { _rdtsc() / ticks_per_ms }
+}
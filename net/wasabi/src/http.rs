@@ -4,7 +4,15 @@ use alloc::{
     vec::Vec,
 };
 use noli::net::{lookup_host, SocketAddr, TcpStream};
-use saba_core::{error::Error, http::HttpResponse};
+use saba_core::{
+    error::Error,
+    http::{HttpRequest, HttpResponse},
+    network_log::Timings,
+    transport::HttpTransport,
+    url::Url,
+};
+
+use crate::clock::now_ms;
 
 pub struct HttpClient {}
 
@@ -14,6 +22,47 @@ impl HttpClient {
     }
 
     pub fn get(&self, host: String, port: u16, path: String) -> Result<HttpResponse, Error> {
+        let url = match Url::new(format!("http://{}:{}/{}", host, port, path)).parse() {
+            Ok(url) => url,
+            Err(e) => return Err(Error::UnexpectedInput(e)),
+        };
+
+        self.send(&HttpRequest::new("GET", url))
+    }
+
+    pub fn send(&self, request: &HttpRequest) -> Result<HttpResponse, Error> {
+        let mut body = Vec::new();
+        let mut response =
+            self.send_streaming(request, &mut |_, chunk| body.extend_from_slice(chunk))?;
+        match String::from_utf8(body) {
+            Ok(body) => response.set_body(body),
+            Err(e) => return Err(Error::Network(format!("Invalid received response: {}", e))),
+        }
+        Ok(response)
+    }
+
+    /// Sends `request` and hands each chunk of the response body to `on_body`
+    /// as it is read from the socket, once the headers have arrived. The body
+    /// is not kept, so the returned response only has the status, headers and
+    /// timings.
+    pub fn send_streaming(
+        &self,
+        request: &HttpRequest,
+        on_body: &mut dyn FnMut(&HttpResponse, &[u8]),
+    ) -> Result<HttpResponse, Error> {
+        let url = request.url();
+        let host = url.host();
+        let port = match url.port().parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => {
+                return Err(Error::UnexpectedInput(format!(
+                    "port number should be u16 but got {}",
+                    url.port()
+                )))
+            }
+        };
+
+        let started_at = now_ms();
         let ips = match lookup_host(&host) {
             Ok(ips) => {
                 if ips.len() < 1 {
@@ -29,6 +78,8 @@ impl HttpClient {
             }
         };
 
+        let resolved_at = now_ms();
+
         let socket_address: SocketAddr = (ips[0], port).into();
 
         let mut stream = match TcpStream::connect(socket_address) {
@@ -37,19 +88,21 @@ impl HttpClient {
                 return Err(Error::Network("Failed to connect TCP stream".to_string()));
             }
         };
+        let connected_at = now_ms();
 
-        let mut request = String::from("GET /");
-        request.push_str(&path);
-        request.push_str(" HTTP/1.1\n");
-
-        request.push_str("Host: ");
-        request.push_str(&host);
-        request.push('\n');
-        request.push_str("Accept: text/html\n");
-        request.push_str("Connection: close\n");
-        request.push('\n');
+        // the network log reports the same request line and headers
+        let mut raw_request = request.request_line();
+        raw_request.push_str("\r\n");
+        for header in request.wire_headers() {
+            raw_request.push_str(&header.name());
+            raw_request.push_str(": ");
+            raw_request.push_str(&header.value());
+            raw_request.push_str("\r\n");
+        }
+        raw_request.push_str("\r\n");
+        raw_request.push_str(&request.body());
 
-        let _bytes_written = match stream.write(request.as_bytes()) {
+        let _bytes_written = match stream.write(raw_request.as_bytes()) {
             Ok(bytes) => bytes,
             Err(_) => {
                 return Err(Error::Network(
@@ -58,7 +111,15 @@ impl HttpClient {
             }
         };
 
+        let sent_at = now_ms();
+
+        // the bytes read before the end of the headers
         let mut received = Vec::new();
+        // how much of `received` has been searched for the end of the headers
+        let mut scanned = 0;
+        // the status line and headers, once all of them have been read
+        let mut head: Option<HttpResponse> = None;
+        let mut first_byte_at = None;
         loop {
             let mut buf = [0u8; 4096];
             let bytes_read = match stream.read(&mut buf) {
@@ -72,12 +133,86 @@ impl HttpClient {
             if bytes_read == 0 {
                 break;
             }
-            received.extend_from_slice(&buf[..bytes_read]);
+            if first_byte_at.is_none() {
+                first_byte_at = Some(now_ms());
+            }
+
+            match head {
+                Some(ref head) => on_body(head, &buf[..bytes_read]),
+                None => {
+                    received.extend_from_slice(&buf[..bytes_read]);
+                    if let Some(start) = body_start(&received, &mut scanned) {
+                        let parsed = match core::str::from_utf8(&received[..start]) {
+                            Ok(h) => HttpResponse::new(h.to_string())?,
+                            Err(e) => {
+                                return Err(Error::Network(format!(
+                                    "Invalid received response: {}",
+                                    e
+                                )))
+                            }
+                        };
+                        on_body(&parsed, &received[start..]);
+                        head = Some(parsed);
+                        received = Vec::new();
+                    }
+                }
+            }
         }
 
-        match core::str::from_utf8(&received) {
-            Ok(response) => HttpResponse::new(response.to_string()),
-            Err(e) => Err(Error::Network(format!("Invalid received response: {}", e))),
+        let received_at = now_ms();
+        let first_byte_at = first_byte_at.unwrap_or(received_at);
+
+        let mut response = match head {
+            Some(head) => head,
+            // the connection was closed before the headers ended
+            None => match core::str::from_utf8(&received) {
+                Ok(response) => HttpResponse::new(response.to_string())?,
+                Err(e) => return Err(Error::Network(format!("Invalid received response: {}", e))),
+            },
+        };
+        // WasabiOS has no wall clock, so the start is the time since reset
+        // counted from the Unix epoch.
+        response.set_timings(Timings::new(
+            started_at,
+            resolved_at - started_at,
+            connected_at - resolved_at,
+            sent_at - connected_at,
+            first_byte_at - sent_at,
+            received_at - first_byte_at,
+        ));
+
+        Ok(response)
+    }
+}
+
+/// Returns where the body starts in `received`, once the empty line after the
+/// headers has been read. `scanned` is how much of `received` earlier calls
+/// have searched; the search picks up from there.
+fn body_start(received: &[u8], scanned: &mut usize) -> Option<usize> {
+    // the empty line may have started at the end of what was searched before
+    let from = scanned.saturating_sub(3);
+    *scanned = received.len();
+    (from..received.len()).find_map(|i| {
+        if received[i..].starts_with(b"\r\n\r\n") {
+            Some(i + 4)
+        } else if received[i..].starts_with(b"\n\n") {
+            Some(i + 2)
+        } else {
+            None
         }
+    })
+}
+
+impl HttpTransport for HttpClient {
+    fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, Error> {
+        HttpClient::send(self, request)
+    }
+
+    fn send_streaming(
+        &mut self,
+        request: &HttpRequest,
+        on_body: &mut dyn FnMut(&HttpResponse, &[u8]),
+    ) -> Result<HttpResponse, Error> {
+        HttpClient::send_streaming(self, request, on_body)
     }
 }
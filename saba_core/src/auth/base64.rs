@@ -0,0 +1,47 @@
+use alloc::string::String;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(input: &[u8]) -> String {
+    let mut result = String::new();
+
+    for chunk in input.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = chunk.get(1).copied().unwrap_or(0) as u32;
+        let b2 = chunk.get(2).copied().unwrap_or(0) as u32;
+        let n = (b0 << 16) | (b1 << 8) | b2;
+
+        result.push(ALPHABET[(n >> 18) as usize & 0x3f] as char);
+        result.push(ALPHABET[(n >> 12) as usize & 0x3f] as char);
+        if chunk.len() > 1 {
+            result.push(ALPHABET[(n >> 6) as usize & 0x3f] as char);
+        } else {
+            result.push('=');
+        }
+        if chunk.len() > 2 {
+            result.push(ALPHABET[n as usize & 0x3f] as char);
+        } else {
+            result.push('=');
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn encode_with_padding() {
+        assert_eq!(encode(b""), "".to_string());
+        assert_eq!(encode(b"f"), "Zg==".to_string());
+        assert_eq!(encode(b"fo"), "Zm8=".to_string());
+        assert_eq!(encode(b"foo"), "Zm9v".to_string());
+        assert_eq!(
+            encode(b"Aladdin:open sesame"),
+            "QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_string()
+        );
+    }
+}
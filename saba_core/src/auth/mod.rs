@@ -0,0 +1,559 @@
+//! HTTP authentication (RFC 7235) with the Basic (RFC 7617) and Digest
+//! (RFC 7616) schemes.
+
+mod base64;
+mod md5;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{error::Error, http::HttpResponse, url::Url};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthScheme {
+    Basic,
+    Digest,
+    Other(String),
+}
+
+impl AuthScheme {
+    fn from_name(name: &str) -> Self {
+        if name.eq_ignore_ascii_case("basic") {
+            AuthScheme::Basic
+        } else if name.eq_ignore_ascii_case("digest") {
+            AuthScheme::Digest
+        } else {
+            AuthScheme::Other(name.to_string())
+        }
+    }
+}
+
+/// A single challenge from a `WWW-Authenticate` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    scheme: AuthScheme,
+    params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    pub fn scheme(&self) -> AuthScheme {
+        self.scheme.clone()
+    }
+
+    /// Returns the value of the auth-param `name`. Names are case-insensitive.
+    pub fn param(&self, name: &str) -> Option<String> {
+        self.params
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.clone())
+    }
+
+    pub fn realm(&self) -> String {
+        self.param("realm").unwrap_or_default()
+    }
+
+    fn is_supported(&self) -> bool {
+        match self.scheme {
+            AuthScheme::Basic => true,
+            AuthScheme::Digest => {
+                let algorithm = self.param("algorithm").unwrap_or("MD5".to_string());
+                self.param("nonce").is_some()
+                    && (algorithm.eq_ignore_ascii_case("MD5")
+                        || algorithm.eq_ignore_ascii_case("MD5-sess"))
+                    && self.digest_qop().is_ok()
+            }
+            AuthScheme::Other(_) => false,
+        }
+    }
+
+    /// Returns `Some("auth")` when the server offers qop=auth, `None` for
+    /// legacy RFC 2069 challenges without qop.
+    fn digest_qop(&self) -> Result<Option<&'static str>, Error> {
+        match self.param("qop") {
+            None => Ok(None),
+            Some(qop) => {
+                if qop
+                    .split(',')
+                    .any(|q| q.trim().eq_ignore_ascii_case("auth"))
+                {
+                    Ok(Some("auth"))
+                } else {
+                    Err(Error::Network(format!("unsupported digest qop: {}", qop)))
+                }
+            }
+        }
+    }
+
+    /// Returns true if the server rejected only the nonce, not the credentials.
+    pub fn is_stale(&self) -> bool {
+        self.param("stale")
+            .map(|s| s.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+    }
+}
+
+struct ChallengeParser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ChallengeParser<'a> {
+    fn is_token_char(c: u8) -> bool {
+        c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.input.len()
+            && (self.input[self.pos] == b' ' || self.input[self.pos] == b'\t')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_whitespace_and_commas(&mut self) {
+        while self.pos < self.input.len()
+            && (self.input[self.pos] == b' '
+                || self.input[self.pos] == b'\t'
+                || self.input[self.pos] == b',')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn token(&mut self) -> String {
+        let start = self.pos;
+        while self.pos < self.input.len() && Self::is_token_char(self.input[self.pos]) {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.input[start..self.pos]).to_string()
+    }
+
+    fn quoted_string(&mut self) -> String {
+        // skip the opening quote
+        self.pos += 1;
+        let mut value = Vec::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    if let Some(escaped) = self.peek() {
+                        value.push(escaped);
+                        self.pos += 1;
+                    }
+                }
+                _ => value.push(c),
+            }
+        }
+        String::from_utf8_lossy(&value).to_string()
+    }
+
+    fn parse(&mut self) -> Vec<Challenge> {
+        let mut challenges = Vec::new();
+
+        loop {
+            self.skip_whitespace_and_commas();
+            let scheme = self.token();
+            if scheme.is_empty() {
+                break;
+            }
+
+            let mut params = Vec::new();
+            loop {
+                let checkpoint = self.pos;
+                self.skip_whitespace_and_commas();
+                let name = self.token();
+                self.skip_whitespace();
+                if name.is_empty() || self.peek() != Some(b'=') {
+                    // the token starts the next challenge
+                    self.pos = checkpoint;
+                    break;
+                }
+
+                // consume '='
+                self.pos += 1;
+                self.skip_whitespace();
+                let value = if self.peek() == Some(b'"') {
+                    self.quoted_string()
+                } else {
+                    self.token()
+                };
+                params.push((name.to_ascii_lowercase(), value));
+            }
+
+            challenges.push(Challenge {
+                scheme: AuthScheme::from_name(&scheme),
+                params,
+            });
+        }
+
+        challenges
+    }
+}
+
+/// Parses the value of a `WWW-Authenticate` header, which may contain
+/// several challenges.
+pub fn parse_challenges(value: &str) -> Vec<Challenge> {
+    ChallengeParser {
+        input: value.as_bytes(),
+        pos: 0,
+    }
+    .parse()
+}
+
+/// Picks the strongest supported challenge from a 401 response.
+pub fn select_challenge(response: &HttpResponse) -> Option<Challenge> {
+    let challenges: Vec<Challenge> = response
+        .header_values("WWW-Authenticate")
+        .iter()
+        .flat_map(|value| parse_challenges(value))
+        .filter(|c| c.is_supported())
+        .collect();
+
+    challenges
+        .iter()
+        .find(|c| c.scheme == AuthScheme::Digest)
+        .or(challenges.first())
+        .cloned()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl Credentials {
+    pub fn new(username: String, password: String) -> Self {
+        Self { username, password }
+    }
+
+    pub fn username(&self) -> String {
+        self.username.clone()
+    }
+}
+
+/// The canonical root URL and realm that a set of credentials applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtectionSpace {
+    host: String,
+    port: String,
+    realm: String,
+}
+
+impl ProtectionSpace {
+    pub fn new(url: &Url, realm: String) -> Self {
+        Self {
+            host: url.host(),
+            port: url.port(),
+            realm,
+        }
+    }
+
+    pub fn host(&self) -> String {
+        self.host.clone()
+    }
+
+    pub fn port(&self) -> String {
+        self.port.clone()
+    }
+
+    pub fn realm(&self) -> String {
+        self.realm.clone()
+    }
+}
+
+/// Builds the value of the `Authorization` header answering `challenge`.
+pub fn authorization(
+    challenge: &Challenge,
+    credentials: &Credentials,
+    method: &str,
+    uri: &str,
+    nonce_count: u32,
+    cnonce: &str,
+) -> Result<String, Error> {
+    match challenge.scheme {
+        AuthScheme::Basic => {
+            let user_pass = format!("{}:{}", credentials.username, credentials.password);
+            Ok(format!("Basic {}", base64::encode(user_pass.as_bytes())))
+        }
+        AuthScheme::Digest => {
+            let realm = challenge.realm();
+            let nonce = match challenge.param("nonce") {
+                Some(nonce) => nonce,
+                None => return Err(Error::Network("digest challenge without nonce".to_string())),
+            };
+            let algorithm = challenge.param("algorithm");
+            let qop = challenge.digest_qop()?;
+            let nc = format!("{:08x}", nonce_count);
+
+            let mut ha1 = md5::hex_digest(&format!(
+                "{}:{}:{}",
+                credentials.username, realm, credentials.password
+            ));
+            match algorithm {
+                Some(ref a) if a.eq_ignore_ascii_case("MD5-sess") => {
+                    ha1 = md5::hex_digest(&format!("{}:{}:{}", ha1, nonce, cnonce));
+                }
+                Some(ref a) if !a.eq_ignore_ascii_case("MD5") => {
+                    return Err(Error::Network(format!(
+                        "unsupported digest algorithm: {}",
+                        a
+                    )));
+                }
+                _ => {}
+            }
+            let ha2 = md5::hex_digest(&format!("{}:{}", method, uri));
+
+            let response = match qop {
+                Some(qop) => md5::hex_digest(&format!(
+                    "{}:{}:{}:{}:{}:{}",
+                    ha1, nonce, nc, cnonce, qop, ha2
+                )),
+                None => md5::hex_digest(&format!("{}:{}:{}", ha1, nonce, ha2)),
+            };
+
+            let mut value = format!(
+                "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\"",
+                credentials.username, realm, nonce, uri
+            );
+            if let Some(a) = algorithm {
+                value.push_str(&format!(", algorithm={}", a));
+            }
+            value.push_str(&format!(", response=\"{}\"", response));
+            if let Some(opaque) = challenge.param("opaque") {
+                value.push_str(&format!(", opaque=\"{}\"", opaque));
+            }
+            if let Some(qop) = qop {
+                value.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+            }
+            Ok(value)
+        }
+        AuthScheme::Other(ref name) => Err(Error::Network(format!(
+            "unsupported authentication scheme: {}",
+            name
+        ))),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    space: ProtectionSpace,
+    credentials: Credentials,
+    challenge: Challenge,
+    /// Directories known to be inside the protection space.
+    paths: Vec<String>,
+    nonce_count: u32,
+}
+
+fn directory_of(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(index) => path[..index + 1].to_string(),
+        None => String::new(),
+    }
+}
+
+/// Credentials the user entered during this session, keyed by protection
+/// space.
+#[derive(Debug, Clone, Default)]
+pub struct CredentialCache {
+    entries: Vec<CacheEntry>,
+    cnonce_seed: u32,
+}
+
+impl CredentialCache {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            cnonce_seed: 0,
+        }
+    }
+
+    pub fn get(&self, space: &ProtectionSpace) -> Option<Credentials> {
+        self.entries
+            .iter()
+            .find(|e| e.space == *space)
+            .map(|e| e.credentials.clone())
+    }
+
+    /// Stores `credentials` for `space`, remembering the latest challenge and
+    /// that `url` belongs to the space.
+    pub fn insert(
+        &mut self,
+        space: ProtectionSpace,
+        credentials: Credentials,
+        challenge: Challenge,
+        url: &Url,
+    ) {
+        let directory = directory_of(url);
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.space == space) {
+            entry.credentials = credentials;
+            entry.challenge = challenge;
+            entry.nonce_count = 0;
+            if !entry.paths.contains(&directory) {
+                entry.paths.push(directory);
+            }
+            return;
+        }
+
+        self.entries.push(CacheEntry {
+            space,
+            credentials,
+            challenge,
+            paths: Vec::from([directory]),
+            nonce_count: 0,
+        });
+    }
+
+    pub fn remove(&mut self, space: &ProtectionSpace) {
+        self.entries.retain(|e| e.space != *space);
+    }
+
+    /// Returns the `Authorization` header to send preemptively for a request
+    /// to `url`, if it is known to be inside a cached protection space.
+    pub fn authorization(&mut self, method: &str, url: &Url) -> Option<String> {
+        let path = url.path();
+        let mut best: Option<(usize, usize)> = None;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.space.host != url.host() || entry.space.port != url.port() {
+                continue;
+            }
+            for p in &entry.paths {
+                let longer = match best {
+                    Some((_, len)) => p.len() > len,
+                    None => true,
+                };
+                if path.starts_with(p.as_str()) && longer {
+                    best = Some((i, p.len()));
+                }
+            }
+        }
+
+        let (index, _) = best?;
+        self.cnonce_seed = self.cnonce_seed.wrapping_add(1);
+        let seed = self.cnonce_seed;
+        let entry = &mut self.entries[index];
+        entry.nonce_count += 1;
+        let cnonce = md5::hex_digest(&format!(
+            "{}:{}:{}",
+            entry.challenge.param("nonce").unwrap_or_default(),
+            entry.nonce_count,
+            seed
+        ));
+
+        authorization(
+            &entry.challenge,
+            &entry.credentials,
+            method,
+            &url.request_target(),
+            entry.nonce_count,
+            &cnonce[..16],
+        )
+        .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_multiple_challenges() {
+        let challenges = parse_challenges(
+            "Newauth realm=\"apps\", type=1, title=\"Login to \\\"apps\\\"\", Basic realm=\"simple\"",
+        );
+
+        assert_eq!(challenges.len(), 2);
+        assert_eq!(
+            challenges[0].scheme(),
+            AuthScheme::Other("Newauth".to_string())
+        );
+        assert_eq!(challenges[0].param("type"), Some("1".to_string()));
+        assert_eq!(
+            challenges[0].param("title"),
+            Some("Login to \"apps\"".to_string())
+        );
+        assert_eq!(challenges[1].scheme(), AuthScheme::Basic);
+        assert_eq!(challenges[1].realm(), "simple".to_string());
+    }
+
+    #[test]
+    fn select_digest_over_basic() {
+        let raw = "HTTP/1.1 401 Unauthorized\nWWW-Authenticate: Basic realm=\"a\"\nWWW-Authenticate: Digest realm=\"a\", nonce=\"xyz\", qop=\"auth,auth-int\"\n\n".to_string();
+        let response = HttpResponse::new(raw).unwrap();
+
+        assert_eq!(
+            select_challenge(&response).unwrap().scheme(),
+            AuthScheme::Digest
+        );
+    }
+
+    #[test]
+    fn basic_authorization() {
+        let challenge = parse_challenges("Basic realm=\"WallyWorld\"").remove(0);
+        let credentials = Credentials::new("Aladdin".to_string(), "open sesame".to_string());
+
+        assert_eq!(
+            authorization(&challenge, &credentials, "GET", "/", 1, "").unwrap(),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_string()
+        );
+    }
+
+    #[test]
+    fn digest_authorization_rfc2617_example() {
+        let challenge = parse_challenges(
+            "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        )
+        .remove(0);
+        let credentials = Credentials::new("Mufasa".to_string(), "Circle Of Life".to_string());
+
+        let value = authorization(
+            &challenge,
+            &credentials,
+            "GET",
+            "/dir/index.html",
+            1,
+            "0a4f113b",
+        )
+        .unwrap();
+
+        assert!(value.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(value.contains("nc=00000001"));
+        assert!(value.contains("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+    }
+
+    #[test]
+    fn cache_is_keyed_by_protection_space() {
+        let url = Url::new("http://example.com/private/index.html".to_string())
+            .parse()
+            .unwrap();
+        let challenge = parse_challenges("Basic realm=\"r\"").remove(0);
+        let mut cache = CredentialCache::new();
+        cache.insert(
+            ProtectionSpace::new(&url, "r".to_string()),
+            Credentials::new("u".to_string(), "p".to_string()),
+            challenge,
+            &url,
+        );
+
+        let same_dir = Url::new("http://example.com/private/other.html".to_string())
+            .parse()
+            .unwrap();
+        let other_dir = Url::new("http://example.com/public/index.html".to_string())
+            .parse()
+            .unwrap();
+        let other_realm = ProtectionSpace::new(&url, "s".to_string());
+
+        assert!(cache.authorization("GET", &same_dir).is_some());
+        assert!(cache.authorization("GET", &other_dir).is_none());
+        assert!(cache.get(&other_realm).is_none());
+    }
+}
@@ -2,12 +2,14 @@ use core::cell::RefCell;
 
 use alloc::{rc::Rc, vec::Vec};
 
-use crate::renderer::page::Page;
+use crate::{auth::CredentialCache, network_log::NetworkLog, renderer::page::Page};
 
 #[derive(Debug, Clone)]
 pub struct Browser {
     active_page_index: usize,
     pages: Vec<Rc<RefCell<Page>>>,
+    credentials: CredentialCache,
+    network_log: NetworkLog,
 }
 
 impl Browser {
@@ -17,6 +19,8 @@ impl Browser {
         let browser = Rc::new(RefCell::new(Self {
             active_page_index: 0,
             pages: Vec::new(),
+            credentials: CredentialCache::new(),
+            network_log: NetworkLog::new(),
         }));
 
         page.set_browser(Rc::downgrade(&browser));
@@ -28,4 +32,18 @@ impl Browser {
     pub fn current_page(&self) -> Rc<RefCell<Page>> {
         self.pages[self.active_page_index].clone()
     }
+
+    pub fn credentials_mut(&mut self) -> &mut CredentialCache {
+        &mut self.credentials
+    }
+
+    pub fn network_log(&self) -> &NetworkLog {
+        &self.network_log
+    }
+
+    /// Returns the state shared by every navigation: the credential cache and
+    /// the network log.
+    pub fn session_mut(&mut self) -> (&mut CredentialCache, &mut NetworkLog) {
+        (&mut self.credentials, &mut self.network_log)
+    }
 }
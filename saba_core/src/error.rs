@@ -5,5 +5,7 @@ pub enum Error {
     Network(String),
     InvalidUI(String),
     UnexpectedInput(String),
+    HierarchyRequest(String),
+    NotFound(String),
     Other(String),
 }
@@ -1,4 +1,4 @@
-use crate::error::Error;
+use crate::{error::Error, network_log::Timings, referrer::ReferrerPolicy, url::Url};
 
 use alloc::{
     format,
@@ -16,6 +16,126 @@ impl Header {
     pub fn new(name: String, value: String) -> Self {
         Self { name, value }
     }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn value(&self) -> String {
+        self.value.clone()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct HttpRequest {
+    method: String,
+    url: Url,
+    headers: Vec<Header>,
+    body: String,
+    /// The URL of the document that initiated this request, if any.
+    referrer: Option<Url>,
+    referrer_policy: ReferrerPolicy,
+}
+
+impl HttpRequest {
+    pub fn new(method: &str, url: Url) -> Self {
+        Self {
+            method: method.to_string(),
+            url,
+            headers: Vec::new(),
+            body: String::new(),
+            referrer: None,
+            referrer_policy: ReferrerPolicy::default(),
+        }
+    }
+
+    pub fn get(url: String) -> Result<Self, Error> {
+        match Url::new(url).parse() {
+            Ok(url) => Ok(Self::new("GET", url)),
+            Err(e) => Err(Error::UnexpectedInput(format!(
+                "input url is not supported: {:?}",
+                e
+            ))),
+        }
+    }
+
+    pub fn method(&self) -> String {
+        self.method.clone()
+    }
+
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    pub fn set_url(&mut self, url: Url) {
+        self.url = url;
+    }
+
+    pub fn headers(&self) -> Vec<Header> {
+        self.headers.clone()
+    }
+
+    pub fn body(&self) -> String {
+        self.body.clone()
+    }
+
+    pub fn set_body(&mut self, body: String) {
+        self.body = body;
+    }
+
+    pub fn referrer(&self) -> Option<Url> {
+        self.referrer.clone()
+    }
+
+    pub fn referrer_policy(&self) -> ReferrerPolicy {
+        self.referrer_policy
+    }
+
+    pub fn set_referrer(&mut self, referrer: Option<Url>, policy: ReferrerPolicy) {
+        self.referrer = referrer;
+        self.referrer_policy = policy;
+    }
+
+    /// Sets a header, replacing any existing header with the same name.
+    pub fn set_header(&mut self, name: &str, value: String) {
+        self.remove_header(name);
+        self.headers.push(Header::new(name.to_string(), value));
+    }
+
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers.retain(|h| !h.name.eq_ignore_ascii_case(name));
+    }
+
+    pub fn header_value(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.clone())
+    }
+
+    /// Returns the request line, such as `GET /index.html HTTP/1.1`.
+    pub fn request_line(&self) -> String {
+        format!("{} {} HTTP/1.1", self.method, self.url.request_target())
+    }
+
+    /// Returns the headers in the order they are written on the wire: `Host`,
+    /// then `Accept` unless the request sets it, the request's own headers,
+    /// `Content-Length` when there is a body to send, and `Connection`.
+    pub fn wire_headers(&self) -> Vec<Header> {
+        let mut headers = Vec::from([Header::new("Host".to_string(), self.url.host())]);
+        if self.header_value("Accept").is_none() {
+            headers.push(Header::new("Accept".to_string(), "text/html".to_string()));
+        }
+        headers.extend(self.headers.iter().cloned());
+        if !self.body.is_empty() || self.method == "POST" {
+            headers.push(Header::new(
+                "Content-Length".to_string(),
+                self.body.len().to_string(),
+            ));
+        }
+        headers.push(Header::new("Connection".to_string(), "close".to_string()));
+        headers
+    }
 }
 
 #[derive(Debug)]
@@ -24,46 +144,50 @@ pub struct HttpResponse {
     status_code: u32,
     reason: String,
     headers: Vec<Header>,
+    /// The status line and headers exactly as they were received.
+    head: String,
     body: String,
+    /// How long each phase of the exchange took, as measured by the client.
+    timings: Timings,
 }
 
 impl HttpResponse {
     pub fn new(raw_response: String) -> Result<Self, Error> {
-        let preprocessed_response = raw_response.trim_start().replace("\n\r", "\n");
-        let (status_line, remaining) = match preprocessed_response.split_once('\n') {
+        let (head, body) = raw_response.split_at(head_len(&raw_response));
+        let preprocessed_head = head.trim_start();
+        let (status_line, remaining) = match preprocessed_head.split_once('\n') {
             Some((s, r)) => (s, r),
             None => {
                 return Err(Error::Network(format!(
                     "invalid http response: {}",
-                    preprocessed_response
+                    preprocessed_head
                 )));
             }
         };
 
-        let statuses: Vec<&str> = status_line.split(' ').collect();
-
-        let (headers, body) = match remaining.split_once("\n\n") {
-            Some((h, body)) => {
-                let mut headers = Vec::new();
-                for header in h.split('\n') {
-                    let splitted_header: Vec<&str> = header.splitn(2, ':').collect();
-                    headers.push(Header::new(
-                        String::from(splitted_header[0].trim()),
-                        String::from(splitted_header[1].trim()),
-                    ));
-                }
+        // The reason phrase is the rest of the line and may contain spaces.
+        let statuses: Vec<&str> = status_line.trim_end().splitn(3, ' ').collect();
 
-                (headers, body)
+        let mut headers = Vec::new();
+        for header in remaining.split('\n').map(|h| h.trim_end()) {
+            if header.is_empty() {
+                continue;
             }
-            None => (Vec::new(), remaining.trim_start_matches("\n")),
-        };
+            let splitted_header: Vec<&str> = header.splitn(2, ':').collect();
+            headers.push(Header::new(
+                String::from(splitted_header[0].trim()),
+                String::from(splitted_header.get(1).unwrap_or(&"").trim()),
+            ));
+        }
 
         Ok(Self {
             version: statuses[0].to_string(),
             status_code: statuses[1].parse().unwrap_or(404),
-            reason: statuses[2].to_string(),
+            reason: statuses.get(2).unwrap_or(&"").to_string(),
             headers,
+            head: head.to_string(),
             body: body.to_string(),
+            timings: Timings::default(),
         })
     }
 
@@ -83,10 +207,28 @@ impl HttpResponse {
         self.headers.clone()
     }
 
+    /// Returns the status line and headers exactly as they were received, up
+    /// to and including the empty line that ends them.
+    pub fn head(&self) -> String {
+        self.head.clone()
+    }
+
     pub fn body(&self) -> String {
         self.body.clone()
     }
 
+    pub fn set_body(&mut self, body: String) {
+        self.body = body;
+    }
+
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    pub fn set_timings(&mut self, timings: Timings) {
+        self.timings = timings;
+    }
+
     pub fn header_value(&self, name: &str) -> Result<String, String> {
         for h in &self.headers {
             if h.name == name {
@@ -96,6 +238,33 @@ impl HttpResponse {
 
         Err(format!("failed to find {} in headers", name))
     }
+
+    /// Returns the values of every header named `name`, compared
+    /// case-insensitively, in the order they were received.
+    pub fn header_values(&self, name: &str) -> Vec<String> {
+        self.headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.clone())
+            .collect()
+    }
+}
+
+/// Returns the length of the status line and headers at the start of `raw`,
+/// up to and including the empty line that ends them. Empty lines before the
+/// status line are skipped over.
+fn head_len(raw: &str) -> usize {
+    let mut len = 0;
+    let mut seen_status_line = false;
+    for line in raw.split_inclusive('\n') {
+        len += line.len();
+        let is_empty = line.trim_end().is_empty();
+        if is_empty && seen_status_line && line.ends_with('\n') {
+            return len;
+        }
+        seen_status_line |= !is_empty;
+    }
+    raw.len()
 }
 
 #[cfg(test)]
@@ -120,6 +289,14 @@ mod tests {
         assert_eq!(result.reason(), "OK");
     }
 
+    #[test]
+    fn reason_phrase_keeps_every_word() {
+        let raw = "HTTP/1.1 301 Moved Permanently\n\n".to_string();
+        let result = HttpResponse::new(raw).unwrap();
+
+        assert_eq!(result.reason(), "Moved Permanently");
+    }
+
     #[test]
     fn status_code_should_be_404_when_invalid_number_comes() {
         let raw = "HTTP/1.1 xxx OK\n\n".to_string();
@@ -195,5 +372,32 @@ mod tests {
                 Err("failed to find Connection in headers".to_string())
             );
         }
+
+        #[test]
+        fn header_values_collects_repeated_headers() {
+            let raw = "HTTP/1.1 401 Unauthorized\nWWW-Authenticate: Basic realm=\"a\"\nwww-authenticate: Digest realm=\"b\", nonce=\"n\"\n\n".to_string();
+            let response = HttpResponse::new(raw).unwrap();
+
+            assert_eq!(
+                response.header_values("WWW-Authenticate"),
+                Vec::from([
+                    "Basic realm=\"a\"".to_string(),
+                    "Digest realm=\"b\", nonce=\"n\"".to_string()
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn set_header_replaces_existing_value() {
+        let mut request = HttpRequest::get("http://example.com/".to_string()).unwrap();
+        request.set_header("Authorization", "Basic a".to_string());
+        request.set_header("authorization", "Basic b".to_string());
+
+        assert_eq!(request.headers().len(), 1);
+        assert_eq!(
+            request.header_value("Authorization"),
+            Some("Basic b".to_string())
+        );
     }
 }
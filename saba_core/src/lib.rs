@@ -1,10 +1,17 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
+pub mod auth;
 pub mod browser;
 pub mod error;
 pub mod http;
+pub mod navigation;
+pub mod network_log;
+pub mod referrer;
 pub mod renderer;
+pub mod transport;
 pub mod url;
 pub mod utils;
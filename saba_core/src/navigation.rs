@@ -0,0 +1,686 @@
+use core::cell::RefCell;
+
+use alloc::{format, rc::Rc, string::String, vec::Vec};
+
+use crate::{
+    auth::{select_challenge, CredentialCache, Credentials, ProtectionSpace},
+    browser::Browser,
+    error::Error,
+    http::{HttpRequest, HttpResponse},
+    network_log::{NetworkEntry, NetworkLog},
+    referrer::{referrer_for, ReferrerPolicy},
+    renderer::{
+        html::{parser::HtmlParser, token::HtmlTokenizer},
+        page::Page,
+    },
+    transport::HttpTransport,
+    url::Url,
+};
+
+const MAX_REDIRECTS: usize = 10;
+const MAX_AUTH_ATTEMPTS: usize = 3;
+
+/// Sends `request` through `transport`, following redirects and answering
+/// authentication challenges. `prompt` is called when the server asks for
+/// credentials that are not cached yet; returning `None` cancels and hands
+/// the 401 response back to the caller. Every exchange, including failed
+/// ones, is recorded in `log`. Returns the final URL along with the response.
+pub fn navigate(
+    request: HttpRequest,
+    transport: &mut dyn HttpTransport,
+    credentials: &mut CredentialCache,
+    log: &mut NetworkLog,
+    prompt: &mut dyn FnMut(&ProtectionSpace) -> Option<Credentials>,
+) -> Result<(Url, HttpResponse), Error> {
+    let mut body = Vec::new();
+    let (url, mut response) = navigate_streaming(
+        request,
+        transport,
+        credentials,
+        log,
+        prompt,
+        &mut |_, _, chunk| body.extend_from_slice(chunk),
+    )?;
+    match String::from_utf8(body) {
+        Ok(body) => response.set_body(body),
+        Err(e) => return Err(Error::Network(format!("Invalid received response: {}", e))),
+    }
+    Ok((url, response))
+}
+
+/// Returns true if another request may follow `response`, in which case its
+/// body is not the one navigation ends with.
+fn may_continue(response: &HttpResponse) -> bool {
+    match response.status_code() {
+        301 | 302 | 303 | 307 | 308 => response.header_value("Location").is_ok(),
+        401 => select_challenge(response).is_some(),
+        _ => false,
+    }
+}
+
+/// Navigates like `navigate`, but hands the body of the final response to
+/// `on_body` in chunks as it arrives, along with the final URL and the
+/// response's status and headers, so that it can be parsed before all of it
+/// is received. Whether the returned response holds the body as well is up to
+/// the transport, and the network log only keeps its size. Bodies of
+/// redirects and answered challenges are not handed over.
+pub fn navigate_streaming(
+    mut request: HttpRequest,
+    transport: &mut dyn HttpTransport,
+    credentials: &mut CredentialCache,
+    log: &mut NetworkLog,
+    prompt: &mut dyn FnMut(&ProtectionSpace) -> Option<Credentials>,
+    on_body: &mut dyn FnMut(&Url, &HttpResponse, &[u8]),
+) -> Result<(Url, HttpResponse), Error> {
+    let mut redirects = 0;
+    let mut auth_attempts = 0;
+
+    loop {
+        match credentials.authorization(&request.method(), &request.url()) {
+            Some(value) => request.set_header("Authorization", value),
+            None => request.remove_header("Authorization"),
+        }
+
+        let referer = request
+            .referrer()
+            .and_then(|r| referrer_for(request.referrer_policy(), &r, &request.url()));
+        match referer {
+            Some(value) => request.set_header("Referer", value),
+            None => request.remove_header("Referer"),
+        }
+
+        // the body of a response that may be followed by another request is
+        // held back, in case it turns out to be the final one after all
+        let mut held = Vec::new();
+        let mut body_size = 0;
+        let url = request.url();
+        let mut stream_body = |head: &HttpResponse, chunk: &[u8]| {
+            body_size += chunk.len();
+            if may_continue(head) {
+                held.extend_from_slice(chunk);
+            } else {
+                on_body(&url, head, chunk);
+            }
+        };
+        let response = match transport.send_streaming(&request, &mut stream_body) {
+            Ok(response) => response,
+            Err(e) => {
+                log.record(NetworkEntry::failed(&request, format!("{:?}", e)));
+                return Err(e);
+            }
+        };
+        log.record(NetworkEntry::streamed(&request, &response, body_size));
+
+        match response.status_code() {
+            301 | 302 | 303 | 307 | 308 => {
+                let location = match response.header_value("Location") {
+                    Ok(value) => value,
+                    Err(_) => return Ok((request.url(), response)),
+                };
+
+                if redirects >= MAX_REDIRECTS {
+                    return Err(Error::Network(format!(
+                        "too many redirects while loading {}",
+                        request.url().url()
+                    )));
+                }
+                redirects += 1;
+
+                // A redirect can tighten the policy for the following hops.
+                let policy = ReferrerPolicy::from_header(
+                    &response.header_values("Referrer-Policy").join(","),
+                )
+                .unwrap_or(request.referrer_policy());
+
+                let url = match request.url().resolve(&location) {
+                    Ok(url) => url,
+                    Err(e) => {
+                        return Err(Error::Network(format!(
+                            "invalid redirect location {}: {}",
+                            location, e
+                        )))
+                    }
+                };
+                let referrer = request.referrer();
+                request = match response.status_code() {
+                    // 307 and 308 repeat the request, body included.
+                    307 | 308 => {
+                        let mut next = request.clone();
+                        next.set_url(url);
+                        next
+                    }
+                    _ => HttpRequest::new("GET", url),
+                };
+                request.set_referrer(referrer, policy);
+            }
+            401 => {
+                let challenge = match select_challenge(&response) {
+                    Some(c) => c,
+                    None => return Ok((request.url(), response)),
+                };
+                if auth_attempts >= MAX_AUTH_ATTEMPTS {
+                    on_body(&url, &response, &held);
+                    return Ok((url, response));
+                }
+                auth_attempts += 1;
+
+                let space = ProtectionSpace::new(&url, challenge.realm());
+                let sent_credentials = request.header_value("Authorization").is_some();
+
+                let cached = match credentials.get(&space) {
+                    // A stale nonce or a new path inside a known space can be
+                    // answered without asking the user again.
+                    Some(c) if !sent_credentials || challenge.is_stale() => Some(c),
+                    _ => None,
+                };
+                let entered = match cached {
+                    Some(c) => c,
+                    None => {
+                        credentials.remove(&space);
+                        match prompt(&space) {
+                            Some(c) => c,
+                            None => {
+                                on_body(&url, &response, &held);
+                                return Ok((url, response));
+                            }
+                        }
+                    }
+                };
+
+                credentials.insert(space, entered, challenge, &url);
+            }
+            _ => return Ok((request.url(), response)),
+        }
+    }
+}
+
+/// Navigates the current page of `browser` to `request`, using the
+/// browser's credential cache and network log. The final response is parsed
+/// as it arrives, and the page shows the document from its first chunk on.
+/// `on_progress` is called with the page after every chunk so that it can be
+/// redrawn; the browser is still borrowed for the navigation at that point.
+pub fn load(
+    browser: &Rc<RefCell<Browser>>,
+    request: HttpRequest,
+    transport: &mut dyn HttpTransport,
+    prompt: &mut dyn FnMut(&ProtectionSpace) -> Option<Credentials>,
+    on_progress: &mut dyn FnMut(&Page),
+) -> Result<(), Error> {
+    let page = browser.borrow().current_page();
+    let mut parser = HtmlParser::new(HtmlTokenizer::streaming());
+    let mut shown = false;
+    // the browser must not stay borrowed while the page handles the
+    // response, since the page looks at the browser too
+    let (url, response) = {
+        let mut browser = browser.borrow_mut();
+        let (credentials, log) = browser.session_mut();
+        navigate_streaming(
+            request,
+            transport,
+            credentials,
+            log,
+            prompt,
+            &mut |url, head, chunk| {
+                parser.feed_bytes(chunk);
+                if !shown {
+                    page.borrow_mut()
+                        .receive_document(url.clone(), head, parser.window());
+                    shown = true;
+                }
+                on_progress(&page.borrow());
+            },
+        )?
+    };
+
+    page.borrow_mut()
+        .receive_document(url, &response, parser.finish());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::dom::node::{Node, NodeKind};
+    use alloc::{
+        string::{String, ToString},
+        vec::Vec,
+    };
+
+    fn response(raw: &str) -> Result<HttpResponse, Error> {
+        HttpResponse::new(raw.to_string())
+    }
+
+    fn protected(request: &HttpRequest) -> Result<HttpResponse, Error> {
+        match request.url().path().as_str() {
+            "old" => response("HTTP/1.1 302 Found\nLocation: /private/\n\n"),
+            _ => match request.header_value("Authorization").as_deref() {
+                // "user:pass"
+                Some("Basic dXNlcjpwYXNz") => response("HTTP/1.1 200 OK\n\nsecret"),
+                _ => response(
+                    "HTTP/1.1 401 Unauthorized\nWWW-Authenticate: Basic realm=\"intranet\"\n\ndenied",
+                ),
+            },
+        }
+    }
+
+    #[test]
+    fn follow_redirect_and_authenticate() {
+        let mut cache = CredentialCache::new();
+        let mut realms = Vec::new();
+        let request = HttpRequest::get("http://example.com/old".to_string()).unwrap();
+
+        let (url, result) = navigate(
+            request,
+            &mut protected,
+            &mut cache,
+            &mut NetworkLog::new(),
+            &mut |space| {
+                realms.push(space.realm());
+                Some(Credentials::new("user".to_string(), "pass".to_string()))
+            },
+        )
+        .unwrap();
+
+        assert_eq!(url.url(), "http://example.com/private/".to_string());
+        assert_eq!(result.status_code(), 200);
+        assert_eq!(result.body(), "secret".to_string());
+        assert_eq!(realms, Vec::from(["intranet".to_string()]));
+    }
+
+    #[test]
+    fn cached_credentials_are_reused_without_prompt() {
+        let mut cache = CredentialCache::new();
+        let request = HttpRequest::get("http://example.com/private/a".to_string()).unwrap();
+        navigate(
+            request,
+            &mut protected,
+            &mut cache,
+            &mut NetworkLog::new(),
+            &mut |_| Some(Credentials::new("user".to_string(), "pass".to_string())),
+        )
+        .unwrap();
+
+        let request = HttpRequest::get("http://example.com/private/b".to_string()).unwrap();
+        let (_, result) = navigate(
+            request,
+            &mut protected,
+            &mut cache,
+            &mut NetworkLog::new(),
+            &mut |_| panic!("credentials should be cached"),
+        )
+        .unwrap();
+
+        assert_eq!(result.status_code(), 200);
+    }
+
+    fn echo_form(request: &HttpRequest) -> Result<HttpResponse, Error> {
+        match request.method().as_str() {
+            "POST" => response("HTTP/1.1 303 See Other\nLocation: /done\n\n"),
+            _ => HttpResponse::new(alloc::format!(
+                "HTTP/1.1 200 OK\n\n{}{}",
+                request.url().request_target(),
+                request.body()
+            )),
+        }
+    }
+
+    #[test]
+    fn post_redirect_get() {
+        let mut cache = CredentialCache::new();
+        let mut request = HttpRequest::new(
+            "POST",
+            Url::new("http://example.com/form".to_string())
+                .parse()
+                .unwrap(),
+        );
+        request.set_body("q=1".to_string());
+
+        let (url, result) = navigate(
+            request,
+            &mut echo_form,
+            &mut cache,
+            &mut NetworkLog::new(),
+            &mut |_| None,
+        )
+        .unwrap();
+
+        assert_eq!(url.url(), "http://example.com/done".to_string());
+        assert_eq!(result.body(), "/done".to_string());
+    }
+
+    fn echo_referer(request: &HttpRequest) -> Result<HttpResponse, Error> {
+        match request.url().path().as_str() {
+            "moved" => response(
+                "HTTP/1.1 301 Moved Permanently\nLocation: http://other.local/new\nReferrer-Policy: no-referrer\n\n",
+            ),
+            _ => HttpResponse::new(alloc::format!(
+                "HTTP/1.1 200 OK\n\n{}",
+                request.header_value("Referer").unwrap_or_default()
+            )),
+        }
+    }
+
+    #[test]
+    fn referer_follows_policy_across_redirects() {
+        let mut cache = CredentialCache::new();
+        let page = Url::new("http://intranet.local/app/page".to_string())
+            .parse()
+            .unwrap();
+
+        let mut request = HttpRequest::get("http://other.local/target".to_string()).unwrap();
+        request.set_referrer(
+            Some(page.clone()),
+            ReferrerPolicy::StrictOriginWhenCrossOrigin,
+        );
+        let (_, result) = navigate(
+            request,
+            &mut echo_referer,
+            &mut cache,
+            &mut NetworkLog::new(),
+            &mut |_| None,
+        )
+        .unwrap();
+        assert_eq!(result.body(), "http://intranet.local/".to_string());
+
+        let mut request = HttpRequest::get("http://intranet.local/moved".to_string()).unwrap();
+        request.set_referrer(Some(page), ReferrerPolicy::UnsafeUrl);
+        let (_, result) = navigate(
+            request,
+            &mut echo_referer,
+            &mut cache,
+            &mut NetworkLog::new(),
+            &mut |_| None,
+        )
+        .unwrap();
+        assert_eq!(result.body(), "".to_string());
+    }
+
+    #[test]
+    fn every_hop_is_logged() {
+        let mut log = NetworkLog::new();
+        let request = HttpRequest::get("http://example.com/old".to_string()).unwrap();
+
+        navigate(
+            request,
+            &mut protected,
+            &mut CredentialCache::new(),
+            &mut log,
+            &mut |_| Some(Credentials::new("user".to_string(), "pass".to_string())),
+        )
+        .unwrap();
+
+        let hops: Vec<(String, u32)> = log
+            .entries()
+            .iter()
+            .map(|e| (e.url(), e.status()))
+            .collect();
+        assert_eq!(
+            hops,
+            Vec::from([
+                ("http://example.com/old".to_string(), 302),
+                ("http://example.com/private/".to_string(), 401),
+                ("http://example.com/private/".to_string(), 200),
+            ])
+        );
+    }
+
+    #[test]
+    fn cancelled_prompt_returns_401_response() {
+        let mut cache = CredentialCache::new();
+        let request = HttpRequest::get("http://example.com/private/".to_string()).unwrap();
+
+        let (_, result) = navigate(
+            request,
+            &mut protected,
+            &mut cache,
+            &mut NetworkLog::new(),
+            &mut |_| None,
+        )
+        .unwrap();
+
+        assert_eq!(result.status_code(), 401);
+        assert_eq!(result.body(), String::from("denied"));
+    }
+
+    const FORMS: &str = "HTTP/1.1 200 OK\n\n<html><head></head><body>\
+        <form action=search><input name=q></form>\
+        <form action=login method=post><input name=user><input type=password name=pass>\
+        <input type=checkbox name=keep><button name=go value=1>Log in</button></form>\
+        </body></html>";
+
+    fn type_into(field: &Rc<RefCell<Node>>, text: &str) {
+        if let NodeKind::Element(ref mut element) = field.borrow_mut().kind {
+            element.set_value(text.to_string());
+        }
+    }
+
+    #[test]
+    fn submit_forms_of_loaded_page() {
+        let browser = Browser::new();
+        let mut sent = Vec::new();
+        let mut site = |request: &HttpRequest| {
+            sent.push(alloc::format!(
+                "{} {} {}",
+                request.method(),
+                request.url().request_target(),
+                request.body()
+            ));
+            match request.url().path().as_str() {
+                "login" => response("HTTP/1.1 303 See Other\nLocation: /home\n\n"),
+                "search" | "home" => response("HTTP/1.1 200 OK\n\ndone"),
+                _ => response(FORMS),
+            }
+        };
+        let home = HttpRequest::get("http://example.com/".to_string()).unwrap();
+        let page = browser.borrow().current_page();
+
+        // Enter in the only text field submits the search form
+        load(
+            &browser,
+            home.clone(),
+            &mut site,
+            &mut |_| None,
+            &mut |_| {},
+        )
+        .unwrap();
+        let fields = page.borrow().interactive_elements();
+        assert_eq!(fields.len(), 5);
+        type_into(&fields[0], "saba browser");
+        let request = page.borrow().submit_implicitly(&fields[0]).unwrap();
+        load(
+            &browser,
+            request.unwrap(),
+            &mut site,
+            &mut |_| None,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(
+            page.borrow().url().unwrap().url(),
+            "http://example.com/search?q=saba+browser".to_string()
+        );
+
+        // Enter in a login field clicks its button
+        load(&browser, home, &mut site, &mut |_| None, &mut |_| {}).unwrap();
+        let fields = page.borrow().interactive_elements();
+        type_into(&fields[1], "user");
+        type_into(&fields[2], "pass");
+        assert_eq!(page.borrow().activate(&fields[3]).unwrap(), None);
+        let request = page.borrow().submit_implicitly(&fields[1]).unwrap();
+        load(
+            &browser,
+            request.unwrap(),
+            &mut site,
+            &mut |_| None,
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(
+            page.borrow().url().unwrap().url(),
+            "http://example.com/home".to_string()
+        );
+
+        // clicking the button submits the same way
+        load(
+            &browser,
+            HttpRequest::get("http://example.com/".to_string()).unwrap(),
+            &mut site,
+            &mut |_| None,
+            &mut |_| {},
+        )
+        .unwrap();
+        let fields = page.borrow().interactive_elements();
+        let request = page.borrow().activate(&fields[4]).unwrap();
+        assert_eq!(request.unwrap().body(), "user=&pass=&go=1".to_string());
+
+        assert_eq!(
+            sent,
+            Vec::from([
+                "GET / ".to_string(),
+                "GET /search?q=saba+browser ".to_string(),
+                "GET / ".to_string(),
+                "POST /login user=user&pass=pass&keep=on&go=1".to_string(),
+                "GET /home ".to_string(),
+                "GET / ".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn follow_links_of_loaded_page() {
+        let browser = Browser::new();
+        let mut sent = Vec::new();
+        let mut site = |request: &HttpRequest| {
+            sent.push(alloc::format!(
+                "{} {}",
+                request.url().request_target(),
+                request.header_value("Referer").unwrap_or_default()
+            ));
+            response(
+                "HTTP/1.1 200 OK\nReferrer-Policy: origin\n\n<html><head></head><body>\
+                <p>text</p><a href=next>next</a><a href=/ rel=noreferrer>top</a></body></html>",
+            )
+        };
+        let page = browser.borrow().current_page();
+
+        let request = HttpRequest::get("http://example.com/docs/".to_string()).unwrap();
+        load(&browser, request, &mut site, &mut |_| None, &mut |_| {}).unwrap();
+        for i in [0, 1] {
+            let links = page.borrow().interactive_elements();
+            assert_eq!(links.len(), 2);
+            let request = page.borrow().activate(&links[i]).unwrap();
+            load(
+                &browser,
+                request.unwrap(),
+                &mut site,
+                &mut |_| None,
+                &mut |_| {},
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            page.borrow().url().unwrap().url(),
+            "http://example.com/".to_string()
+        );
+        assert_eq!(
+            sent,
+            Vec::from([
+                "/docs/ ".to_string(),
+                "/docs/next http://example.com/".to_string(),
+                "/ ".to_string(),
+            ])
+        );
+    }
+
+    /// Redirects to `/page`, and sends every body a few bytes at a time
+    /// without keeping it, like a socket.
+    struct Trickle {
+        chunks: usize,
+    }
+
+    impl HttpTransport for Trickle {
+        fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, Error> {
+            self.send_streaming(request, &mut |_, _| {})
+        }
+
+        fn send_streaming(
+            &mut self,
+            request: &HttpRequest,
+            on_body: &mut dyn FnMut(&HttpResponse, &[u8]),
+        ) -> Result<HttpResponse, Error> {
+            let raw = match request.url().path().as_str() {
+                "page" => "HTTP/1.1 200 OK\n\n<p>caf\u{e9} <b>bold</b></p>",
+                _ => "HTTP/1.1 302 Found\nLocation: /page\n\n<p>moved</p>",
+            };
+            let (head, body) = raw.split_once("\n\n").unwrap();
+            let head = response(&alloc::format!("{}\n\n", head))?;
+            // the second chunk of the page ends in the middle of "é"
+            for chunk in body.as_bytes().chunks(4) {
+                self.chunks += 1;
+                on_body(&head, chunk);
+            }
+            Ok(head)
+        }
+    }
+
+    #[test]
+    fn load_parses_final_body_as_it_arrives() {
+        let browser = Browser::new();
+        let mut transport = Trickle { chunks: 0 };
+        let request = HttpRequest::get("http://example.com/".to_string()).unwrap();
+        let mut shown = Vec::new();
+
+        load(
+            &browser,
+            request,
+            &mut transport,
+            &mut |_| None,
+            &mut |page| shown.push(page.html().unwrap()),
+        )
+        .unwrap();
+
+        let page = browser.borrow().current_page();
+        assert_eq!(
+            page.borrow().html().unwrap(),
+            "<html><head></head><body><p>caf\u{e9} <b>bold</b></p></body></html>".to_string()
+        );
+        assert_eq!(transport.chunks, 3 + 6);
+        // the page showed the document while the rest of it was on its way
+        assert_eq!(shown.len(), 6);
+        assert_eq!(
+            shown[2],
+            "<html><head></head><body><p>caf\u{e9} <b></b></p></body></html>".to_string()
+        );
+        let sizes: Vec<usize> = browser
+            .borrow()
+            .network_log()
+            .entries()
+            .iter()
+            .map(|e| e.response_body_size())
+            .collect();
+        assert_eq!(sizes, Vec::from([12, 24]));
+    }
+
+    #[test]
+    fn wrong_credentials_are_asked_again_up_to_limit() {
+        let mut cache = CredentialCache::new();
+        let mut prompts = 0;
+        let request = HttpRequest::get("http://example.com/private/".to_string()).unwrap();
+
+        let (_, result) = navigate(
+            request,
+            &mut protected,
+            &mut cache,
+            &mut NetworkLog::new(),
+            &mut |_| {
+                prompts += 1;
+                Some(Credentials::new("user".to_string(), "wrong".to_string()))
+            },
+        )
+        .unwrap();
+
+        assert_eq!(result.status_code(), 401);
+        assert_eq!(prompts, MAX_AUTH_ATTEMPTS);
+    }
+}
@@ -0,0 +1,449 @@
+//! A record of every HTTP exchange made while browsing, exportable as a
+//! HAR 1.2 document.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::http::{Header, HttpRequest, HttpResponse};
+
+/// When an exchange started and how long each phase took, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Timings {
+    /// Milliseconds since the Unix epoch.
+    started_at: u64,
+    dns: u64,
+    connect: u64,
+    send: u64,
+    wait: u64,
+    receive: u64,
+}
+
+impl Timings {
+    pub fn new(
+        started_at: u64,
+        dns: u64,
+        connect: u64,
+        send: u64,
+        wait: u64,
+        receive: u64,
+    ) -> Self {
+        Self {
+            started_at,
+            dns,
+            connect,
+            send,
+            wait,
+            receive,
+        }
+    }
+
+    pub fn started_at(&self) -> u64 {
+        self.started_at
+    }
+
+    pub fn dns(&self) -> u64 {
+        self.dns
+    }
+
+    pub fn connect(&self) -> u64 {
+        self.connect
+    }
+
+    pub fn send(&self) -> u64 {
+        self.send
+    }
+
+    pub fn wait(&self) -> u64 {
+        self.wait
+    }
+
+    pub fn receive(&self) -> u64 {
+        self.receive
+    }
+
+    pub fn total(&self) -> u64 {
+        self.dns + self.connect + self.send + self.wait + self.receive
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkEntry {
+    method: String,
+    url: String,
+    /// The request line and headers as they are written on the wire.
+    request_line: String,
+    request_headers: Vec<Header>,
+    request_body: String,
+    /// 0 when the request failed before a response arrived.
+    status: u32,
+    reason: String,
+    http_version: String,
+    response_headers: Vec<Header>,
+    /// None when the body was handed on as it arrived instead of being kept.
+    response_body: Option<String>,
+    response_body_size: usize,
+    error: Option<String>,
+    timings: Timings,
+}
+
+fn headers_size(start_line: &str, headers: &[Header]) -> usize {
+    // the start line, each header line and the empty line, all ending in CRLF
+    start_line.len()
+        + 2
+        + headers
+            .iter()
+            .map(|h| h.name().len() + 2 + h.value().len() + 2)
+            .sum::<usize>()
+        + 2
+}
+
+impl NetworkEntry {
+    pub fn new(request: &HttpRequest, response: &HttpResponse) -> Self {
+        Self {
+            method: request.method(),
+            url: request.url().url(),
+            request_line: request.request_line(),
+            request_headers: request.wire_headers(),
+            request_body: request.body(),
+            status: response.status_code(),
+            reason: response.reason(),
+            http_version: response.version(),
+            response_headers: response.headers(),
+            response_body: Some(response.body()),
+            response_body_size: response.body().len(),
+            error: None,
+            timings: response.timings(),
+        }
+    }
+
+    /// Records an exchange whose response body of `body_size` bytes was handed
+    /// on as it arrived. Only the size of the body is kept.
+    pub fn streamed(request: &HttpRequest, response: &HttpResponse, body_size: usize) -> Self {
+        let mut entry = Self::new(request, response);
+        entry.response_body = None;
+        entry.response_body_size = body_size;
+        entry
+    }
+
+    /// Records a request that failed without a response.
+    pub fn failed(request: &HttpRequest, error: String) -> Self {
+        Self {
+            method: request.method(),
+            url: request.url().url(),
+            request_line: request.request_line(),
+            request_headers: request.wire_headers(),
+            request_body: request.body(),
+            status: 0,
+            reason: String::new(),
+            http_version: "HTTP/1.1".to_string(),
+            response_headers: Vec::new(),
+            response_body: None,
+            response_body_size: 0,
+            error: Some(error),
+            timings: Timings::default(),
+        }
+    }
+
+    pub fn method(&self) -> String {
+        self.method.clone()
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    pub fn request_headers_size(&self) -> usize {
+        headers_size(&self.request_line, &self.request_headers)
+    }
+
+    pub fn request_body_size(&self) -> usize {
+        self.request_body.len()
+    }
+
+    pub fn response_headers_size(&self) -> usize {
+        if self.status == 0 {
+            return 0;
+        }
+        let status_line = format!("{} {} {}", self.http_version, self.status, self.reason);
+        headers_size(&status_line, &self.response_headers)
+    }
+
+    pub fn response_body_size(&self) -> usize {
+        self.response_body_size
+    }
+
+    fn header(headers: &[Header], name: &str) -> Option<String> {
+        headers
+            .iter()
+            .find(|h| h.name().eq_ignore_ascii_case(name))
+            .map(|h| h.value())
+    }
+
+    fn to_har(&self) -> String {
+        let mut request = format!(
+            "{{\"method\":{},\"url\":{},\"httpVersion\":\"HTTP/1.1\",\"cookies\":[],\"headers\":{},\"queryString\":{},",
+            json_string(&self.method),
+            json_string(&self.url),
+            har_headers(&self.request_headers),
+            har_query_string(&self.url),
+        );
+        if !self.request_body.is_empty() {
+            let mime_type = Self::header(&self.request_headers, "Content-Type").unwrap_or_default();
+            request.push_str(&format!(
+                "\"postData\":{{\"mimeType\":{},\"text\":{}}},",
+                json_string(&mime_type),
+                json_string(&self.request_body)
+            ));
+        }
+        request.push_str(&format!(
+            "\"headersSize\":{},\"bodySize\":{}}}",
+            self.request_headers_size(),
+            self.request_body_size()
+        ));
+
+        let mime_type = Self::header(&self.response_headers, "Content-Type").unwrap_or_default();
+        let redirect_url = Self::header(&self.response_headers, "Location").unwrap_or_default();
+        let mut content = format!(
+            "{{\"size\":{},\"mimeType\":{}",
+            self.response_body_size(),
+            json_string(&mime_type)
+        );
+        if let Some(ref text) = self.response_body {
+            content.push_str(&format!(",\"text\":{}", json_string(text)));
+        }
+        content.push('}');
+        let response = format!(
+            "{{\"status\":{},\"statusText\":{},\"httpVersion\":{},\"cookies\":[],\"headers\":{},\"content\":{},\"redirectURL\":{},\"headersSize\":{},\"bodySize\":{}}}",
+            self.status,
+            json_string(&self.reason),
+            json_string(&self.http_version),
+            har_headers(&self.response_headers),
+            content,
+            json_string(&redirect_url),
+            if self.status == 0 { -1 } else { self.response_headers_size() as i64 },
+            if self.status == 0 { -1 } else { self.response_body_size() as i64 },
+        );
+
+        let timings = format!(
+            "{{\"blocked\":-1,\"dns\":{},\"connect\":{},\"send\":{},\"wait\":{},\"receive\":{},\"ssl\":-1}}",
+            self.timings.dns,
+            self.timings.connect,
+            self.timings.send,
+            self.timings.wait,
+            self.timings.receive
+        );
+
+        let mut entry = format!(
+            "{{\"startedDateTime\":{},\"time\":{},\"request\":{},\"response\":{},\"cache\":{{}},\"timings\":{}",
+            json_string(&format_iso8601(self.timings.started_at)),
+            self.timings.total(),
+            request,
+            response,
+            timings
+        );
+        if let Some(ref error) = self.error {
+            entry.push_str(&format!(",\"comment\":{}", json_string(error)));
+        }
+        entry.push('}');
+        entry
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NetworkLog {
+    entries: Vec<NetworkEntry>,
+}
+
+impl NetworkLog {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, entry: NetworkEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> Vec<NetworkEntry> {
+        self.entries.clone()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Serializes the log as a HAR 1.2 JSON document.
+    pub fn to_har(&self) -> String {
+        let entries: Vec<String> = self.entries.iter().map(|e| e.to_har()).collect();
+        format!(
+            "{{\"log\":{{\"version\":\"1.2\",\"creator\":{{\"name\":\"saba\",\"version\":{}}},\"pages\":[],\"entries\":[{}]}}}}",
+            json_string(env!("CARGO_PKG_VERSION")),
+            entries.join(",")
+        )
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn har_headers(headers: &[Header]) -> String {
+    let headers: Vec<String> = headers
+        .iter()
+        .map(|h| {
+            format!(
+                "{{\"name\":{},\"value\":{}}}",
+                json_string(&h.name()),
+                json_string(&h.value())
+            )
+        })
+        .collect();
+    format!("[{}]", headers.join(","))
+}
+
+fn har_query_string(url: &str) -> String {
+    let query = match url.split_once('?') {
+        Some((_, q)) => q.split('#').next().unwrap_or(""),
+        None => "",
+    };
+    let params: Vec<String> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (name, value) = p.split_once('=').unwrap_or((p, ""));
+            format!(
+                "{{\"name\":{},\"value\":{}}}",
+                json_string(name),
+                json_string(value)
+            )
+        })
+        .collect();
+    format!("[{}]", params.join(","))
+}
+
+/// Formats milliseconds since the Unix epoch as an ISO 8601 date in UTC.
+fn format_iso8601(ms: u64) -> String {
+    let days = (ms / 86_400_000) as i64;
+    let ms_of_day = ms % 86_400_000;
+
+    // Converts days since the epoch to a civil date (Howard Hinnant's
+    // `civil_from_days`).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        ms_of_day / 3_600_000,
+        ms_of_day / 60_000 % 60,
+        ms_of_day / 1000 % 60,
+        ms_of_day % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_dates() {
+        assert_eq!(format_iso8601(0), "1970-01-01T00:00:00.000Z".to_string());
+        assert_eq!(
+            format_iso8601(1_709_251_200_123),
+            "2024-03-01T00:00:00.123Z".to_string()
+        );
+    }
+
+    #[test]
+    fn entry_sizes_and_timings() {
+        let mut request = HttpRequest::get("http://example.com/a?q=1".to_string()).unwrap();
+        request.set_header("Accept", "text/html".to_string());
+        let mut response =
+            HttpResponse::new("HTTP/1.1 200 OK\nContent-Type: text/html\n\nhello".to_string())
+                .unwrap();
+        response.set_timings(Timings::new(0, 1, 2, 3, 4, 5));
+
+        let entry = NetworkEntry::new(&request, &response);
+
+        assert_eq!(entry.timings().total(), 15);
+        // "GET /a?q=1 HTTP/1.1\r\n" + "Host: example.com\r\n" +
+        // "Accept: text/html\r\n" + "Connection: close\r\n" + "\r\n"
+        assert_eq!(entry.request_headers_size(), 21 + 19 + 19 + 19 + 2);
+        assert_eq!(entry.response_body_size(), 5);
+        // "HTTP/1.1 200 OK\r\n" + "Content-Type: text/html\r\n" + "\r\n"
+        assert_eq!(entry.response_headers_size(), 17 + 25 + 2);
+    }
+
+    #[test]
+    fn export_har() {
+        let mut request =
+            HttpRequest::get("http://example.com/search?q=a%20b".to_string()).unwrap();
+        request.set_header("Accept", "text/html".to_string());
+        let mut response = HttpResponse::new(
+            "HTTP/1.1 200 OK\nContent-Type: text/html\n\n<p>\"hi\"</p>".to_string(),
+        )
+        .unwrap();
+        response.set_timings(Timings::new(1_000, 1, 2, 3, 4, 5));
+        let mut log = NetworkLog::new();
+        log.record(NetworkEntry::new(&request, &response));
+        log.record(NetworkEntry::streamed(&request, &response, 42));
+        log.record(NetworkEntry::failed(
+            &HttpRequest::get("http://down.local/".to_string()).unwrap(),
+            "connection refused".to_string(),
+        ));
+
+        let har = log.to_har();
+
+        assert!(har.starts_with("{\"log\":{\"version\":\"1.2\",\"creator\":{\"name\":\"saba\""));
+        assert!(har.contains("\"startedDateTime\":\"1970-01-01T00:00:01.000Z\",\"time\":15"));
+        assert!(har.contains("\"startedDateTime\":\"1970-01-01T00:00:00.000Z\",\"time\":0"));
+        assert!(har.contains("\"headers\":[{\"name\":\"Host\",\"value\":\"example.com\"},{\"name\":\"Accept\",\"value\":\"text/html\"},{\"name\":\"Connection\",\"value\":\"close\"}]"));
+        assert!(har.contains("\"queryString\":[{\"name\":\"q\",\"value\":\"a%20b\"}]"));
+        assert!(har.contains("\"text\":\"<p>\\\"hi\\\"</p>\""));
+        assert!(har.contains("\"content\":{\"size\":42,\"mimeType\":\"text/html\"}"));
+        assert!(har.contains(
+            "\"timings\":{\"blocked\":-1,\"dns\":1,\"connect\":2,\"send\":3,\"wait\":4,\"receive\":5,\"ssl\":-1}"
+        ));
+        assert!(har.contains("\"status\":0"));
+        assert!(har.contains("\"comment\":\"connection refused\""));
+        assert!(har.ends_with("]}}"));
+    }
+}
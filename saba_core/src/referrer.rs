@@ -0,0 +1,220 @@
+//! Computing the `Referer` header as defined in the Referrer Policy
+//! specification.
+
+use alloc::{format, string::String};
+
+use crate::url::Url;
+
+/// Referrers longer than this are reduced to their origin.
+const MAX_REFERRER_LENGTH: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    SameOrigin,
+    Origin,
+    StrictOrigin,
+    OriginWhenCrossOrigin,
+    #[default]
+    StrictOriginWhenCrossOrigin,
+    UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+    /// Parses a single policy token such as `no-referrer`.
+    pub fn from_token(token: &str) -> Option<Self> {
+        let token = token.trim().to_ascii_lowercase();
+        match token.as_str() {
+            "no-referrer" => Some(ReferrerPolicy::NoReferrer),
+            "no-referrer-when-downgrade" => Some(ReferrerPolicy::NoReferrerWhenDowngrade),
+            "same-origin" => Some(ReferrerPolicy::SameOrigin),
+            "origin" => Some(ReferrerPolicy::Origin),
+            "strict-origin" => Some(ReferrerPolicy::StrictOrigin),
+            "origin-when-cross-origin" => Some(ReferrerPolicy::OriginWhenCrossOrigin),
+            "strict-origin-when-cross-origin" => Some(ReferrerPolicy::StrictOriginWhenCrossOrigin),
+            "unsafe-url" => Some(ReferrerPolicy::UnsafeUrl),
+            _ => None,
+        }
+    }
+
+    /// Parses a `Referrer-Policy` header. The header is a comma-separated list
+    /// and the last recognized token wins, so that new values can be deployed
+    /// with a fallback for older browsers. Repeated headers are read as one
+    /// list joined with commas.
+    pub fn from_header(value: &str) -> Option<Self> {
+        value.split(',').filter_map(Self::from_token).next_back()
+    }
+
+    /// Parses the `content` of `<meta name="referrer">`, which also accepts
+    /// a few legacy keywords.
+    pub fn from_meta_content(content: &str) -> Option<Self> {
+        let content = content.trim().to_ascii_lowercase();
+        match content.as_str() {
+            "never" => Some(ReferrerPolicy::NoReferrer),
+            "default" => Some(ReferrerPolicy::StrictOriginWhenCrossOrigin),
+            "always" => Some(ReferrerPolicy::UnsafeUrl),
+            "origin-when-crossorigin" => Some(ReferrerPolicy::OriginWhenCrossOrigin),
+            _ => Self::from_token(&content),
+        }
+    }
+}
+
+fn is_potentially_trustworthy(url: &Url) -> bool {
+    url.url().starts_with("https://")
+}
+
+/// Returns the URL without its fragment, as it is sent in `Referer`.
+fn strip_for_referrer(url: &Url) -> String {
+    let target = url.request_target();
+    let target = match target.split_once('#') {
+        Some((t, _)) => t,
+        None => &target,
+    };
+    format!("{}{}", url.origin(), target)
+}
+
+/// Returns the value of the `Referer` header for a request from a document
+/// at `referrer` to `target`, or `None` if no referrer should be sent.
+pub fn referrer_for(policy: ReferrerPolicy, referrer: &Url, target: &Url) -> Option<String> {
+    let origin_only = format!("{}/", referrer.origin());
+    let mut full = strip_for_referrer(referrer);
+    if full.len() > MAX_REFERRER_LENGTH {
+        full = origin_only.clone();
+    }
+
+    let same_origin = referrer.origin() == target.origin();
+    let downgrade = is_potentially_trustworthy(referrer) && !is_potentially_trustworthy(target);
+
+    match policy {
+        ReferrerPolicy::NoReferrer => None,
+        ReferrerPolicy::Origin => Some(origin_only),
+        ReferrerPolicy::UnsafeUrl => Some(full),
+        ReferrerPolicy::StrictOrigin => {
+            if downgrade {
+                None
+            } else {
+                Some(origin_only)
+            }
+        }
+        ReferrerPolicy::StrictOriginWhenCrossOrigin => {
+            if same_origin {
+                Some(full)
+            } else if downgrade {
+                None
+            } else {
+                Some(origin_only)
+            }
+        }
+        ReferrerPolicy::SameOrigin => {
+            if same_origin {
+                Some(full)
+            } else {
+                None
+            }
+        }
+        ReferrerPolicy::OriginWhenCrossOrigin => {
+            if same_origin {
+                Some(full)
+            } else {
+                Some(origin_only)
+            }
+        }
+        ReferrerPolicy::NoReferrerWhenDowngrade => {
+            if downgrade {
+                None
+            } else {
+                Some(full)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn url(s: &str) -> Url {
+        Url::new(s.to_string()).parse().unwrap()
+    }
+
+    #[test]
+    fn header_takes_last_recognized_token() {
+        assert_eq!(
+            ReferrerPolicy::from_header("no-referrer, unknown-policy, same-origin, bogus"),
+            Some(ReferrerPolicy::SameOrigin)
+        );
+        assert_eq!(ReferrerPolicy::from_header("bogus"), None);
+    }
+
+    #[test]
+    fn meta_accepts_legacy_keywords() {
+        assert_eq!(
+            ReferrerPolicy::from_meta_content("never"),
+            Some(ReferrerPolicy::NoReferrer)
+        );
+        assert_eq!(
+            ReferrerPolicy::from_meta_content("Origin"),
+            Some(ReferrerPolicy::Origin)
+        );
+    }
+
+    #[test]
+    fn policies_for_same_and_cross_origin() {
+        let from = url("http://intranet.local/app/page?id=3");
+        let same = url("http://intranet.local/api");
+        let cross = url("http://other.local/");
+        let full = Some("http://intranet.local/app/page?id=3".to_string());
+        let origin = Some("http://intranet.local/".to_string());
+
+        let cases = [
+            (ReferrerPolicy::NoReferrer, None, None),
+            (
+                ReferrerPolicy::NoReferrerWhenDowngrade,
+                full.clone(),
+                full.clone(),
+            ),
+            (ReferrerPolicy::SameOrigin, full.clone(), None),
+            (ReferrerPolicy::Origin, origin.clone(), origin.clone()),
+            (ReferrerPolicy::StrictOrigin, origin.clone(), origin.clone()),
+            (
+                ReferrerPolicy::OriginWhenCrossOrigin,
+                full.clone(),
+                origin.clone(),
+            ),
+            (
+                ReferrerPolicy::StrictOriginWhenCrossOrigin,
+                full.clone(),
+                origin.clone(),
+            ),
+            (ReferrerPolicy::UnsafeUrl, full.clone(), full.clone()),
+        ];
+
+        for (policy, same_expected, cross_expected) in cases {
+            assert_eq!(
+                referrer_for(policy, &from, &same),
+                same_expected,
+                "{:?}",
+                policy
+            );
+            assert_eq!(
+                referrer_for(policy, &from, &cross),
+                cross_expected,
+                "{:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn non_default_port_is_part_of_origin() {
+        let from = url("http://intranet.local:8080/a");
+        let to = url("http://intranet.local/b");
+
+        assert_eq!(
+            referrer_for(ReferrerPolicy::StrictOriginWhenCrossOrigin, &from, &to),
+            Some("http://intranet.local:8080/".to_string())
+        );
+    }
+}
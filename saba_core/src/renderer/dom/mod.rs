@@ -1 +1,3 @@
 pub mod node;
+pub mod query;
+pub mod traversal;
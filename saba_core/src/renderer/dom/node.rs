@@ -1,24 +1,161 @@
 use core::{cell::RefCell, str::FromStr};
 
 use alloc::{
+    collections::BTreeMap,
     format,
     rc::{Rc, Weak},
-    string::String,
+    string::{String, ToString},
     vec::Vec,
 };
 
+use crate::error::Error;
 use crate::renderer::html::attribute::Attribute;
 
+use super::traversal::{ancestors, children, inclusive_descendants};
+
+/// The elements of the HTML namespace. `Unknown` is any other element,
+/// including custom elements and elements of other namespaces; its name is the
+/// element's local name.
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum ElementKind {
-    Html,
-    Head,
-    Style,
-    Body,
-    P,
     A,
+    Abbr,
+    Acronym,
+    Address,
+    Applet,
+    Area,
+    Article,
+    Aside,
+    Audio,
+    B,
+    Base,
+    Basefont,
+    Bdi,
+    Bdo,
+    Bgsound,
+    Big,
+    Blink,
+    Blockquote,
+    Body,
+    Br,
+    Button,
+    Canvas,
+    Caption,
+    Center,
+    Cite,
+    Code,
+    Col,
+    Colgroup,
+    Data,
+    Datalist,
+    Dd,
+    Del,
+    Details,
+    Dfn,
+    Dialog,
+    Dir,
+    Div,
+    Dl,
+    Dt,
+    Em,
+    Embed,
+    Fieldset,
+    Figcaption,
+    Figure,
+    Font,
+    Footer,
+    Form,
+    Frame,
+    Frameset,
     H1,
     H2,
+    H3,
+    H4,
+    H5,
+    H6,
+    Head,
+    Header,
+    Hgroup,
+    Hr,
+    Html,
+    I,
+    Iframe,
+    Img,
+    Input,
+    Ins,
+    Kbd,
+    Keygen,
+    Label,
+    Legend,
+    Li,
+    Link,
+    Listing,
+    Main,
+    Map,
+    Mark,
+    Marquee,
+    Menu,
+    Menuitem,
+    Meta,
+    Meter,
+    Nav,
+    Nobr,
+    Noembed,
+    Noframes,
+    Noscript,
+    Object,
+    Ol,
+    Optgroup,
+    Option,
+    Output,
+    P,
+    Param,
+    Picture,
+    Plaintext,
+    Pre,
+    Progress,
+    Q,
+    Rb,
+    Rp,
+    Rt,
+    Rtc,
+    Ruby,
+    S,
+    Samp,
+    Script,
+    Search,
+    Section,
+    Select,
+    Slot,
+    Small,
+    Source,
+    Span,
+    Strike,
+    Strong,
+    Style,
+    Sub,
+    Summary,
+    Sup,
+    Table,
+    Tbody,
+    Td,
+    Template,
+    Textarea,
+    Tfoot,
+    Th,
+    Thead,
+    Time,
+    Title,
+    Tr,
+    Track,
+    Tt,
+    U,
+    Ul,
+    Var,
+    Video,
+    Wbr,
+    Xmp,
+    Unknown,
 }
 
 impl FromStr for ElementKind {
@@ -26,51 +163,508 @@ impl FromStr for ElementKind {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "html" => Ok(ElementKind::Html),
-            "head" => Ok(ElementKind::Head),
-            "style" => Ok(ElementKind::Style),
-            "body" => Ok(ElementKind::Body),
-            "p" => Ok(ElementKind::P),
             "a" => Ok(ElementKind::A),
+            "abbr" => Ok(ElementKind::Abbr),
+            "acronym" => Ok(ElementKind::Acronym),
+            "address" => Ok(ElementKind::Address),
+            "applet" => Ok(ElementKind::Applet),
+            "area" => Ok(ElementKind::Area),
+            "article" => Ok(ElementKind::Article),
+            "aside" => Ok(ElementKind::Aside),
+            "audio" => Ok(ElementKind::Audio),
+            "b" => Ok(ElementKind::B),
+            "base" => Ok(ElementKind::Base),
+            "basefont" => Ok(ElementKind::Basefont),
+            "bdi" => Ok(ElementKind::Bdi),
+            "bdo" => Ok(ElementKind::Bdo),
+            "bgsound" => Ok(ElementKind::Bgsound),
+            "big" => Ok(ElementKind::Big),
+            "blink" => Ok(ElementKind::Blink),
+            "blockquote" => Ok(ElementKind::Blockquote),
+            "body" => Ok(ElementKind::Body),
+            "br" => Ok(ElementKind::Br),
+            "button" => Ok(ElementKind::Button),
+            "canvas" => Ok(ElementKind::Canvas),
+            "caption" => Ok(ElementKind::Caption),
+            "center" => Ok(ElementKind::Center),
+            "cite" => Ok(ElementKind::Cite),
+            "code" => Ok(ElementKind::Code),
+            "col" => Ok(ElementKind::Col),
+            "colgroup" => Ok(ElementKind::Colgroup),
+            "data" => Ok(ElementKind::Data),
+            "datalist" => Ok(ElementKind::Datalist),
+            "dd" => Ok(ElementKind::Dd),
+            "del" => Ok(ElementKind::Del),
+            "details" => Ok(ElementKind::Details),
+            "dfn" => Ok(ElementKind::Dfn),
+            "dialog" => Ok(ElementKind::Dialog),
+            "dir" => Ok(ElementKind::Dir),
+            "div" => Ok(ElementKind::Div),
+            "dl" => Ok(ElementKind::Dl),
+            "dt" => Ok(ElementKind::Dt),
+            "em" => Ok(ElementKind::Em),
+            "embed" => Ok(ElementKind::Embed),
+            "fieldset" => Ok(ElementKind::Fieldset),
+            "figcaption" => Ok(ElementKind::Figcaption),
+            "figure" => Ok(ElementKind::Figure),
+            "font" => Ok(ElementKind::Font),
+            "footer" => Ok(ElementKind::Footer),
+            "form" => Ok(ElementKind::Form),
+            "frame" => Ok(ElementKind::Frame),
+            "frameset" => Ok(ElementKind::Frameset),
             "h1" => Ok(ElementKind::H1),
             "h2" => Ok(ElementKind::H2),
-            _ => Err(format!("unimplemented element name {:?}", s)),
+            "h3" => Ok(ElementKind::H3),
+            "h4" => Ok(ElementKind::H4),
+            "h5" => Ok(ElementKind::H5),
+            "h6" => Ok(ElementKind::H6),
+            "head" => Ok(ElementKind::Head),
+            "header" => Ok(ElementKind::Header),
+            "hgroup" => Ok(ElementKind::Hgroup),
+            "hr" => Ok(ElementKind::Hr),
+            "html" => Ok(ElementKind::Html),
+            "i" => Ok(ElementKind::I),
+            "iframe" => Ok(ElementKind::Iframe),
+            "img" => Ok(ElementKind::Img),
+            "input" => Ok(ElementKind::Input),
+            "ins" => Ok(ElementKind::Ins),
+            "kbd" => Ok(ElementKind::Kbd),
+            "keygen" => Ok(ElementKind::Keygen),
+            "label" => Ok(ElementKind::Label),
+            "legend" => Ok(ElementKind::Legend),
+            "li" => Ok(ElementKind::Li),
+            "link" => Ok(ElementKind::Link),
+            "listing" => Ok(ElementKind::Listing),
+            "main" => Ok(ElementKind::Main),
+            "map" => Ok(ElementKind::Map),
+            "mark" => Ok(ElementKind::Mark),
+            "marquee" => Ok(ElementKind::Marquee),
+            "menu" => Ok(ElementKind::Menu),
+            "menuitem" => Ok(ElementKind::Menuitem),
+            "meta" => Ok(ElementKind::Meta),
+            "meter" => Ok(ElementKind::Meter),
+            "nav" => Ok(ElementKind::Nav),
+            "nobr" => Ok(ElementKind::Nobr),
+            "noembed" => Ok(ElementKind::Noembed),
+            "noframes" => Ok(ElementKind::Noframes),
+            "noscript" => Ok(ElementKind::Noscript),
+            "object" => Ok(ElementKind::Object),
+            "ol" => Ok(ElementKind::Ol),
+            "optgroup" => Ok(ElementKind::Optgroup),
+            "option" => Ok(ElementKind::Option),
+            "output" => Ok(ElementKind::Output),
+            "p" => Ok(ElementKind::P),
+            "param" => Ok(ElementKind::Param),
+            "picture" => Ok(ElementKind::Picture),
+            "plaintext" => Ok(ElementKind::Plaintext),
+            "pre" => Ok(ElementKind::Pre),
+            "progress" => Ok(ElementKind::Progress),
+            "q" => Ok(ElementKind::Q),
+            "rb" => Ok(ElementKind::Rb),
+            "rp" => Ok(ElementKind::Rp),
+            "rt" => Ok(ElementKind::Rt),
+            "rtc" => Ok(ElementKind::Rtc),
+            "ruby" => Ok(ElementKind::Ruby),
+            "s" => Ok(ElementKind::S),
+            "samp" => Ok(ElementKind::Samp),
+            "script" => Ok(ElementKind::Script),
+            "search" => Ok(ElementKind::Search),
+            "section" => Ok(ElementKind::Section),
+            "select" => Ok(ElementKind::Select),
+            "slot" => Ok(ElementKind::Slot),
+            "small" => Ok(ElementKind::Small),
+            "source" => Ok(ElementKind::Source),
+            "span" => Ok(ElementKind::Span),
+            "strike" => Ok(ElementKind::Strike),
+            "strong" => Ok(ElementKind::Strong),
+            "style" => Ok(ElementKind::Style),
+            "sub" => Ok(ElementKind::Sub),
+            "summary" => Ok(ElementKind::Summary),
+            "sup" => Ok(ElementKind::Sup),
+            "table" => Ok(ElementKind::Table),
+            "tbody" => Ok(ElementKind::Tbody),
+            "td" => Ok(ElementKind::Td),
+            "template" => Ok(ElementKind::Template),
+            "textarea" => Ok(ElementKind::Textarea),
+            "tfoot" => Ok(ElementKind::Tfoot),
+            "th" => Ok(ElementKind::Th),
+            "thead" => Ok(ElementKind::Thead),
+            "time" => Ok(ElementKind::Time),
+            "title" => Ok(ElementKind::Title),
+            "tr" => Ok(ElementKind::Tr),
+            "track" => Ok(ElementKind::Track),
+            "tt" => Ok(ElementKind::Tt),
+            "u" => Ok(ElementKind::U),
+            "ul" => Ok(ElementKind::Ul),
+            "var" => Ok(ElementKind::Var),
+            "video" => Ok(ElementKind::Video),
+            "wbr" => Ok(ElementKind::Wbr),
+            "xmp" => Ok(ElementKind::Xmp),
+            _ => Err(format!("unknown element name {:?}", s)),
         }
     }
 }
 
-#[derive(PartialEq, Debug)]
+/// Names with a hyphen that SVG and MathML already use, so they can't be
+/// custom elements.
+const RESERVED_CUSTOM_ELEMENT_NAMES: [&str; 8] = [
+    "annotation-xml",
+    "color-profile",
+    "font-face",
+    "font-face-src",
+    "font-face-uri",
+    "font-face-format",
+    "font-face-name",
+    "missing-glyph",
+];
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Namespace {
+    Html,
+    Svg,
+    MathMl,
+}
+
+impl Namespace {
+    pub fn url(&self) -> &'static str {
+        match self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Element {
     kind: ElementKind,
+    local_name: String,
+    namespace: Namespace,
     attributes: Vec<Attribute>,
+    /// The form a form-associated element belongs to, set by the parser.
+    form_owner: Weak<RefCell<Node>>,
+    /// The current value of a form control once it differs from its `value`
+    /// attribute.
+    dirty_value: Option<String>,
+    /// The current checkedness of a checkbox or a radio button once it
+    /// differs from its `checked` attribute.
+    dirty_checkedness: Option<bool>,
+    /// The contents of a `template` element. They are kept apart from its
+    /// children so that they are not rendered.
+    template_content: Option<Rc<RefCell<Node>>>,
+    /// The id index of the document the element is in and the element's own
+    /// node, so that changing its id keeps the index up to date. Both are
+    /// empty while the element isn't in a document.
+    id_index: Weak<RefCell<IdIndex>>,
+    node: Weak<RefCell<Node>>,
+}
+
+/// The form owner, what the user did to a control and the link to the id
+/// index aren't compared.
+impl PartialEq for Element {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+            && self.local_name == other.local_name
+            && self.namespace == other.namespace
+            && self.attributes == other.attributes
+    }
 }
 
 impl Element {
+    /// Creates an element of the HTML namespace.
     pub fn new(tag: &str, attributes: Vec<Attribute>) -> Self {
+        Self::new_in_namespace(Namespace::Html, tag, attributes)
+    }
+
+    pub fn new_in_namespace(namespace: Namespace, tag: &str, attributes: Vec<Attribute>) -> Self {
+        let kind = match namespace {
+            Namespace::Html => ElementKind::from_str(tag).unwrap_or(ElementKind::Unknown),
+            _ => ElementKind::Unknown,
+        };
+
+        // Only the first of attributes with the same name is kept.
+        let mut unique: Vec<Attribute> = Vec::new();
+        for a in attributes {
+            let name = match namespace {
+                Namespace::Html => a.name().to_ascii_lowercase(),
+                _ => a.name(),
+            };
+            if !unique.iter().any(|u| u.name() == name) {
+                unique.push(Attribute::with_value(&name, &a.value()));
+            }
+        }
+        let attributes = unique;
+
+        let template_content = match kind {
+            ElementKind::Template => {
+                Some(Rc::new(RefCell::new(Node::new(NodeKind::DocumentFragment))))
+            }
+            _ => None,
+        };
+
         Self {
-            kind: ElementKind::from_str(tag).expect("failed to convert string to Element Kind"),
+            kind,
+            local_name: tag.to_string(),
+            namespace,
             attributes,
+            form_owner: Weak::new(),
+            dirty_value: None,
+            dirty_checkedness: None,
+            template_content,
+            id_index: Weak::new(),
+            node: Weak::new(),
         }
     }
 
     pub fn kind(&self) -> ElementKind {
         self.kind
     }
+
+    pub fn local_name(&self) -> String {
+        self.local_name.clone()
+    }
+
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    /// Returns true for an element whose name is a valid custom element name,
+    /// such as `my-element`.
+    pub fn is_custom(&self) -> bool {
+        self.namespace == Namespace::Html
+            && self.kind == ElementKind::Unknown
+            && self
+                .local_name
+                .starts_with(|c: char| c.is_ascii_lowercase())
+            && self.local_name.contains('-')
+            && !RESERVED_CUSTOM_ELEMENT_NAMES.contains(&self.local_name.as_str())
+    }
+
+    pub fn attributes(&self) -> Vec<Attribute> {
+        self.attributes.clone()
+    }
+
+    pub fn attribute_iter(&self) -> core::slice::Iter<'_, Attribute> {
+        self.attributes.iter()
+    }
+
+    /// Attribute names of HTML elements are ASCII case-insensitive; those of
+    /// SVG and MathML elements are not.
+    fn attribute_name(&self, name: &str) -> String {
+        match self.namespace {
+            Namespace::Html => name.to_ascii_lowercase(),
+            _ => name.to_string(),
+        }
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<String> {
+        let name = self.attribute_name(name);
+        self.attributes
+            .iter()
+            .find(|a| a.name() == name)
+            .map(|a| a.value())
+    }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.get_attribute(name).is_some()
+    }
+
+    /// Sets the value of the attribute `name`, adding the attribute after the
+    /// others if the element doesn't have it yet.
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        let name = self.attribute_name(name);
+        self.update_id(false);
+        match self.attributes.iter_mut().find(|a| a.name() == name) {
+            Some(a) => a.set_value(value),
+            None => self.attributes.push(Attribute::with_value(&name, value)),
+        }
+        self.update_id(true);
+    }
+
+    /// Removes the attribute `name` and returns its value.
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        let name = self.attribute_name(name);
+        let index = self.attributes.iter().position(|a| a.name() == name)?;
+        self.update_id(false);
+        let value = self.attributes.remove(index).value();
+        self.update_id(true);
+        Some(value)
+    }
+
+    /// Adds the id of the element to the index of the document it is in, or
+    /// removes it from there. Every change to the index goes through here.
+    fn update_id(&self, add: bool) {
+        let (index, id) = match (self.id_index.upgrade(), self.id()) {
+            (Some(index), Some(id)) if !id.is_empty() => (index, id),
+            _ => return,
+        };
+        let mut index = index.borrow_mut();
+        match add {
+            true => index.add(id, self.node.clone()),
+            false => index.remove(&id, &self.node),
+        }
+    }
+
+    pub fn id(&self) -> Option<String> {
+        self.get_attribute("id")
+    }
+
+    /// Returns the classes in the `class` attribute, in order and without
+    /// duplicates.
+    pub fn class_list(&self) -> Vec<String> {
+        let mut classes: Vec<String> = Vec::new();
+        if let Some(class) = self.get_attribute("class") {
+            for c in class.split_ascii_whitespace() {
+                if !classes.iter().any(|existing| existing == c) {
+                    classes.push(c.to_string());
+                }
+            }
+        }
+        classes
+    }
+
+    pub fn set_form_owner(&mut self, form: Weak<RefCell<Node>>) {
+        self.form_owner = form;
+    }
+
+    pub fn form_owner(&self) -> Weak<RefCell<Node>> {
+        self.form_owner.clone()
+    }
+
+    pub fn set_value(&mut self, value: String) {
+        self.dirty_value = Some(value);
+    }
+
+    pub fn value(&self) -> Option<String> {
+        self.dirty_value.clone()
+    }
+
+    pub fn set_checked(&mut self, checked: bool) {
+        self.dirty_checkedness = Some(checked);
+    }
+
+    pub fn checked(&self) -> Option<bool> {
+        self.dirty_checkedness
+    }
+
+    /// Returns the `DocumentFragment` that holds the contents of a
+    /// `template` element, or None for any other element.
+    pub fn template_content(&self) -> Option<Rc<RefCell<Node>>> {
+        self.template_content.clone()
+    }
+}
+
+/// The compatibility mode of a document. Pages with an old DOCTYPE or none
+/// at all are in quirks mode, and are rendered with the quirks of old
+/// browsers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    /// Only a few quirks, such as the height of lines of images, apply.
+    LimitedQuirks,
+    Quirks,
+}
+
+/// The elements in a document by their ids. The document shares it with the
+/// elements in it, which keep it up to date as they come and go and as their
+/// ids change.
+#[derive(Debug, Default)]
+struct IdIndex {
+    ids: BTreeMap<String, Vec<Weak<RefCell<Node>>>>,
+}
+
+impl IdIndex {
+    fn add(&mut self, id: String, element: Weak<RefCell<Node>>) {
+        self.ids.entry(id).or_default().push(element);
+    }
+
+    fn remove(&mut self, id: &str, element: &Weak<RefCell<Node>>) {
+        if let Some(elements) = self.ids.get_mut(id) {
+            elements.retain(|e| !Weak::ptr_eq(e, element));
+            if elements.is_empty() {
+                self.ids.remove(id);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Document {
+    quirks_mode: QuirksMode,
+    ids: Rc<RefCell<IdIndex>>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    pub fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.quirks_mode = mode;
+    }
+
+    /// Returns `document.compatMode`.
+    pub fn compat_mode(&self) -> &'static str {
+        match self.quirks_mode {
+            QuirksMode::Quirks => "BackCompat",
+            _ => "CSS1Compat",
+        }
+    }
+
+    /// Returns the elements in the document with the id `id`, in no
+    /// particular order.
+    pub fn elements_with_id(&self, id: &str) -> Vec<Rc<RefCell<Node>>> {
+        self.ids
+            .borrow()
+            .ids
+            .get(id)
+            .map(|elements| elements.iter().filter_map(Weak::upgrade).collect())
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Debug)]
 pub enum NodeKind {
-    Document,
+    Document(Document),
+    /// A DOCTYPE. Missing identifiers are empty strings, as in the DOM.
+    DocumentType {
+        name: String,
+        public_id: String,
+        system_id: String,
+    },
+    /// A node without a parent that holds other nodes, such as the contents
+    /// of a `template` element.
+    DocumentFragment,
     Element(Element),
     Text(String),
+    Comment(String),
 }
 
 impl PartialEq for NodeKind {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Self::Document, Self::Document) => true,
-            (Self::Element(l0), Self::Element(r0)) => l0.kind == r0.kind,
+            (Self::Document(_), Self::Document(_)) => true,
+            (Self::DocumentFragment, Self::DocumentFragment) => true,
+            (Self::Element(l0), Self::Element(r0)) => l0 == r0,
             (Self::Text(l0), Self::Text(r0)) => l0 == r0,
+            (Self::Comment(l0), Self::Comment(r0)) => l0 == r0,
+            (
+                Self::DocumentType {
+                    name: l0,
+                    public_id: l1,
+                    system_id: l2,
+                },
+                Self::DocumentType {
+                    name: r0,
+                    public_id: r1,
+                    system_id: r2,
+                },
+            ) => l0 == r0 && l1 == r1 && l2 == r2,
             _ => false,
         }
     }
@@ -154,6 +748,330 @@ impl Node {
     }
 }
 
+/// Returns a copy of `node` without a parent, as `cloneNode()` does. With
+/// `deep`, its children and the contents of a template are copied too.
+pub fn clone_node(node: &Rc<RefCell<Node>>, deep: bool) -> Rc<RefCell<Node>> {
+    let kind = match node.borrow().kind {
+        NodeKind::Document(ref d) => {
+            let mut copy = Document::new();
+            copy.set_quirks_mode(d.quirks_mode());
+            NodeKind::Document(copy)
+        }
+        NodeKind::DocumentType {
+            ref name,
+            ref public_id,
+            ref system_id,
+        } => NodeKind::DocumentType {
+            name: name.clone(),
+            public_id: public_id.clone(),
+            system_id: system_id.clone(),
+        },
+        NodeKind::DocumentFragment => NodeKind::DocumentFragment,
+        NodeKind::Element(ref e) => {
+            let mut copy = Element::new_in_namespace(e.namespace, &e.local_name, e.attributes());
+            copy.dirty_value = e.dirty_value.clone();
+            copy.dirty_checkedness = e.dirty_checkedness;
+            if let (Some(from), Some(to), true) =
+                (&e.template_content, &copy.template_content, deep)
+            {
+                clone_children(from, to);
+            }
+            NodeKind::Element(copy)
+        }
+        NodeKind::Text(ref s) => NodeKind::Text(s.clone()),
+        NodeKind::Comment(ref s) => NodeKind::Comment(s.clone()),
+    };
+
+    let copy = Rc::new(RefCell::new(Node::new(kind)));
+    if deep {
+        clone_children(node, &copy);
+    }
+    copy
+}
+
+/// Appends deep copies of the children of `from` to `to`.
+fn clone_children(from: &Rc<RefCell<Node>>, to: &Rc<RefCell<Node>>) {
+    let mut child = from.borrow().first_child();
+    while let Some(c) = child {
+        insert(to, &clone_node(&c, true), None);
+        child = c.borrow().next_sibling();
+    }
+}
+
+/// Appends `node` to the children of `parent`, as `appendChild()` does, and
+/// returns it. See `insert_before`.
+pub fn append_child(
+    parent: &Rc<RefCell<Node>>,
+    node: Rc<RefCell<Node>>,
+) -> Result<Rc<RefCell<Node>>, Error> {
+    insert_before(parent, node, None)
+}
+
+/// Inserts `node` into `parent` right before its child `child`, or last if
+/// `child` is None, as `insertBefore()` does, and returns it. `node` is
+/// first removed from where it is; a `DocumentFragment` is replaced by its
+/// children.
+pub fn insert_before(
+    parent: &Rc<RefCell<Node>>,
+    node: Rc<RefCell<Node>>,
+    child: Option<&Rc<RefCell<Node>>>,
+) -> Result<Rc<RefCell<Node>>, Error> {
+    ensure_insertion_is_valid(parent, &node, child, false)?;
+
+    // inserting a node before itself leaves it where it is
+    let child = match child {
+        Some(c) if Rc::ptr_eq(c, &node) => node.borrow().next_sibling(),
+        c => c.cloned(),
+    };
+    insert(parent, &node, child.as_ref());
+    Ok(node)
+}
+
+/// Removes `child` from `parent`, as `removeChild()` does, and returns it.
+pub fn remove_child(
+    parent: &Rc<RefCell<Node>>,
+    child: &Rc<RefCell<Node>>,
+) -> Result<Rc<RefCell<Node>>, Error> {
+    if !is_child_of(child, parent) {
+        return Err(Error::NotFound(
+            "the node to remove is not a child of the parent".to_string(),
+        ));
+    }
+    detach(child);
+    Ok(child.clone())
+}
+
+/// Puts `node` in the place of `child` in `parent`, as `replaceChild()`
+/// does, and returns `child`.
+pub fn replace_child(
+    parent: &Rc<RefCell<Node>>,
+    node: Rc<RefCell<Node>>,
+    child: &Rc<RefCell<Node>>,
+) -> Result<Rc<RefCell<Node>>, Error> {
+    ensure_insertion_is_valid(parent, &node, Some(child), true)?;
+
+    let mut reference = child.borrow().next_sibling();
+    if reference.as_ref().is_some_and(|r| Rc::ptr_eq(r, &node)) {
+        reference = node.borrow().next_sibling();
+    }
+    if !Rc::ptr_eq(child, &node) {
+        detach(child);
+        insert(parent, &node, reference.as_ref());
+    }
+    Ok(child.clone())
+}
+
+fn is_child_of(node: &Rc<RefCell<Node>>, parent: &Rc<RefCell<Node>>) -> bool {
+    node.borrow()
+        .parent()
+        .upgrade()
+        .is_some_and(|p| Rc::ptr_eq(&p, parent))
+}
+
+/// Returns an error if inserting `node` into `parent` before `child`, or in
+/// place of it if `replacing`, would make a tree the DOM doesn't allow.
+fn ensure_insertion_is_valid(
+    parent: &Rc<RefCell<Node>>,
+    node: &Rc<RefCell<Node>>,
+    child: Option<&Rc<RefCell<Node>>>,
+    replacing: bool,
+) -> Result<(), Error> {
+    let hierarchy_error = |message: &str| Err(Error::HierarchyRequest(message.to_string()));
+
+    let parent_is_document = match parent.borrow().kind {
+        NodeKind::Document(_) => true,
+        NodeKind::DocumentFragment | NodeKind::Element(_) => false,
+        _ => return hierarchy_error("the parent can't have children"),
+    };
+
+    let mut ancestor = Some(parent.clone());
+    while let Some(a) = ancestor {
+        if Rc::ptr_eq(&a, node) {
+            return hierarchy_error("a node can't be inserted into itself");
+        }
+        ancestor = a.borrow().parent().upgrade();
+    }
+
+    if let Some(child) = child {
+        if !is_child_of(child, parent) {
+            return Err(Error::NotFound(
+                "the reference node is not a child of the parent".to_string(),
+            ));
+        }
+    }
+
+    match node.borrow().kind {
+        NodeKind::Document(_) => return hierarchy_error("a document can't be inserted"),
+        NodeKind::Text(_) if parent_is_document => {
+            return hierarchy_error("text can't be a child of a document")
+        }
+        NodeKind::DocumentType { .. } if !parent_is_document => {
+            return hierarchy_error("a DOCTYPE can only be a child of a document")
+        }
+        _ => {}
+    }
+    if !parent_is_document {
+        return Ok(());
+    }
+
+    // a document has at most one DOCTYPE and one element, in that order
+    let is_element = |n: &Rc<RefCell<Node>>| matches!(n.borrow().kind, NodeKind::Element(_));
+    let is_doctype =
+        |n: &Rc<RefCell<Node>>| matches!(n.borrow().kind, NodeKind::DocumentType { .. });
+    let is_replaced = |n: &Rc<RefCell<Node>>| replacing && child.is_some_and(|c| Rc::ptr_eq(c, n));
+    let siblings: Vec<_> = children(parent).collect();
+    let child_index = child.and_then(|c| siblings.iter().position(|s| Rc::ptr_eq(s, c)));
+    let (before, after) = match child_index {
+        Some(i) => (
+            &siblings[..i],
+            &siblings[i + if replacing { 1 } else { 0 }..],
+        ),
+        None => (&siblings[..], &siblings[siblings.len()..]),
+    };
+    let has_element = siblings.iter().any(|s| is_element(s) && !is_replaced(s));
+    let has_doctype = siblings.iter().any(|s| is_doctype(s) && !is_replaced(s));
+
+    let element_count = match node.borrow().kind {
+        NodeKind::DocumentFragment => {
+            let children: Vec<_> = children(node).collect();
+            if children
+                .iter()
+                .any(|c| matches!(c.borrow().kind, NodeKind::Text(_)))
+            {
+                return hierarchy_error("text can't be a child of a document");
+            }
+            children.iter().filter(|c| is_element(c)).count()
+        }
+        NodeKind::Element(_) => 1,
+        NodeKind::DocumentType { .. } => {
+            if has_doctype || before.iter().any(is_element) || child.is_none() && has_element {
+                return hierarchy_error("a document can only have one DOCTYPE, before its element");
+            }
+            0
+        }
+        _ => 0,
+    };
+    if element_count > 1 || element_count == 1 && (has_element || after.iter().any(is_doctype)) {
+        return hierarchy_error("a document can only have one element, after its DOCTYPE");
+    }
+
+    Ok(())
+}
+
+/// Inserts `node`, or the children of a `DocumentFragment`, before `child`
+/// once the insertion is known to be valid.
+fn insert(parent: &Rc<RefCell<Node>>, node: &Rc<RefCell<Node>>, child: Option<&Rc<RefCell<Node>>>) {
+    let nodes = match node.borrow().kind {
+        NodeKind::DocumentFragment => children(node).collect(),
+        _ => Vec::from([node.clone()]),
+    };
+    for n in nodes {
+        detach(&n);
+        link(parent, n.clone(), child);
+        update_ids(&n, true);
+    }
+}
+
+/// Removes `node` from its parent, if it has one.
+fn detach(node: &Rc<RefCell<Node>>) {
+    let parent = match node.borrow().parent().upgrade() {
+        Some(parent) => parent,
+        None => return,
+    };
+    update_ids(node, false);
+    let previous = node.borrow().previous_sibling().upgrade();
+    let next = node.borrow().next_sibling();
+
+    match previous {
+        Some(ref previous) => previous.borrow_mut().set_next_sibling(next.clone()),
+        None => parent.borrow_mut().set_first_child(next.clone()),
+    }
+    let previous = previous.as_ref().map(Rc::downgrade).unwrap_or_default();
+    match next {
+        Some(ref next) => next.borrow_mut().set_previous_sibling(previous),
+        None => parent.borrow_mut().set_last_child(previous),
+    }
+
+    let mut node = node.borrow_mut();
+    node.set_parent(Weak::new());
+    node.set_previous_sibling(Weak::new());
+    node.set_next_sibling(None);
+}
+
+/// Links `node`, which has no parent, into `parent` before `child`, or last.
+fn link(parent: &Rc<RefCell<Node>>, node: Rc<RefCell<Node>>, child: Option<&Rc<RefCell<Node>>>) {
+    let previous = match child {
+        Some(child) => child.borrow().previous_sibling().upgrade(),
+        None => parent.borrow().last_child().upgrade(),
+    };
+    match previous {
+        Some(ref previous) => previous.borrow_mut().set_next_sibling(Some(node.clone())),
+        None => parent.borrow_mut().set_first_child(Some(node.clone())),
+    }
+    match child {
+        Some(child) => child
+            .borrow_mut()
+            .set_previous_sibling(Rc::downgrade(&node)),
+        None => parent.borrow_mut().set_last_child(Rc::downgrade(&node)),
+    }
+
+    let mut n = node.borrow_mut();
+    n.set_parent(Rc::downgrade(parent));
+    n.set_previous_sibling(previous.as_ref().map(Rc::downgrade).unwrap_or_default());
+    n.set_next_sibling(child.cloned());
+}
+
+/// Returns the document `node` is in, if it is in one.
+fn document_of(node: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>> {
+    let root = ancestors(node).last().unwrap_or_else(|| node.clone());
+    let is_document = matches!(root.borrow().kind, NodeKind::Document(_));
+    is_document.then_some(root)
+}
+
+/// Connects `node` and the elements under it to the id index of the
+/// document they are in, or disconnects them from it.
+fn update_ids(node: &Rc<RefCell<Node>>, add: bool) {
+    if !matches!(node.borrow().kind, NodeKind::Element(_)) {
+        return;
+    }
+    let index = match document_of(node) {
+        Some(document) => match document.borrow().kind {
+            NodeKind::Document(ref d) => Rc::downgrade(&d.ids),
+            _ => return,
+        },
+        None => return,
+    };
+    for n in inclusive_descendants(node) {
+        if let NodeKind::Element(ref mut e) = n.borrow_mut().kind {
+            if add {
+                e.id_index = index.clone();
+                e.node = Rc::downgrade(&n);
+                e.update_id(true);
+            } else {
+                e.update_id(false);
+                e.id_index = Weak::new();
+                e.node = Weak::new();
+            }
+        }
+    }
+}
+
+/// Sets an attribute of the element `node`, like `Element::set_attribute`.
+pub fn set_attribute(node: &Rc<RefCell<Node>>, name: &str, value: &str) {
+    if let NodeKind::Element(ref mut e) = node.borrow_mut().kind {
+        e.set_attribute(name, value);
+    }
+}
+
+/// Removes an attribute of the element `node` and returns its value, like
+/// `Element::remove_attribute`.
+pub fn remove_attribute(node: &Rc<RefCell<Node>>, name: &str) -> Option<String> {
+    match node.borrow_mut().kind {
+        NodeKind::Element(ref mut e) => e.remove_attribute(name),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Window {
     document: Rc<RefCell<Node>>,
@@ -162,7 +1080,7 @@ pub struct Window {
 impl Window {
     pub fn new() -> Self {
         Self {
-            document: Rc::new(RefCell::new(Node::new(NodeKind::Document))),
+            document: Rc::new(RefCell::new(Node::new(NodeKind::Document(Document::new())))),
         }
     }
 
@@ -170,3 +1088,245 @@ impl Window {
         self.document.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_elements_keep_their_name() {
+        let known = Element::new("section", Vec::new());
+        assert_eq!(known.kind(), ElementKind::Section);
+        assert!(!known.is_custom());
+
+        let custom = Element::new("my-widget", Vec::new());
+        assert_eq!(custom.kind(), ElementKind::Unknown);
+        assert_eq!(custom.local_name(), "my-widget".to_string());
+        assert!(custom.is_custom());
+
+        let unknown = Element::new("blah", Vec::new());
+        assert_eq!(unknown.kind(), ElementKind::Unknown);
+        assert!(!unknown.is_custom());
+        assert_ne!(unknown, custom);
+    }
+
+    #[test]
+    fn foreign_elements_are_not_html_elements() {
+        let a = Element::new_in_namespace(Namespace::Svg, "a", Vec::new());
+        assert_eq!(a.kind(), ElementKind::Unknown);
+        assert_eq!(a.namespace(), Namespace::Svg);
+        assert_ne!(a, Element::new("a", Vec::new()));
+    }
+    fn attributes(pairs: &[(&str, &str)]) -> Vec<Attribute> {
+        pairs
+            .iter()
+            .map(|(name, value)| Attribute::with_value(name, value))
+            .collect()
+    }
+
+    #[test]
+    fn read_and_write_attributes() {
+        let mut a = Element::new(
+            "a",
+            attributes(&[("HREF", "/x"), ("id", "top"), ("href", "/y")]),
+        );
+        assert_eq!(a.get_attribute("href"), Some("/x".to_string()));
+        assert_eq!(a.get_attribute("Href"), Some("/x".to_string()));
+        assert_eq!(a.attribute_iter().count(), 2);
+        assert_eq!(a.id(), Some("top".to_string()));
+
+        a.set_attribute("ID", "bottom");
+        a.set_attribute("class", " b  a b\tc ");
+        assert_eq!(a.id(), Some("bottom".to_string()));
+        assert_eq!(
+            a.class_list(),
+            Vec::from(["b".to_string(), "a".to_string(), "c".to_string()])
+        );
+
+        assert_eq!(a.remove_attribute("href"), Some("/x".to_string()));
+        assert!(!a.has_attribute("href"));
+        assert_eq!(a.remove_attribute("href"), None);
+        let names: Vec<String> = a.attribute_iter().map(|a| a.name()).collect();
+        assert_eq!(names, Vec::from(["id".to_string(), "class".to_string()]));
+    }
+
+    #[test]
+    fn element_nodes_compare_attributes() {
+        let a = NodeKind::Element(Element::new("a", attributes(&[("href", "/x")])));
+        let b = NodeKind::Element(Element::new("a", attributes(&[("href", "/y")])));
+        assert_ne!(a, b);
+        assert_eq!(
+            a,
+            NodeKind::Element(Element::new("a", attributes(&[("href", "/x")])))
+        );
+    }
+
+    #[test]
+    fn clone_nodes() {
+        let template = Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
+            "template",
+            attributes(&[("id", "t")]),
+        )))));
+        let content = match template.borrow().kind {
+            NodeKind::Element(ref e) => e.template_content().unwrap(),
+            _ => unreachable!(),
+        };
+        let source = Rc::new(RefCell::new(Node::new(NodeKind::DocumentFragment)));
+        let text = Rc::new(RefCell::new(Node::new(NodeKind::Text("a".to_string()))));
+        source.borrow_mut().set_first_child(Some(text.clone()));
+        source.borrow_mut().set_last_child(Rc::downgrade(&text));
+        clone_children(&source, &content);
+
+        let content_of = |node: &Rc<RefCell<Node>>| match node.borrow().kind {
+            NodeKind::Element(ref e) => e.template_content().unwrap(),
+            _ => unreachable!(),
+        };
+
+        let shallow = clone_node(&template, false);
+        assert_eq!(shallow, template);
+        assert_eq!(content_of(&shallow).borrow().first_child(), None);
+
+        let deep = clone_node(&template, true);
+        let copied = content_of(&deep).borrow().first_child().unwrap();
+        assert_eq!(copied, content.borrow().first_child().unwrap());
+        assert!(!Rc::ptr_eq(
+            &copied,
+            &content.borrow().first_child().unwrap()
+        ));
+        assert!(Rc::ptr_eq(
+            &copied.borrow().parent().upgrade().unwrap(),
+            &content_of(&deep)
+        ));
+        let id = match deep.borrow().kind {
+            NodeKind::Element(ref e) => e.id(),
+            _ => None,
+        };
+        assert_eq!(id, Some("t".to_string()));
+    }
+
+    fn new_node(kind: NodeKind) -> Rc<RefCell<Node>> {
+        Rc::new(RefCell::new(Node::new(kind)))
+    }
+
+    fn text(s: &str) -> Rc<RefCell<Node>> {
+        new_node(NodeKind::Text(s.to_string()))
+    }
+
+    /// Returns the texts of the children of `parent`, checking that every
+    /// link between them agrees both ways.
+    fn child_texts(parent: &Rc<RefCell<Node>>) -> Vec<String> {
+        let mut texts = Vec::new();
+        let mut previous: Option<Rc<RefCell<Node>>> = None;
+        let mut child = parent.borrow().first_child();
+        while let Some(c) = child {
+            assert!(Rc::ptr_eq(&c.borrow().parent().upgrade().unwrap(), parent));
+            let p = c.borrow().previous_sibling().upgrade();
+            assert_eq!(p.is_some(), previous.is_some());
+            assert!(p.zip(previous).map_or(true, |(p, q)| Rc::ptr_eq(&p, &q)));
+            if let NodeKind::Text(ref s) = c.borrow().kind {
+                texts.push(s.clone());
+            }
+            child = c.borrow().next_sibling();
+            previous = Some(c);
+        }
+        let last = parent.borrow().last_child().upgrade();
+        assert!(last.zip(previous).map_or(true, |(l, p)| Rc::ptr_eq(&l, &p)));
+        texts
+    }
+
+    #[test]
+    fn mutations_keep_links_consistent() {
+        let div = new_node(NodeKind::Element(Element::new("div", Vec::new())));
+        let a = text("a");
+        let b = text("b");
+        let c = text("c");
+        append_child(&div, a.clone()).unwrap();
+        append_child(&div, c.clone()).unwrap();
+        insert_before(&div, b.clone(), Some(&c)).unwrap();
+        assert_eq!(child_texts(&div), ["a", "b", "c"]);
+
+        // moving a node within its parent
+        append_child(&div, a.clone()).unwrap();
+        assert_eq!(child_texts(&div), ["b", "c", "a"]);
+        insert_before(&div, a.clone(), Some(&b)).unwrap();
+        assert_eq!(child_texts(&div), ["a", "b", "c"]);
+        insert_before(&div, b.clone(), Some(&b)).unwrap();
+        assert_eq!(child_texts(&div), ["a", "b", "c"]);
+
+        let d = text("d");
+        assert!(Rc::ptr_eq(&replace_child(&div, d.clone(), &b).unwrap(), &b));
+        assert_eq!(child_texts(&div), ["a", "d", "c"]);
+        assert!(b.borrow().parent().upgrade().is_none());
+        replace_child(&div, c.clone(), &a).unwrap();
+        assert_eq!(child_texts(&div), ["c", "d"]);
+
+        remove_child(&div, &d).unwrap();
+        assert_eq!(child_texts(&div), ["c"]);
+        assert!(d.borrow().next_sibling().is_none());
+        assert!(d.borrow().previous_sibling().upgrade().is_none());
+
+        // the children of a fragment are moved, not the fragment
+        let fragment = new_node(NodeKind::DocumentFragment);
+        append_child(&fragment, a.clone()).unwrap();
+        append_child(&fragment, b.clone()).unwrap();
+        insert_before(&div, fragment.clone(), Some(&c)).unwrap();
+        assert_eq!(child_texts(&div), ["a", "b", "c"]);
+        assert!(fragment.borrow().first_child().is_none());
+        assert!(fragment.borrow().last_child().upgrade().is_none());
+    }
+
+    #[test]
+    fn mutations_reject_invalid_trees() {
+        let document = new_node(NodeKind::Document(Document::new()));
+        let html = new_node(NodeKind::Element(Element::new("html", Vec::new())));
+        let body = new_node(NodeKind::Element(Element::new("body", Vec::new())));
+        append_child(&document, html.clone()).unwrap();
+        append_child(&html, body.clone()).unwrap();
+
+        let is_hierarchy_error =
+            |r: Result<Rc<RefCell<Node>>, Error>| matches!(r, Err(Error::HierarchyRequest(_)));
+        assert!(is_hierarchy_error(append_child(&body, html.clone())));
+        assert!(is_hierarchy_error(append_child(&body, body.clone())));
+        assert!(is_hierarchy_error(append_child(&document, text("a"))));
+        assert!(is_hierarchy_error(append_child(
+            &document,
+            new_node(NodeKind::Element(Element::new("p", Vec::new())))
+        )));
+        assert!(is_hierarchy_error(append_child(&text("a"), text("b"))));
+        let doctype = || {
+            new_node(NodeKind::DocumentType {
+                name: "html".to_string(),
+                public_id: String::new(),
+                system_id: String::new(),
+            })
+        };
+        assert!(is_hierarchy_error(append_child(&document, doctype())));
+        assert!(is_hierarchy_error(append_child(&body, doctype())));
+        insert_before(&document, doctype(), Some(&html)).unwrap();
+
+        // the element of a document can be replaced by another one
+        let other = new_node(NodeKind::Element(Element::new("html", Vec::new())));
+        replace_child(&document, other.clone(), &html).unwrap();
+        assert!(Rc::ptr_eq(
+            &document.borrow().last_child().upgrade().unwrap(),
+            &other
+        ));
+
+        assert!(matches!(
+            remove_child(&body, &other),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            insert_before(&body, text("a"), Some(&other)),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn foreign_attribute_names_are_case_sensitive() {
+        let svg =
+            Element::new_in_namespace(Namespace::Svg, "svg", attributes(&[("viewBox", "0 0 1 1")]));
+        assert!(svg.has_attribute("viewBox"));
+        assert!(!svg.has_attribute("viewbox"));
+    }
+}
@@ -0,0 +1,265 @@
+//! Finding elements in a document or under an element, like
+//! `getElementById()`, `getElementsByTagName()` and
+//! `getElementsByClassName()`.
+
+use core::cell::RefCell;
+
+use alloc::{rc::Rc, vec::Vec};
+
+use super::{
+    node::{Namespace, Node, NodeKind, QuirksMode},
+    traversal::{ancestors, descendants, precedes},
+};
+
+/// Returns the first element in tree order under `node` whose id is `id`.
+/// In a document, the elements are looked up in its id index instead of
+/// walking the tree.
+pub fn get_element_by_id(node: &Rc<RefCell<Node>>, id: &str) -> Option<Rc<RefCell<Node>>> {
+    if id.is_empty() {
+        return None;
+    }
+
+    let indexed = match node.borrow().kind {
+        NodeKind::Document(ref d) => Some(d.elements_with_id(id)),
+        _ => None,
+    };
+    match indexed {
+        Some(elements) => elements
+            .into_iter()
+            .reduce(|first, e| if precedes(&e, &first) { e } else { first }),
+        None => descendants(node).find(|n| match n.borrow().kind {
+            NodeKind::Element(ref e) => e.id().is_some_and(|i| i == id),
+            _ => false,
+        }),
+    }
+}
+
+/// Returns the elements under `node` in tree order whose name is
+/// `qualified_name`, or all of them for `"*"`. HTML elements are matched
+/// case-insensitively. The result is a snapshot, not a live collection.
+pub fn get_elements_by_tag_name(
+    node: &Rc<RefCell<Node>>,
+    qualified_name: &str,
+) -> Vec<Rc<RefCell<Node>>> {
+    let lowercase = qualified_name.to_ascii_lowercase();
+    elements_under(node, |n| match n.kind {
+        NodeKind::Element(ref e) => {
+            qualified_name == "*"
+                || match e.namespace() {
+                    Namespace::Html => e.local_name() == lowercase,
+                    _ => e.local_name() == qualified_name,
+                }
+        }
+        _ => false,
+    })
+}
+
+/// Returns the elements under `node` in tree order that have every class in
+/// the space-separated `class_names`. Classes are matched
+/// case-insensitively in a quirks mode document.
+pub fn get_elements_by_class_name(
+    node: &Rc<RefCell<Node>>,
+    class_names: &str,
+) -> Vec<Rc<RefCell<Node>>> {
+    let wanted: Vec<&str> = class_names.split_ascii_whitespace().collect();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let root = ancestors(node).last().unwrap_or_else(|| node.clone());
+    let quirks = match root.borrow().kind {
+        NodeKind::Document(ref d) => d.quirks_mode() == QuirksMode::Quirks,
+        _ => false,
+    };
+
+    elements_under(node, |n| match n.kind {
+        NodeKind::Element(ref e) => {
+            let classes = e.class_list();
+            wanted.iter().all(|w| {
+                classes.iter().any(|c| match quirks {
+                    true => c.eq_ignore_ascii_case(w),
+                    false => c == w,
+                })
+            })
+        }
+        _ => false,
+    })
+}
+
+fn elements_under(
+    node: &Rc<RefCell<Node>>,
+    matches: impl Fn(&Node) -> bool,
+) -> Vec<Rc<RefCell<Node>>> {
+    descendants(node).filter(|n| matches(&n.borrow())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::string::{String, ToString};
+    use crate::renderer::dom::node::{
+        append_child, clone_node, remove_attribute, remove_child, set_attribute, Element,
+    };
+    use crate::renderer::html::{parser::HtmlParser, token::HtmlTokenizer};
+
+    fn parse(html: &str) -> Rc<RefCell<Node>> {
+        let window = HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree();
+        let document = window.borrow().document();
+        document
+    }
+
+    fn texts(elements: &[Rc<RefCell<Node>>]) -> Vec<String> {
+        elements
+            .iter()
+            .map(|e| {
+                descendants(e)
+                    .filter_map(|n| match n.borrow().kind {
+                        NodeKind::Text(ref s) => Some(s.clone()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn text_of(element: Option<Rc<RefCell<Node>>>) -> String {
+        texts(&[element.unwrap()]).concat()
+    }
+
+    #[test]
+    fn test_get_elements() {
+        let document = parse(
+            "<div id=a><p class='x y'>1</p><P CLASS=y>2</P><svg><clipPath id=a class=x>3</clipPath></svg></div><p id=a>4</p>",
+        );
+        assert_eq!(text_of(get_element_by_id(&document, "a")), "123");
+        assert_eq!(get_element_by_id(&document, "b"), None);
+        assert_eq!(get_element_by_id(&document, ""), None);
+
+        let div = get_element_by_id(&document, "a").unwrap();
+        assert_eq!(text_of(get_element_by_id(&div, "a")), "3");
+
+        assert_eq!(
+            texts(&get_elements_by_tag_name(&document, "P")),
+            ["1", "2", "4"]
+        );
+        assert_eq!(texts(&get_elements_by_tag_name(&div, "p")), ["1", "2"]);
+        assert!(get_elements_by_tag_name(&div, "clippath").is_empty());
+        assert_eq!(texts(&get_elements_by_tag_name(&div, "clipPath")), ["3"]);
+        assert_eq!(get_elements_by_tag_name(&div, "*").len(), 4);
+
+        assert_eq!(
+            texts(&get_elements_by_class_name(&document, " y ")),
+            ["1", "2"]
+        );
+        assert_eq!(texts(&get_elements_by_class_name(&document, "y x")), ["1"]);
+        assert_eq!(texts(&get_elements_by_class_name(&div, "x")), ["1", "3"]);
+        assert!(get_elements_by_class_name(&document, " ").is_empty());
+
+        // classes are case-insensitive in quirks mode
+        let quirks = parse("<p class=Big>1</p>");
+        assert_eq!(texts(&get_elements_by_class_name(&quirks, "big")), ["1"]);
+        let standards = parse("<!DOCTYPE html><p class=Big>1</p>");
+        assert!(get_elements_by_class_name(&standards, "big").is_empty());
+    }
+
+    #[test]
+    fn test_id_index_follows_mutations() {
+        let document = parse("<!DOCTYPE html><body><p id=a>1</p></body>");
+        let body = get_elements_by_tag_name(&document, "body").remove(0);
+        let p = get_element_by_id(&document, "a").unwrap();
+
+        remove_child(&body, &p).unwrap();
+        assert_eq!(get_element_by_id(&document, "a"), None);
+
+        // the ids of a whole subtree come and go with it
+        let div = Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
+            "div",
+            Vec::new(),
+        )))));
+        append_child(&div, p.clone()).unwrap();
+        append_child(&body, div.clone()).unwrap();
+        assert_eq!(text_of(get_element_by_id(&document, "a")), "1");
+
+        // a copy with the same id goes after the first one in tree order
+        let copy = clone_node(&p, true);
+        append_child(&body, copy.clone()).unwrap();
+        assert!(Rc::ptr_eq(&get_element_by_id(&document, "a").unwrap(), &p));
+        remove_child(&body, &div).unwrap();
+        assert!(Rc::ptr_eq(
+            &get_element_by_id(&document, "a").unwrap(),
+            &copy
+        ));
+
+        set_attribute(&copy, "ID", "b");
+        assert_eq!(get_element_by_id(&document, "a"), None);
+        assert!(Rc::ptr_eq(
+            &get_element_by_id(&document, "b").unwrap(),
+            &copy
+        ));
+        assert_eq!(remove_attribute(&copy, "id"), Some("b".to_string()));
+        assert_eq!(get_element_by_id(&document, "b"), None);
+    }
+
+    #[test]
+    fn test_id_index_follows_attribute_changes() {
+        let document = parse("<!DOCTYPE html><p id=a>1</p><p id=b>2</p>");
+        let p = get_element_by_id(&document, "a").unwrap();
+
+        set_attribute(&p, "id", "c");
+        assert_eq!(get_element_by_id(&document, "a"), None);
+        assert!(Rc::ptr_eq(&get_element_by_id(&document, "c").unwrap(), &p));
+
+        let second = get_element_by_id(&document, "b").unwrap();
+        assert_eq!(remove_attribute(&second, "id"), Some("b".to_string()));
+        assert_eq!(get_element_by_id(&document, "b"), None);
+        assert!(Rc::ptr_eq(&get_element_by_id(&document, "c").unwrap(), &p));
+    }
+
+    fn indexed(document: &Rc<RefCell<Node>>, id: &str) -> usize {
+        match document.borrow().kind {
+            NodeKind::Document(ref d) => d.elements_with_id(id).len(),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn test_id_index_follows_element_methods() {
+        let document = parse("<!DOCTYPE html><body><p id=a>1</p></body>");
+        let body = get_elements_by_tag_name(&document, "body").remove(0);
+        let p = get_element_by_id(&document, "a").unwrap();
+
+        if let NodeKind::Element(ref mut e) = p.borrow_mut().kind {
+            e.set_attribute("id", "b");
+        }
+        assert_eq!(indexed(&document, "a"), 0);
+        assert_eq!(indexed(&document, "b"), 1);
+
+        // an element out of the document leaves the index alone
+        remove_child(&body, &p).unwrap();
+        assert_eq!(indexed(&document, "b"), 0);
+        if let NodeKind::Element(ref mut e) = p.borrow_mut().kind {
+            e.set_attribute("id", "c");
+        }
+        assert_eq!(indexed(&document, "c"), 0);
+
+        append_child(&body, p.clone()).unwrap();
+        assert_eq!(indexed(&document, "c"), 1);
+        if let NodeKind::Element(ref mut e) = p.borrow_mut().kind {
+            assert_eq!(e.remove_attribute("id"), Some("c".to_string()));
+        }
+        assert_eq!(indexed(&document, "c"), 0);
+    }
+
+    #[test]
+    fn test_id_given_to_an_earlier_element_is_found_first() {
+        let document = parse("<!DOCTYPE html><p>1</p><p id=a>2</p>");
+        let first = get_elements_by_tag_name(&document, "p").remove(0);
+
+        if let NodeKind::Element(ref mut e) = first.borrow_mut().kind {
+            e.set_attribute("id", "a");
+        }
+        assert!(Rc::ptr_eq(
+            &get_element_by_id(&document, "a").unwrap(),
+            &first
+        ));
+    }
+}
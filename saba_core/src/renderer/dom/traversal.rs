@@ -0,0 +1,708 @@
+//! Walking the DOM without recursion: iterators over the nodes related to a
+//! node, and the `TreeWalker` and `NodeIterator` of
+//! <https://dom.spec.whatwg.org/#traversal>.
+
+use core::cell::RefCell;
+
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+
+use super::node::{Node, NodeKind};
+
+/// Returns an iterator over the children of `node`.
+pub fn children(node: &Rc<RefCell<Node>>) -> Children {
+    Children {
+        next: node.borrow().first_child(),
+    }
+}
+
+/// Returns an iterator over the descendants of `node` in tree order, which
+/// is the order their start tags come in.
+pub fn descendants(node: &Rc<RefCell<Node>>) -> Descendants {
+    Descendants {
+        root: node.clone(),
+        next: node.borrow().first_child(),
+    }
+}
+
+/// Returns an iterator over `node` and its descendants in tree order.
+pub fn inclusive_descendants(node: &Rc<RefCell<Node>>) -> Descendants {
+    Descendants {
+        root: node.clone(),
+        next: Some(node.clone()),
+    }
+}
+
+/// Returns an iterator over the parent of `node`, its parent and so on up to
+/// the root.
+pub fn ancestors(node: &Rc<RefCell<Node>>) -> Ancestors {
+    Ancestors {
+        next: node.borrow().parent().upgrade(),
+    }
+}
+
+/// Returns an iterator over the nodes that come after `node` in tree order,
+/// up to the end of its tree.
+pub fn following(node: &Rc<RefCell<Node>>) -> Following {
+    Following {
+        next: next_in_tree_order(node, None),
+    }
+}
+
+/// Returns an iterator over the nodes that come before `node` in tree
+/// order, its ancestors included, from the closest one back to the root.
+pub fn preceding(node: &Rc<RefCell<Node>>) -> Preceding {
+    Preceding {
+        next: previous_in_tree_order(node, None),
+    }
+}
+
+/// Returns true if `a` comes before `b` in tree order. Nodes in different
+/// trees don't come before each other.
+pub fn precedes(a: &Rc<RefCell<Node>>, b: &Rc<RefCell<Node>>) -> bool {
+    // the path from the root down to each node
+    let path = |node: &Rc<RefCell<Node>>| {
+        let mut path: Vec<_> = ancestors(node).collect();
+        path.reverse();
+        path.push(node.clone());
+        path
+    };
+    let (a, b) = (path(a), path(b));
+    let common = a
+        .iter()
+        .zip(b.iter())
+        .take_while(|(x, y)| Rc::ptr_eq(x, y))
+        .count();
+    if common == 0 {
+        return false;
+    }
+
+    match (a.get(common), b.get(common)) {
+        // an ancestor comes before its descendants
+        (None, Some(_)) => true,
+        (Some(x), Some(y)) => {
+            let mut sibling = x.borrow().next_sibling();
+            while let Some(s) = sibling {
+                if Rc::ptr_eq(&s, y) {
+                    return true;
+                }
+                sibling = s.borrow().next_sibling();
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+pub struct Children {
+    next: Option<Rc<RefCell<Node>>>,
+}
+
+impl Iterator for Children {
+    type Item = Rc<RefCell<Node>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+        self.next = node.borrow().next_sibling();
+        Some(node)
+    }
+}
+
+pub struct Descendants {
+    root: Rc<RefCell<Node>>,
+    next: Option<Rc<RefCell<Node>>>,
+}
+
+impl Iterator for Descendants {
+    type Item = Rc<RefCell<Node>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+        self.next = next_in_tree_order(&node, Some(&self.root));
+        Some(node)
+    }
+}
+
+pub struct Ancestors {
+    next: Option<Rc<RefCell<Node>>>,
+}
+
+impl Iterator for Ancestors {
+    type Item = Rc<RefCell<Node>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+        self.next = node.borrow().parent().upgrade();
+        Some(node)
+    }
+}
+
+pub struct Following {
+    next: Option<Rc<RefCell<Node>>>,
+}
+
+impl Iterator for Following {
+    type Item = Rc<RefCell<Node>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+        self.next = next_in_tree_order(&node, None);
+        Some(node)
+    }
+}
+
+pub struct Preceding {
+    next: Option<Rc<RefCell<Node>>>,
+}
+
+impl Iterator for Preceding {
+    type Item = Rc<RefCell<Node>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.next.take()?;
+        self.next = previous_in_tree_order(&node, None);
+        Some(node)
+    }
+}
+
+fn is(a: &Rc<RefCell<Node>>, b: Option<&Rc<RefCell<Node>>>) -> bool {
+    b.is_some_and(|b| Rc::ptr_eq(a, b))
+}
+
+/// Returns the node after `node` in tree order, without leaving the
+/// descendants of `root` if there is one.
+fn next_in_tree_order(
+    node: &Rc<RefCell<Node>>,
+    root: Option<&Rc<RefCell<Node>>>,
+) -> Option<Rc<RefCell<Node>>> {
+    if let Some(child) = node.borrow().first_child() {
+        return Some(child);
+    }
+
+    let mut current = node.clone();
+    loop {
+        if is(&current, root) {
+            return None;
+        }
+        if let Some(next) = current.borrow().next_sibling() {
+            return Some(next);
+        }
+        let parent = current.borrow().parent().upgrade()?;
+        current = parent;
+    }
+}
+
+/// Returns the node before `node` in tree order, without leaving the
+/// inclusive descendants of `root` if there is one.
+fn previous_in_tree_order(
+    node: &Rc<RefCell<Node>>,
+    root: Option<&Rc<RefCell<Node>>>,
+) -> Option<Rc<RefCell<Node>>> {
+    if is(node, root) {
+        return None;
+    }
+
+    let previous = node.borrow().previous_sibling().upgrade();
+    match previous {
+        Some(mut previous) => {
+            // the last node of the previous sibling's subtree
+            loop {
+                let last = previous.borrow().last_child().upgrade();
+                match last {
+                    Some(last) => previous = last,
+                    None => return Some(previous),
+                }
+            }
+        }
+        None => node.borrow().parent().upgrade(),
+    }
+}
+
+/// What a `NodeFilter` decides about a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterResult {
+    Accept,
+    /// Leaves out the node. A `TreeWalker` leaves out its descendants too.
+    Reject,
+    /// Leaves out the node but not its descendants.
+    Skip,
+}
+
+type FilterCallback = Box<dyn Fn(&Node) -> FilterResult>;
+
+/// Decides which nodes a `TreeWalker` or a `NodeIterator` stops at: the
+/// kinds of nodes in `what_to_show`, of those the ones the callback
+/// accepts.
+pub struct NodeFilter {
+    what_to_show: u32,
+    callback: Option<FilterCallback>,
+}
+
+impl NodeFilter {
+    pub const SHOW_ALL: u32 = 0xffff_ffff;
+    pub const SHOW_ELEMENT: u32 = 0x1;
+    pub const SHOW_TEXT: u32 = 0x4;
+    pub const SHOW_COMMENT: u32 = 0x80;
+    pub const SHOW_DOCUMENT: u32 = 0x100;
+    pub const SHOW_DOCUMENT_TYPE: u32 = 0x200;
+    pub const SHOW_DOCUMENT_FRAGMENT: u32 = 0x400;
+
+    pub fn new(what_to_show: u32) -> Self {
+        Self {
+            what_to_show,
+            callback: None,
+        }
+    }
+
+    pub fn with_callback(
+        what_to_show: u32,
+        callback: impl Fn(&Node) -> FilterResult + 'static,
+    ) -> Self {
+        Self {
+            what_to_show,
+            callback: Some(Box::new(callback)),
+        }
+    }
+
+    fn filter(&self, node: &Rc<RefCell<Node>>) -> FilterResult {
+        let node = node.borrow();
+        let show = match node.kind {
+            NodeKind::Element(_) => Self::SHOW_ELEMENT,
+            NodeKind::Text(_) => Self::SHOW_TEXT,
+            NodeKind::Comment(_) => Self::SHOW_COMMENT,
+            NodeKind::Document(_) => Self::SHOW_DOCUMENT,
+            NodeKind::DocumentType { .. } => Self::SHOW_DOCUMENT_TYPE,
+            NodeKind::DocumentFragment => Self::SHOW_DOCUMENT_FRAGMENT,
+        };
+        if self.what_to_show & show == 0 {
+            return FilterResult::Skip;
+        }
+        match self.callback {
+            Some(ref callback) => callback(&node),
+            None => FilterResult::Accept,
+        }
+    }
+}
+
+/// Moves around the subtree of its root one step at a time, stopping only
+/// at the nodes its filter accepts, like the DOM's `TreeWalker`.
+pub struct TreeWalker {
+    root: Rc<RefCell<Node>>,
+    filter: NodeFilter,
+    current: Rc<RefCell<Node>>,
+}
+
+impl TreeWalker {
+    pub fn new(root: Rc<RefCell<Node>>, filter: NodeFilter) -> Self {
+        Self {
+            current: root.clone(),
+            root,
+            filter,
+        }
+    }
+
+    pub fn root(&self) -> Rc<RefCell<Node>> {
+        self.root.clone()
+    }
+
+    pub fn current_node(&self) -> Rc<RefCell<Node>> {
+        self.current.clone()
+    }
+
+    pub fn set_current_node(&mut self, node: Rc<RefCell<Node>>) {
+        self.current = node;
+    }
+
+    fn accept(&mut self, node: Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>> {
+        self.current = node.clone();
+        Some(node)
+    }
+
+    /// Moves to the closest accepted ancestor of the current node inside
+    /// the root.
+    pub fn parent_node(&mut self) -> Option<Rc<RefCell<Node>>> {
+        let mut node = self.current.clone();
+        while !Rc::ptr_eq(&node, &self.root) {
+            let parent = node.borrow().parent().upgrade()?;
+            node = parent;
+            if self.filter.filter(&node) == FilterResult::Accept {
+                return self.accept(node);
+            }
+        }
+        None
+    }
+
+    pub fn first_child(&mut self) -> Option<Rc<RefCell<Node>>> {
+        self.traverse_children(true)
+    }
+
+    pub fn last_child(&mut self) -> Option<Rc<RefCell<Node>>> {
+        self.traverse_children(false)
+    }
+
+    pub fn next_sibling(&mut self) -> Option<Rc<RefCell<Node>>> {
+        self.traverse_siblings(true)
+    }
+
+    pub fn previous_sibling(&mut self) -> Option<Rc<RefCell<Node>>> {
+        self.traverse_siblings(false)
+    }
+
+    fn traverse_children(&mut self, first: bool) -> Option<Rc<RefCell<Node>>> {
+        let child = |node: &Rc<RefCell<Node>>| match first {
+            true => node.borrow().first_child(),
+            false => node.borrow().last_child().upgrade(),
+        };
+        let sibling = |node: &Rc<RefCell<Node>>| match first {
+            true => node.borrow().next_sibling(),
+            false => node.borrow().previous_sibling().upgrade(),
+        };
+
+        let mut node = child(&self.current)?;
+        loop {
+            match self.filter.filter(&node) {
+                FilterResult::Accept => return self.accept(node),
+                FilterResult::Skip => {
+                    if let Some(c) = child(&node) {
+                        node = c;
+                        continue;
+                    }
+                }
+                FilterResult::Reject => {}
+            }
+
+            // go on with the next sibling of the node or of its closest
+            // ancestor that has one, without going above the current node
+            loop {
+                if let Some(s) = sibling(&node) {
+                    node = s;
+                    break;
+                }
+                let parent = node.borrow().parent().upgrade();
+                match parent {
+                    Some(p) if !Rc::ptr_eq(&p, &self.root) && !Rc::ptr_eq(&p, &self.current) => {
+                        node = p
+                    }
+                    _ => return None,
+                }
+            }
+        }
+    }
+
+    fn traverse_siblings(&mut self, next: bool) -> Option<Rc<RefCell<Node>>> {
+        let child = |node: &Rc<RefCell<Node>>| match next {
+            true => node.borrow().first_child(),
+            false => node.borrow().last_child().upgrade(),
+        };
+        let sibling = |node: &Rc<RefCell<Node>>| match next {
+            true => node.borrow().next_sibling(),
+            false => node.borrow().previous_sibling().upgrade(),
+        };
+
+        let mut node = self.current.clone();
+        if Rc::ptr_eq(&node, &self.root) {
+            return None;
+        }
+        loop {
+            let mut s = sibling(&node);
+            while let Some(candidate) = s {
+                node = candidate;
+                let result = self.filter.filter(&node);
+                if result == FilterResult::Accept {
+                    return self.accept(node);
+                }
+                // the children of a skipped node stand in for it
+                s = match result {
+                    FilterResult::Skip => child(&node),
+                    _ => None,
+                };
+                if s.is_none() {
+                    s = sibling(&node);
+                }
+            }
+
+            let parent = node.borrow().parent().upgrade()?;
+            node = parent;
+            if Rc::ptr_eq(&node, &self.root) || self.filter.filter(&node) == FilterResult::Accept {
+                return None;
+            }
+        }
+    }
+
+    /// Moves to the accepted node before the current node in tree order.
+    pub fn previous_node(&mut self) -> Option<Rc<RefCell<Node>>> {
+        let mut node = self.current.clone();
+        while !Rc::ptr_eq(&node, &self.root) {
+            let mut sibling = node.borrow().previous_sibling().upgrade();
+            while let Some(s) = sibling {
+                node = s;
+                let mut result = self.filter.filter(&node);
+                while result != FilterResult::Reject {
+                    let last = node.borrow().last_child().upgrade();
+                    match last {
+                        Some(last) => {
+                            node = last;
+                            result = self.filter.filter(&node);
+                        }
+                        None => break,
+                    }
+                }
+                if result == FilterResult::Accept {
+                    return self.accept(node);
+                }
+                sibling = node.borrow().previous_sibling().upgrade();
+            }
+
+            let parent = node.borrow().parent().upgrade();
+            match parent {
+                Some(p) if !Rc::ptr_eq(&node, &self.root) => node = p,
+                _ => return None,
+            }
+            if self.filter.filter(&node) == FilterResult::Accept {
+                return self.accept(node);
+            }
+        }
+        None
+    }
+
+    /// Moves to the accepted node after the current node in tree order.
+    pub fn next_node(&mut self) -> Option<Rc<RefCell<Node>>> {
+        let mut node = self.current.clone();
+        let mut result = FilterResult::Accept;
+        loop {
+            while result != FilterResult::Reject {
+                let first = node.borrow().first_child();
+                match first {
+                    Some(first) => {
+                        node = first;
+                        result = self.filter.filter(&node);
+                        if result == FilterResult::Accept {
+                            return self.accept(node);
+                        }
+                    }
+                    None => break,
+                }
+            }
+
+            // the next sibling of the node or of its closest ancestor that
+            // has one
+            let mut temporary = Some(node.clone());
+            loop {
+                let t = temporary?;
+                if Rc::ptr_eq(&t, &self.root) {
+                    return None;
+                }
+                if let Some(s) = t.borrow().next_sibling() {
+                    node = s;
+                    break;
+                }
+                temporary = t.borrow().parent().upgrade();
+            }
+
+            result = self.filter.filter(&node);
+            if result == FilterResult::Accept {
+                return self.accept(node);
+            }
+        }
+    }
+}
+
+/// Goes through the accepted nodes of the subtree of its root in tree
+/// order, like the DOM's `NodeIterator`. A rejected node only leaves out
+/// itself, not its descendants. Unlike the DOM's, it isn't moved when the
+/// node it is at is removed from the tree.
+pub struct NodeIterator {
+    root: Rc<RefCell<Node>>,
+    filter: NodeFilter,
+    reference: Rc<RefCell<Node>>,
+    pointer_before_reference: bool,
+}
+
+impl NodeIterator {
+    pub fn new(root: Rc<RefCell<Node>>, filter: NodeFilter) -> Self {
+        Self {
+            reference: root.clone(),
+            root,
+            filter,
+            pointer_before_reference: true,
+        }
+    }
+
+    pub fn root(&self) -> Rc<RefCell<Node>> {
+        self.root.clone()
+    }
+
+    pub fn reference_node(&self) -> Rc<RefCell<Node>> {
+        self.reference.clone()
+    }
+
+    pub fn next_node(&mut self) -> Option<Rc<RefCell<Node>>> {
+        self.traverse(true)
+    }
+
+    pub fn previous_node(&mut self) -> Option<Rc<RefCell<Node>>> {
+        self.traverse(false)
+    }
+
+    fn traverse(&mut self, next: bool) -> Option<Rc<RefCell<Node>>> {
+        let mut node = self.reference.clone();
+        let mut before_node = self.pointer_before_reference;
+        loop {
+            if before_node != next {
+                node = match next {
+                    true => next_in_tree_order(&node, Some(&self.root))?,
+                    false => previous_in_tree_order(&node, Some(&self.root))?,
+                };
+            } else {
+                before_node = !next;
+            }
+            if self.filter.filter(&node) == FilterResult::Accept {
+                break;
+            }
+        }
+
+        self.reference = node.clone();
+        self.pointer_before_reference = before_node;
+        Some(node)
+    }
+}
+
+impl Iterator for NodeIterator {
+    type Item = Rc<RefCell<Node>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_node()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::string::{String, ToString};
+    use crate::renderer::html::{parser::HtmlParser, token::HtmlTokenizer};
+
+    fn parse(html: &str) -> Rc<RefCell<Node>> {
+        let window = HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree();
+        let document = window.borrow().document();
+        document
+    }
+
+    fn name(node: &Rc<RefCell<Node>>) -> String {
+        match node.borrow().kind {
+            NodeKind::Element(ref e) => e.local_name(),
+            NodeKind::Text(ref s) => s.clone(),
+            NodeKind::Comment(_) => "#comment".to_string(),
+            NodeKind::Document(_) => "#document".to_string(),
+            _ => "?".to_string(),
+        }
+    }
+
+    fn names(nodes: impl Iterator<Item = Rc<RefCell<Node>>>) -> Vec<String> {
+        nodes.map(|n| name(&n)).collect()
+    }
+
+    fn find(root: &Rc<RefCell<Node>>, tag: &str) -> Rc<RefCell<Node>> {
+        descendants(root).find(|n| name(n) == tag).unwrap()
+    }
+
+    #[test]
+    fn test_iterators() {
+        let document = parse("<div><p>a<b>b</b></p><!--c--><p>d</p></div><i>e</i>");
+        let div = find(&document, "div");
+        let b = find(&document, "b");
+
+        assert_eq!(names(children(&div)), ["p", "#comment", "p"]);
+        assert_eq!(
+            names(descendants(&div)),
+            ["p", "a", "b", "b", "#comment", "p", "d"]
+        );
+        assert_eq!(names(inclusive_descendants(&b)), ["b", "b"]);
+        assert_eq!(
+            names(ancestors(&b)),
+            ["p", "div", "body", "html", "#document"]
+        );
+        assert_eq!(names(following(&b)), ["b", "#comment", "p", "d", "i", "e"]);
+        assert!(precedes(&div, &b));
+        assert!(!precedes(&b, &div));
+        assert!(precedes(&b, &find(&document, "i")));
+        assert!(!precedes(&b, &b));
+        assert_eq!(
+            names(preceding(&b)),
+            ["a", "p", "div", "body", "head", "html", "#document"]
+        );
+    }
+
+    #[test]
+    fn test_tree_walker() {
+        let document = parse("<div><p>a<b>b</b></p><!--c--><span><i>d</i></span><p>e</p></div>");
+        let div = find(&document, "div");
+
+        // spans are skipped, so their children stand in for them
+        let filter = NodeFilter::with_callback(NodeFilter::SHOW_ELEMENT, |node| match node.kind {
+            NodeKind::Element(ref e) if e.local_name() == "span" => FilterResult::Skip,
+            _ => FilterResult::Accept,
+        });
+        let mut walker = TreeWalker::new(div.clone(), filter);
+        let mut forward = Vec::new();
+        while let Some(n) = walker.next_node() {
+            forward.push(name(&n));
+        }
+        assert_eq!(forward, ["p", "b", "i", "p"]);
+        let mut backward = Vec::new();
+        while let Some(n) = walker.previous_node() {
+            backward.push(name(&n));
+        }
+        assert_eq!(backward, ["i", "b", "p", "div"]);
+
+        assert_eq!(walker.first_child().map(|n| name(&n)), Some("p".into()));
+        assert_eq!(walker.next_sibling().map(|n| name(&n)), Some("i".into()));
+        assert_eq!(walker.next_sibling().map(|n| name(&n)), Some("p".into()));
+        assert_eq!(walker.next_sibling(), None);
+        assert_eq!(
+            walker.previous_sibling().map(|n| name(&n)),
+            Some("i".into())
+        );
+        assert_eq!(walker.parent_node().map(|n| name(&n)), Some("div".into()));
+        assert_eq!(walker.parent_node(), None);
+        assert_eq!(walker.last_child().map(|n| name(&n)), Some("p".into()));
+
+        // a rejected node takes its descendants with it
+        let mut walker = TreeWalker::new(
+            div,
+            NodeFilter::with_callback(NodeFilter::SHOW_ALL, |node| match node.kind {
+                NodeKind::Element(ref e) if e.local_name() == "span" => FilterResult::Reject,
+                _ => FilterResult::Accept,
+            }),
+        );
+        let mut texts = Vec::new();
+        while let Some(n) = walker.next_node() {
+            if let NodeKind::Text(ref s) = n.borrow().kind {
+                texts.push(s.clone());
+            }
+        }
+        assert_eq!(texts, ["a", "b", "e"]);
+    }
+
+    #[test]
+    fn test_node_iterator() {
+        let document = parse("<p>a<b>b</b></p><!--c--><p>d</p>");
+
+        let body = find(&document, "body");
+        let iterator = NodeIterator::new(body.clone(), NodeFilter::new(NodeFilter::SHOW_TEXT));
+        assert_eq!(names(iterator), ["a", "b", "d"]);
+
+        let mut iterator = NodeIterator::new(
+            body,
+            NodeFilter::new(NodeFilter::SHOW_ELEMENT | NodeFilter::SHOW_COMMENT),
+        );
+        assert_eq!(iterator.next_node().map(|n| name(&n)), Some("body".into()));
+        assert_eq!(iterator.next_node().map(|n| name(&n)), Some("p".into()));
+        assert_eq!(iterator.next_node().map(|n| name(&n)), Some("b".into()));
+        // turning back gives the same node again first
+        assert_eq!(iterator.previous_node().map(|n| name(&n)), Some("b".into()));
+        assert_eq!(iterator.previous_node().map(|n| name(&n)), Some("p".into()));
+        assert_eq!(iterator.next_node().map(|n| name(&n)), Some("p".into()));
+        assert_eq!(names(iterator.by_ref()), ["b", "#comment", "p"]);
+        assert_eq!(iterator.next_node(), None);
+        assert_eq!(name(&iterator.reference_node()), "p");
+    }
+}
@@ -14,6 +14,13 @@ impl Attribute {
         }
     }
 
+    pub fn with_value(name: &str, value: &str) -> Self {
+        Self {
+            name: String::from(name),
+            value: String::from(value),
+        }
+    }
+
     pub fn add_name_char(&mut self, c: char) {
         self.name.push(c);
     }
@@ -21,4 +28,16 @@ impl Attribute {
     pub fn add_value_char(&mut self, c: char) {
         self.value.push(c);
     }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn value(&self) -> String {
+        self.value.clone()
+    }
+
+    pub fn set_value(&mut self, value: &str) {
+        self.value = String::from(value);
+    }
 }
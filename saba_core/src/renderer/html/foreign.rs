@@ -0,0 +1,273 @@
+//! The parts of the tree construction stage that deal with SVG and MathML
+//! elements, following
+//! <https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign>.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::renderer::dom::node::{Element, Namespace};
+
+use super::attribute::Attribute;
+
+/// SVG element names with capital letters, by their lowercase names. The
+/// tokenizer lowercases every tag name.
+const SVG_TAG_NAMES: [(&str, &str); 37] = [
+    ("altglyph", "altGlyph"),
+    ("altglyphdef", "altGlyphDef"),
+    ("altglyphitem", "altGlyphItem"),
+    ("animatecolor", "animateColor"),
+    ("animatemotion", "animateMotion"),
+    ("animatetransform", "animateTransform"),
+    ("clippath", "clipPath"),
+    ("feblend", "feBlend"),
+    ("fecolormatrix", "feColorMatrix"),
+    ("fecomponenttransfer", "feComponentTransfer"),
+    ("fecomposite", "feComposite"),
+    ("feconvolvematrix", "feConvolveMatrix"),
+    ("fediffuselighting", "feDiffuseLighting"),
+    ("fedisplacementmap", "feDisplacementMap"),
+    ("fedistantlight", "feDistantLight"),
+    ("fedropshadow", "feDropShadow"),
+    ("feflood", "feFlood"),
+    ("fefunca", "feFuncA"),
+    ("fefuncb", "feFuncB"),
+    ("fefuncg", "feFuncG"),
+    ("fefuncr", "feFuncR"),
+    ("fegaussianblur", "feGaussianBlur"),
+    ("feimage", "feImage"),
+    ("femerge", "feMerge"),
+    ("femergenode", "feMergeNode"),
+    ("femorphology", "feMorphology"),
+    ("feoffset", "feOffset"),
+    ("fepointlight", "fePointLight"),
+    ("fespecularlighting", "feSpecularLighting"),
+    ("fespotlight", "feSpotLight"),
+    ("fetile", "feTile"),
+    ("feturbulence", "feTurbulence"),
+    ("foreignobject", "foreignObject"),
+    ("glyphref", "glyphRef"),
+    ("lineargradient", "linearGradient"),
+    ("radialgradient", "radialGradient"),
+    ("textpath", "textPath"),
+];
+
+/// SVG attribute names with capital letters, by their lowercase names.
+const SVG_ATTRIBUTE_NAMES: [(&str, &str); 58] = [
+    ("attributename", "attributeName"),
+    ("attributetype", "attributeType"),
+    ("basefrequency", "baseFrequency"),
+    ("baseprofile", "baseProfile"),
+    ("calcmode", "calcMode"),
+    ("clippathunits", "clipPathUnits"),
+    ("diffuseconstant", "diffuseConstant"),
+    ("edgemode", "edgeMode"),
+    ("filterunits", "filterUnits"),
+    ("glyphref", "glyphRef"),
+    ("gradienttransform", "gradientTransform"),
+    ("gradientunits", "gradientUnits"),
+    ("kernelmatrix", "kernelMatrix"),
+    ("kernelunitlength", "kernelUnitLength"),
+    ("keypoints", "keyPoints"),
+    ("keysplines", "keySplines"),
+    ("keytimes", "keyTimes"),
+    ("lengthadjust", "lengthAdjust"),
+    ("limitingconeangle", "limitingConeAngle"),
+    ("markerheight", "markerHeight"),
+    ("markerunits", "markerUnits"),
+    ("markerwidth", "markerWidth"),
+    ("maskcontentunits", "maskContentUnits"),
+    ("maskunits", "maskUnits"),
+    ("numoctaves", "numOctaves"),
+    ("pathlength", "pathLength"),
+    ("patterncontentunits", "patternContentUnits"),
+    ("patterntransform", "patternTransform"),
+    ("patternunits", "patternUnits"),
+    ("pointsatx", "pointsAtX"),
+    ("pointsaty", "pointsAtY"),
+    ("pointsatz", "pointsAtZ"),
+    ("preservealpha", "preserveAlpha"),
+    ("preserveaspectratio", "preserveAspectRatio"),
+    ("primitiveunits", "primitiveUnits"),
+    ("refx", "refX"),
+    ("refy", "refY"),
+    ("repeatcount", "repeatCount"),
+    ("repeatdur", "repeatDur"),
+    ("requiredextensions", "requiredExtensions"),
+    ("requiredfeatures", "requiredFeatures"),
+    ("specularconstant", "specularConstant"),
+    ("specularexponent", "specularExponent"),
+    ("spreadmethod", "spreadMethod"),
+    ("startoffset", "startOffset"),
+    ("stddeviation", "stdDeviation"),
+    ("stitchtiles", "stitchTiles"),
+    ("surfacescale", "surfaceScale"),
+    ("systemlanguage", "systemLanguage"),
+    ("tablevalues", "tableValues"),
+    ("targetx", "targetX"),
+    ("targety", "targetY"),
+    ("textlength", "textLength"),
+    ("viewbox", "viewBox"),
+    ("viewtarget", "viewTarget"),
+    ("xchannelselector", "xChannelSelector"),
+    ("ychannelselector", "yChannelSelector"),
+    ("zoomandpan", "zoomAndPan"),
+];
+
+fn lookup(table: &[(&str, &'static str)], name: &str) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(lowercase, _)| *lowercase == name)
+        .map(|(_, adjusted)| *adjusted)
+}
+
+/// Returns the name of an element of `namespace` with its capital letters
+/// put back.
+pub fn adjust_tag_name(namespace: Namespace, tag: &str) -> String {
+    match namespace {
+        Namespace::Svg => lookup(&SVG_TAG_NAMES, tag).unwrap_or(tag).to_string(),
+        _ => tag.to_string(),
+    }
+}
+
+/// Returns the attributes of an element of `namespace` with the capital
+/// letters of their names put back.
+pub fn adjust_attributes(namespace: Namespace, attributes: Vec<Attribute>) -> Vec<Attribute> {
+    attributes
+        .into_iter()
+        .map(|a| {
+            let name = a.name();
+            let adjusted = match namespace {
+                Namespace::Svg => lookup(&SVG_ATTRIBUTE_NAMES, &name),
+                Namespace::MathMl if name == "definitionurl" => Some("definitionURL"),
+                _ => None,
+            };
+            match adjusted {
+                Some(adjusted) => Attribute::with_value(adjusted, &a.value()),
+                None => a,
+            }
+        })
+        .collect()
+}
+
+/// Returns true for the HTML start tags that can't be in SVG or MathML, so
+/// that they close the foreign elements they are in.
+pub fn is_breakout_tag(tag: &str, attributes: &[Attribute]) -> bool {
+    match tag {
+        "font" => attributes
+            .iter()
+            .any(|a| matches!(a.name().as_str(), "color" | "face" | "size")),
+        _ => matches!(
+            tag,
+            "b" | "big"
+                | "blockquote"
+                | "body"
+                | "br"
+                | "center"
+                | "code"
+                | "dd"
+                | "div"
+                | "dl"
+                | "dt"
+                | "em"
+                | "embed"
+                | "h1"
+                | "h2"
+                | "h3"
+                | "h4"
+                | "h5"
+                | "h6"
+                | "head"
+                | "hr"
+                | "i"
+                | "img"
+                | "li"
+                | "listing"
+                | "menu"
+                | "meta"
+                | "nobr"
+                | "ol"
+                | "p"
+                | "pre"
+                | "ruby"
+                | "s"
+                | "small"
+                | "span"
+                | "strong"
+                | "strike"
+                | "sub"
+                | "sup"
+                | "table"
+                | "tt"
+                | "u"
+                | "ul"
+                | "var"
+        ),
+    }
+}
+
+/// Returns true for the MathML elements whose text is parsed as in HTML.
+pub fn is_mathml_text_integration_point(e: &Element) -> bool {
+    e.namespace() == Namespace::MathMl
+        && matches!(e.local_name().as_str(), "mi" | "mo" | "mn" | "ms" | "mtext")
+}
+
+/// Returns true for the foreign elements whose content is parsed as HTML.
+pub fn is_html_integration_point(e: &Element) -> bool {
+    match e.namespace() {
+        Namespace::MathMl => {
+            e.local_name() == "annotation-xml"
+                && e.get_attribute("encoding").is_some_and(|encoding| {
+                    encoding.eq_ignore_ascii_case("text/html")
+                        || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+                })
+        }
+        Namespace::Svg => matches!(e.local_name().as_str(), "foreignObject" | "desc" | "title"),
+        Namespace::Html => false,
+    }
+}
+
+/// Returns true for the foreign elements that are special, which bound the
+/// scope of the elements open outside them.
+pub fn is_special_foreign_element(e: &Element) -> bool {
+    match e.namespace() {
+        Namespace::MathMl => matches!(
+            e.local_name().as_str(),
+            "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"
+        ),
+        Namespace::Svg => matches!(e.local_name().as_str(), "foreignObject" | "desc" | "title"),
+        Namespace::Html => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adjust_names() {
+        assert_eq!(
+            adjust_tag_name(Namespace::Svg, "lineargradient"),
+            "linearGradient"
+        );
+        assert_eq!(adjust_tag_name(Namespace::Svg, "path"), "path");
+        assert_eq!(
+            adjust_tag_name(Namespace::MathMl, "lineargradient"),
+            "lineargradient"
+        );
+
+        let attributes = Vec::from([
+            Attribute::with_value("viewbox", "0 0 1 1"),
+            Attribute::with_value("definitionurl", "x"),
+        ]);
+        let names = |namespace| -> Vec<String> {
+            adjust_attributes(namespace, attributes.clone())
+                .iter()
+                .map(|a| a.name())
+                .collect()
+        };
+        assert_eq!(names(Namespace::Svg), ["viewBox", "definitionurl"]);
+        assert_eq!(names(Namespace::MathMl), ["viewbox", "definitionURL"]);
+    }
+}
@@ -0,0 +1,669 @@
+//! Constructing the form data set of a `<form>` and encoding it for
+//! submission.
+
+use core::cell::RefCell;
+
+use alloc::{
+    format,
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    error::Error,
+    http::HttpRequest,
+    renderer::dom::{
+        node::{ElementKind, Node, NodeKind},
+        traversal::{ancestors, inclusive_descendants},
+    },
+    url::Url,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormEnctype {
+    UrlEncoded,
+    Multipart,
+    TextPlain,
+}
+
+impl FormEnctype {
+    fn from_attribute(value: &str) -> Self {
+        if value.eq_ignore_ascii_case("multipart/form-data") {
+            FormEnctype::Multipart
+        } else if value.eq_ignore_ascii_case("text/plain") {
+            FormEnctype::TextPlain
+        } else {
+            FormEnctype::UrlEncoded
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormValue {
+    Text(String),
+    /// A file input. Only the file name is known since no file can be chosen
+    /// yet.
+    File(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormEntry {
+    name: String,
+    value: FormValue,
+}
+
+impl FormEntry {
+    pub fn new(name: String, value: FormValue) -> Self {
+        Self { name, value }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn value(&self) -> FormValue {
+        self.value.clone()
+    }
+
+    fn text_value(&self) -> String {
+        match self.value {
+            FormValue::Text(ref v) => v.clone(),
+            FormValue::File(ref filename) => filename.clone(),
+        }
+    }
+}
+
+pub(crate) fn attribute(node: &Rc<RefCell<Node>>, name: &str) -> Option<String> {
+    match node.borrow().kind {
+        NodeKind::Element(ref element) => element.get_attribute(name),
+        _ => None,
+    }
+}
+
+fn text_content(node: &Rc<RefCell<Node>>) -> String {
+    let mut result = String::new();
+    for n in inclusive_descendants(node) {
+        if let NodeKind::Text(ref s) = n.borrow().kind {
+            result.push_str(s);
+        }
+    }
+    result
+}
+
+/// Collects every element under `node` (inclusive) in tree order.
+pub(crate) fn elements_in_tree_order(
+    node: &Rc<RefCell<Node>>,
+    result: &mut Vec<Rc<RefCell<Node>>>,
+) {
+    result.extend(
+        inclusive_descendants(node).filter(|n| matches!(n.borrow().kind, NodeKind::Element(_))),
+    );
+}
+
+fn root_of(node: &Rc<RefCell<Node>>) -> Rc<RefCell<Node>> {
+    ancestors(node).last().unwrap_or_else(|| node.clone())
+}
+
+fn form_owner(
+    control: &Rc<RefCell<Node>>,
+    elements: &[Rc<RefCell<Node>>],
+) -> Option<Rc<RefCell<Node>>> {
+    if let Some(id) = attribute(control, "form") {
+        return elements
+            .iter()
+            .find(|e| {
+                e.borrow().element_kind() == Some(ElementKind::Form)
+                    && attribute(e, "id").as_deref() == Some(id.as_str())
+            })
+            .cloned();
+    }
+
+    match control.borrow().kind {
+        NodeKind::Element(ref element) => element.form_owner().upgrade(),
+        _ => None,
+    }
+}
+
+/// Returns the form `control` belongs to, if any.
+pub(crate) fn form_of(control: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>> {
+    let mut elements = Vec::new();
+    elements_in_tree_order(&root_of(control), &mut elements);
+    form_owner(control, &elements)
+}
+
+fn control_type(control: &Rc<RefCell<Node>>) -> String {
+    attribute(control, "type")
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// Returns true for a button that submits its form when activated.
+pub(crate) fn is_submit_button(control: &Rc<RefCell<Node>>) -> bool {
+    let kind = control.borrow().element_kind();
+    match kind {
+        Some(ElementKind::Input) => matches!(control_type(control).as_str(), "submit" | "image"),
+        Some(ElementKind::Button) => !matches!(control_type(control).as_str(), "button" | "reset"),
+        _ => false,
+    }
+}
+
+/// Returns true for an input that the user types a single line into, which
+/// keeps Enter from submitting a form with several of them.
+pub(crate) fn blocks_implicit_submission(control: &Rc<RefCell<Node>>) -> bool {
+    if control.borrow().element_kind() != Some(ElementKind::Input) {
+        return false;
+    }
+    matches!(
+        control_type(control).as_str(),
+        "" | "text"
+            | "search"
+            | "url"
+            | "tel"
+            | "email"
+            | "password"
+            | "date"
+            | "month"
+            | "week"
+            | "time"
+            | "datetime-local"
+            | "number"
+    )
+}
+
+/// Returns what pressing Enter in `field` submits: the default button of
+/// its form if it has one, or else the form itself if no other field blocks
+/// implicit submission.
+pub(crate) fn implicit_submission(field: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>> {
+    let form = form_of(field)?;
+    let mut elements = Vec::new();
+    elements_in_tree_order(&root_of(&form), &mut elements);
+    let controls: Vec<Rc<RefCell<Node>>> = elements
+        .iter()
+        .filter(|e| form_owner(e, &elements).is_some_and(|owner| Rc::ptr_eq(&owner, &form)))
+        .cloned()
+        .collect();
+
+    if let Some(button) = controls.iter().find(|c| is_submit_button(c)) {
+        return Some(button.clone());
+    }
+    if controls
+        .iter()
+        .filter(|c| blocks_implicit_submission(c))
+        .count()
+        > 1
+    {
+        return None;
+    }
+    Some(form)
+}
+
+/// Toggles a checkbox, or checks a radio button and unchecks the others in
+/// its group.
+pub(crate) fn toggle(control: &Rc<RefCell<Node>>) {
+    let checked = is_checked(control);
+    match control_type(control).as_str() {
+        "checkbox" => set_checked(control, !checked),
+        "radio" => {
+            let name = attribute(control, "name").unwrap_or_default();
+            let form = form_of(control);
+            let mut elements = Vec::new();
+            elements_in_tree_order(&root_of(control), &mut elements);
+            for other in &elements {
+                let in_group = control_type(other) == "radio"
+                    && !name.is_empty()
+                    && attribute(other, "name").as_deref() == Some(name.as_str())
+                    && match (form_owner(other, &elements), &form) {
+                        (Some(a), Some(b)) => Rc::ptr_eq(&a, b),
+                        (None, None) => true,
+                        _ => false,
+                    };
+                if in_group {
+                    set_checked(other, false);
+                }
+            }
+            set_checked(control, true);
+        }
+        _ => {}
+    }
+}
+
+fn set_checked(control: &Rc<RefCell<Node>>, checked: bool) {
+    if let NodeKind::Element(ref mut element) = control.borrow_mut().kind {
+        element.set_checked(checked);
+    }
+}
+
+fn is_checked(node: &Rc<RefCell<Node>>) -> bool {
+    let dirty = match node.borrow().kind {
+        NodeKind::Element(ref element) => element.checked(),
+        _ => None,
+    };
+    dirty.unwrap_or_else(|| attribute(node, "checked").is_some())
+}
+
+fn control_value(node: &Rc<RefCell<Node>>) -> String {
+    let dirty = match node.borrow().kind {
+        NodeKind::Element(ref element) => element.value(),
+        _ => None,
+    };
+    dirty
+        .or_else(|| attribute(node, "value"))
+        .unwrap_or_default()
+}
+
+fn option_value(option: &Rc<RefCell<Node>>) -> String {
+    match attribute(option, "value") {
+        Some(value) => value,
+        None => text_content(option)
+            .split_ascii_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" "),
+    }
+}
+
+fn selected_options(select: &Rc<RefCell<Node>>) -> Vec<Rc<RefCell<Node>>> {
+    let mut elements = Vec::new();
+    elements_in_tree_order(select, &mut elements);
+    let options: Vec<Rc<RefCell<Node>>> = elements
+        .into_iter()
+        .filter(|e| e.borrow().element_kind() == Some(ElementKind::Option))
+        .collect();
+
+    let selected: Vec<Rc<RefCell<Node>>> = options
+        .iter()
+        .filter(|o| {
+            let dirty = match o.borrow().kind {
+                NodeKind::Element(ref element) => element.checked(),
+                _ => None,
+            };
+            dirty.unwrap_or_else(|| attribute(o, "selected").is_some())
+        })
+        .cloned()
+        .collect();
+
+    if !selected.is_empty() || attribute(select, "multiple").is_some() {
+        return selected;
+    }
+
+    // A single-choice select shows its first enabled option by default.
+    options
+        .into_iter()
+        .filter(|o| attribute(o, "disabled").is_none())
+        .take(1)
+        .collect()
+}
+
+/// Constructs the entry list of `form` as defined in the HTML standard.
+/// `submitter` is the button used to submit the form, if any.
+pub fn form_data_set(
+    form: &Rc<RefCell<Node>>,
+    submitter: Option<&Rc<RefCell<Node>>>,
+) -> Vec<FormEntry> {
+    let mut elements = Vec::new();
+    elements_in_tree_order(&root_of(form), &mut elements);
+
+    let mut entries = Vec::new();
+    for control in &elements {
+        let kind = control.borrow().element_kind();
+        match kind {
+            Some(ElementKind::Input)
+            | Some(ElementKind::Button)
+            | Some(ElementKind::Select)
+            | Some(ElementKind::Textarea) => {}
+            _ => continue,
+        }
+
+        match form_owner(control, &elements) {
+            Some(owner) if Rc::ptr_eq(&owner, form) => {}
+            _ => continue,
+        }
+        if attribute(control, "disabled").is_some() {
+            continue;
+        }
+
+        let is_submitter = submitter.is_some_and(|s| Rc::ptr_eq(s, control));
+        let input_type = control_type(control);
+
+        if kind == Some(ElementKind::Input) && input_type == "image" {
+            if is_submitter {
+                let prefix = match attribute(control, "name") {
+                    Some(name) if !name.is_empty() => format!("{}.", name),
+                    _ => String::new(),
+                };
+                entries.push(FormEntry::new(
+                    format!("{}x", prefix),
+                    FormValue::Text("0".to_string()),
+                ));
+                entries.push(FormEntry::new(
+                    format!("{}y", prefix),
+                    FormValue::Text("0".to_string()),
+                ));
+            }
+            continue;
+        }
+
+        let name = match attribute(control, "name") {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+
+        match kind {
+            Some(ElementKind::Input) => match input_type.as_str() {
+                "checkbox" | "radio" => {
+                    if is_checked(control) {
+                        let value = attribute(control, "value").unwrap_or("on".to_string());
+                        entries.push(FormEntry::new(name, FormValue::Text(value)));
+                    }
+                }
+                "submit" => {
+                    if is_submitter {
+                        entries.push(FormEntry::new(
+                            name,
+                            FormValue::Text(control_value(control)),
+                        ));
+                    }
+                }
+                "button" | "reset" => {}
+                "file" => entries.push(FormEntry::new(name, FormValue::File(String::new()))),
+                "hidden" if name.eq_ignore_ascii_case("_charset_") => {
+                    entries.push(FormEntry::new(name, FormValue::Text("UTF-8".to_string())));
+                }
+                _ => entries.push(FormEntry::new(
+                    name,
+                    FormValue::Text(control_value(control)),
+                )),
+            },
+            Some(ElementKind::Button) => {
+                let is_submit_button = input_type.is_empty() || input_type == "submit";
+                if is_submitter && is_submit_button {
+                    entries.push(FormEntry::new(
+                        name,
+                        FormValue::Text(control_value(control)),
+                    ));
+                }
+            }
+            Some(ElementKind::Select) => {
+                for option in selected_options(control) {
+                    entries.push(FormEntry::new(
+                        name.clone(),
+                        FormValue::Text(option_value(&option)),
+                    ));
+                }
+            }
+            Some(ElementKind::Textarea) => {
+                let dirty = match control.borrow().kind {
+                    NodeKind::Element(ref element) => element.value(),
+                    _ => None,
+                };
+                let value = dirty.unwrap_or_else(|| text_content(control));
+                entries.push(FormEntry::new(name, FormValue::Text(value)));
+            }
+            _ => {}
+        }
+    }
+
+    entries
+}
+
+fn normalize_newlines(s: &str) -> String {
+    s.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', "\r\n")
+}
+
+fn percent_encode_form(s: &str, result: &mut String) {
+    for byte in s.bytes() {
+        match byte {
+            b'*' | b'-' | b'.' | b'_' => result.push(byte as char),
+            b' ' => result.push('+'),
+            _ if byte.is_ascii_alphanumeric() => result.push(byte as char),
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+}
+
+/// Serializes entries as `application/x-www-form-urlencoded`.
+pub fn urlencode(entries: &[FormEntry]) -> String {
+    let mut result = String::new();
+    for entry in entries {
+        if !result.is_empty() {
+            result.push('&');
+        }
+        percent_encode_form(&normalize_newlines(&entry.name), &mut result);
+        result.push('=');
+        percent_encode_form(&normalize_newlines(&entry.text_value()), &mut result);
+    }
+    result
+}
+
+fn escape_multipart_name(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Serializes entries as `multipart/form-data` delimited by `boundary`.
+pub fn multipart_encode(entries: &[FormEntry], boundary: &str) -> String {
+    let mut result = String::new();
+    for entry in entries {
+        result.push_str(&format!("--{}\r\n", boundary));
+        let name = escape_multipart_name(&normalize_newlines(&entry.name));
+        match entry.value {
+            FormValue::Text(ref value) => {
+                result.push_str(&format!(
+                    "Content-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                    name
+                ));
+                result.push_str(&normalize_newlines(value));
+            }
+            FormValue::File(ref filename) => {
+                result.push_str(&format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n",
+                    name,
+                    escape_multipart_name(filename)
+                ));
+                result.push_str("Content-Type: application/octet-stream\r\n\r\n");
+            }
+        }
+        result.push_str("\r\n");
+    }
+    result.push_str(&format!("--{}--\r\n", boundary));
+    result
+}
+
+/// Serializes entries as `text/plain`.
+pub fn text_plain_encode(entries: &[FormEntry]) -> String {
+    let mut result = String::new();
+    for entry in entries {
+        result.push_str(&entry.name);
+        result.push('=');
+        result.push_str(&entry.text_value());
+        result.push_str("\r\n");
+    }
+    result
+}
+
+fn multipart_boundary(entries: &[FormEntry]) -> String {
+    let mut boundary = "----sabaFormBoundary".to_string();
+    let mut suffix = 0;
+    while entries
+        .iter()
+        .any(|e| e.name.contains(&boundary) || e.text_value().contains(&boundary))
+    {
+        suffix += 1;
+        boundary = format!("----sabaFormBoundary{}", suffix);
+    }
+    boundary
+}
+
+/// Builds the request that submitting `form` navigates to. Relative actions
+/// are resolved against `document_url`.
+pub fn submit(
+    form: &Rc<RefCell<Node>>,
+    submitter: Option<&Rc<RefCell<Node>>>,
+    document_url: &Url,
+) -> Result<HttpRequest, Error> {
+    // A submit button can override the form's own attributes.
+    let form_attribute = |submitter_name: &str, form_name: &str| -> Option<String> {
+        submitter
+            .and_then(|s| attribute(s, submitter_name))
+            .or_else(|| attribute(form, form_name))
+    };
+
+    let action = form_attribute("formaction", "action").unwrap_or_default();
+    let url = match document_url.resolve(&action) {
+        Ok(url) => url,
+        Err(e) => {
+            return Err(Error::UnexpectedInput(format!(
+                "invalid form action {}: {}",
+                action, e
+            )))
+        }
+    };
+    let method = form_attribute("formmethod", "method").unwrap_or_default();
+    let enctype =
+        FormEnctype::from_attribute(&form_attribute("formenctype", "enctype").unwrap_or_default());
+    let entries = form_data_set(form, submitter);
+
+    if !method.eq_ignore_ascii_case("post") {
+        let query = urlencode(&entries);
+        let url = match url.resolve(&format!("?{}", query)) {
+            Ok(url) => url,
+            Err(e) => return Err(Error::UnexpectedInput(e)),
+        };
+        return Ok(HttpRequest::new("GET", url));
+    }
+
+    let mut request = HttpRequest::new("POST", url);
+    let (content_type, body) = match enctype {
+        FormEnctype::UrlEncoded => (
+            "application/x-www-form-urlencoded".to_string(),
+            urlencode(&entries),
+        ),
+        FormEnctype::Multipart => {
+            let boundary = multipart_boundary(&entries);
+            (
+                format!("multipart/form-data; boundary={}", boundary),
+                multipart_encode(&entries, &boundary),
+            )
+        }
+        FormEnctype::TextPlain => ("text/plain".to_string(), text_plain_encode(&entries)),
+    };
+    request.set_header("Content-Type", content_type);
+    request.set_body(body);
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::html::{parser::HtmlParser, token::HtmlTokenizer};
+
+    fn forms(html: &str) -> Vec<Rc<RefCell<Node>>> {
+        let html = format!("<html><head></head><body>{}</body></html>", html);
+        let window = HtmlParser::new(HtmlTokenizer::new(html)).construct_tree();
+        let mut elements = Vec::new();
+        elements_in_tree_order(&window.borrow().document(), &mut elements);
+        elements
+            .into_iter()
+            .filter(|e| e.borrow().element_kind() == Some(ElementKind::Form))
+            .collect()
+    }
+
+    fn document_url() -> Url {
+        Url::new("http://intranet.local/app/index.html".to_string())
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn get_form_is_encoded_as_query() {
+        let form = forms(
+            "<form action=\"search\"><input name=\"q\" value=\"rust lang\"><input type=\"checkbox\" name=\"exact\"><input type=\"submit\" name=\"go\" value=\"Go\"></form>",
+        )
+        .remove(0);
+
+        let request = submit(&form, None, &document_url()).unwrap();
+
+        assert_eq!(request.method(), "GET".to_string());
+        assert_eq!(
+            request.url().url(),
+            "http://intranet.local/app/search?q=rust+lang".to_string()
+        );
+    }
+
+    #[test]
+    fn post_form_is_urlencoded() {
+        let form = forms(
+            "<form method=\"post\" action=\"/login\"><input name=\"user\" value=\"a&b\"><input type=\"password\" name=\"pass\" value=\"\u{3042}\"><select name=\"lang\"><option>en<option selected value=\"ja\">Japanese</select><textarea name=\"memo\">hi</textarea></form>",
+        )
+        .remove(0);
+
+        let request = submit(&form, None, &document_url()).unwrap();
+
+        assert_eq!(request.method(), "POST".to_string());
+        assert_eq!(
+            request.url().url(),
+            "http://intranet.local/login".to_string()
+        );
+        assert_eq!(
+            request.header_value("Content-Type"),
+            Some("application/x-www-form-urlencoded".to_string())
+        );
+        assert_eq!(
+            request.body(),
+            "user=a%26b&pass=%E3%81%82&lang=ja&memo=hi".to_string()
+        );
+    }
+
+    #[test]
+    fn only_the_submitter_button_is_included() {
+        let form = forms(
+            "<form><button name=\"action\" value=\"save\">Save</button><button name=\"action\" value=\"delete\">Delete</button></form>",
+        )
+        .remove(0);
+        let mut elements = Vec::new();
+        elements_in_tree_order(&form, &mut elements);
+        let delete = elements
+            .into_iter()
+            .filter(|e| e.borrow().element_kind() == Some(ElementKind::Button))
+            .nth(1)
+            .unwrap();
+
+        assert_eq!(
+            form_data_set(&form, Some(&delete)),
+            Vec::from([FormEntry::new(
+                "action".to_string(),
+                FormValue::Text("delete".to_string())
+            )])
+        );
+    }
+
+    #[test]
+    fn dirty_values_override_attributes() {
+        let form = forms("<form><input name=\"q\" value=\"default\"></form>").remove(0);
+        let input = form.borrow().first_child().unwrap();
+        if let NodeKind::Element(ref mut element) = input.borrow_mut().kind {
+            element.set_value("typed".to_string());
+        }
+
+        assert_eq!(
+            urlencode(&form_data_set(&form, None)),
+            "q=typed".to_string()
+        );
+    }
+
+    #[test]
+    fn multipart_body() {
+        let entries = Vec::from([
+            FormEntry::new("title".to_string(), FormValue::Text("a\nb".to_string())),
+            FormEntry::new("file".to_string(), FormValue::File(String::new())),
+        ]);
+
+        assert_eq!(
+            multipart_encode(&entries, "XyZ"),
+            "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\na\r\nb\r\n--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"\"\r\nContent-Type: application/octet-stream\r\n\r\n\r\n--XyZ--\r\n".to_string()
+        );
+    }
+}
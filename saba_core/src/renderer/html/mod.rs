@@ -1,3 +1,9 @@
 pub mod attribute;
+mod entities;
+mod foreign;
+pub mod form;
+pub mod parse_error;
 pub mod parser;
+mod quirks;
+pub mod serializer;
 pub mod token;
@@ -0,0 +1,223 @@
+//! Parse errors and source locations.
+//!
+//! A parse error never stops parsing: the spec defines how to recover from
+//! each of them, so they are only collected for tools that want to show them.
+//! The codes are the ones the HTML standard uses, such as `eof-in-tag`.
+
+/// A location in the source. `line` and `column` start at 1 and count
+/// characters, `offset` starts at 0. Line breaks are normalized first, so
+/// `\r\n` is a single character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SourcePosition {
+    line: usize,
+    column: usize,
+    offset: usize,
+}
+
+impl SourcePosition {
+    pub fn new(line: usize, column: usize, offset: usize) -> Self {
+        Self {
+            line,
+            column,
+            offset,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// The part of the source a token came from. `end` is just past the last
+/// character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SourceSpan {
+    start: SourcePosition,
+    end: SourcePosition,
+}
+
+impl SourceSpan {
+    pub fn new(start: SourcePosition, end: SourcePosition) -> Self {
+        Self { start, end }
+    }
+
+    pub fn start(&self) -> SourcePosition {
+        self.start
+    }
+
+    pub fn end(&self) -> SourcePosition {
+        self.end
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    AbruptClosingOfEmptyComment,
+    AbruptDoctypePublicIdentifier,
+    AbruptDoctypeSystemIdentifier,
+    AbsenceOfDigitsInNumericCharacterReference,
+    CdataInHtmlContent,
+    CharacterReferenceOutsideUnicodeRange,
+    ControlCharacterInInputStream,
+    ControlCharacterReference,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInCdata,
+    EofInComment,
+    EofInDoctype,
+    EofInScriptHtmlCommentLikeText,
+    EofInTag,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidCharacterSequenceAfterDoctypeName,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingDoctypeName,
+    MissingDoctypePublicIdentifier,
+    MissingDoctypeSystemIdentifier,
+    MissingEndTagName,
+    MissingQuoteBeforeDoctypePublicIdentifier,
+    MissingQuoteBeforeDoctypeSystemIdentifier,
+    MissingSemicolonAfterCharacterReference,
+    MissingWhitespaceAfterDoctypePublicKeyword,
+    MissingWhitespaceAfterDoctypeSystemKeyword,
+    MissingWhitespaceBeforeDoctypeName,
+    MissingWhitespaceBetweenAttributes,
+    MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+    NestedComment,
+    NoncharacterCharacterReference,
+    NoncharacterInInputStream,
+    NullCharacterReference,
+    SurrogateCharacterReference,
+    UnexpectedCharacterAfterDoctypeSystemIdentifier,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    UnknownNamedCharacterReference,
+}
+
+impl ParseErrorKind {
+    /// Returns the code the HTML standard gives this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseErrorKind::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            ParseErrorKind::AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            ParseErrorKind::AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference => {
+                "absence-of-digits-in-numeric-character-reference"
+            }
+            ParseErrorKind::CdataInHtmlContent => "cdata-in-html-content",
+            ParseErrorKind::CharacterReferenceOutsideUnicodeRange => {
+                "character-reference-outside-unicode-range"
+            }
+            ParseErrorKind::ControlCharacterInInputStream => "control-character-in-input-stream",
+            ParseErrorKind::ControlCharacterReference => "control-character-reference",
+            ParseErrorKind::DuplicateAttribute => "duplicate-attribute",
+            ParseErrorKind::EndTagWithAttributes => "end-tag-with-attributes",
+            ParseErrorKind::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            ParseErrorKind::EofBeforeTagName => "eof-before-tag-name",
+            ParseErrorKind::EofInCdata => "eof-in-cdata",
+            ParseErrorKind::EofInComment => "eof-in-comment",
+            ParseErrorKind::EofInDoctype => "eof-in-doctype",
+            ParseErrorKind::EofInScriptHtmlCommentLikeText => {
+                "eof-in-script-html-comment-like-text"
+            }
+            ParseErrorKind::EofInTag => "eof-in-tag",
+            ParseErrorKind::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            ParseErrorKind::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            ParseErrorKind::InvalidCharacterSequenceAfterDoctypeName => {
+                "invalid-character-sequence-after-doctype-name"
+            }
+            ParseErrorKind::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            ParseErrorKind::MissingAttributeValue => "missing-attribute-value",
+            ParseErrorKind::MissingDoctypeName => "missing-doctype-name",
+            ParseErrorKind::MissingDoctypePublicIdentifier => "missing-doctype-public-identifier",
+            ParseErrorKind::MissingDoctypeSystemIdentifier => "missing-doctype-system-identifier",
+            ParseErrorKind::MissingEndTagName => "missing-end-tag-name",
+            ParseErrorKind::MissingQuoteBeforeDoctypePublicIdentifier => {
+                "missing-quote-before-doctype-public-identifier"
+            }
+            ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier => {
+                "missing-quote-before-doctype-system-identifier"
+            }
+            ParseErrorKind::MissingSemicolonAfterCharacterReference => {
+                "missing-semicolon-after-character-reference"
+            }
+            ParseErrorKind::MissingWhitespaceAfterDoctypePublicKeyword => {
+                "missing-whitespace-after-doctype-public-keyword"
+            }
+            ParseErrorKind::MissingWhitespaceAfterDoctypeSystemKeyword => {
+                "missing-whitespace-after-doctype-system-keyword"
+            }
+            ParseErrorKind::MissingWhitespaceBeforeDoctypeName => {
+                "missing-whitespace-before-doctype-name"
+            }
+            ParseErrorKind::MissingWhitespaceBetweenAttributes => {
+                "missing-whitespace-between-attributes"
+            }
+            ParseErrorKind::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers => {
+                "missing-whitespace-between-doctype-public-and-system-identifiers"
+            }
+            ParseErrorKind::NestedComment => "nested-comment",
+            ParseErrorKind::NoncharacterCharacterReference => "noncharacter-character-reference",
+            ParseErrorKind::NoncharacterInInputStream => "noncharacter-in-input-stream",
+            ParseErrorKind::NullCharacterReference => "null-character-reference",
+            ParseErrorKind::SurrogateCharacterReference => "surrogate-character-reference",
+            ParseErrorKind::UnexpectedCharacterAfterDoctypeSystemIdentifier => {
+                "unexpected-character-after-doctype-system-identifier"
+            }
+            ParseErrorKind::UnexpectedCharacterInAttributeName => {
+                "unexpected-character-in-attribute-name"
+            }
+            ParseErrorKind::UnexpectedCharacterInUnquotedAttributeValue => {
+                "unexpected-character-in-unquoted-attribute-value"
+            }
+            ParseErrorKind::UnexpectedEqualsSignBeforeAttributeName => {
+                "unexpected-equals-sign-before-attribute-name"
+            }
+            ParseErrorKind::UnexpectedNullCharacter => "unexpected-null-character",
+            ParseErrorKind::UnexpectedQuestionMarkInsteadOfTagName => {
+                "unexpected-question-mark-instead-of-tag-name"
+            }
+            ParseErrorKind::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            ParseErrorKind::UnknownNamedCharacterReference => "unknown-named-character-reference",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    position: SourcePosition,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, position: SourcePosition) -> Self {
+        Self { kind, position }
+    }
+
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    pub fn position(&self) -> SourcePosition {
+        self.position
+    }
+}
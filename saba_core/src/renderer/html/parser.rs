@@ -1,12 +1,24 @@
 use core::{cell::RefCell, str::FromStr};
 
-use alloc::{rc::Rc, string::String, vec::Vec};
+use alloc::{
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
 
-use crate::renderer::dom::node::{Element, ElementKind, Node, NodeKind, Window};
+use crate::renderer::dom::node::{
+    self, Element, ElementKind, Namespace, Node, NodeKind, QuirksMode, Window,
+};
 
 use super::{
     attribute::Attribute,
-    token::{HtmlToken, HtmlTokenizer},
+    foreign::{
+        adjust_attributes, adjust_tag_name, is_breakout_tag, is_html_integration_point,
+        is_mathml_text_integration_point, is_special_foreign_element,
+    },
+    parse_error::ParseError,
+    quirks::quirks_mode,
+    token::{is_whitespace, HtmlToken, HtmlTokenizer, TokenizerState},
 };
 
 #[derive(Clone, Copy)]
@@ -18,15 +30,46 @@ enum InsertionMode {
     AfterHead,
     InBody,
     Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InTemplate,
     AfterBody,
     AfterAfterBody,
 }
 
+/// An entry of the list of active formatting elements. A marker keeps the
+/// formatting opened outside an element such as `object` from being
+/// reopened inside it.
+#[derive(Clone)]
+enum ActiveFormattingElement {
+    Marker,
+    Element(Rc<RefCell<Node>>),
+}
+
 pub struct HtmlParser {
     window: Rc<RefCell<Window>>,
     mode: InsertionMode,
     original_intersection_mode: InsertionMode,
     stack_of_open_elements: Vec<Rc<RefCell<Node>>>,
+    active_formatting_elements: Vec<ActiveFormattingElement>,
+    form_element_pointer: Option<Rc<RefCell<Node>>>,
+    /// Set while content misplaced in a table is moved out before it.
+    foster_parenting: bool,
+    /// Characters read in a table, until it is known whether they are only
+    /// whitespace.
+    pending_table_characters: Vec<char>,
+    /// The modes the contents of the open `template` elements are parsed in,
+    /// the innermost last.
+    template_insertion_modes: Vec<InsertionMode>,
+    /// Set after `<textarea>`, whose first newline is not part of its value.
+    skip_next_newline: bool,
+    /// The element whose content is parsed, when parsing a fragment.
+    context: Option<Rc<RefCell<Node>>>,
     t: HtmlTokenizer,
 }
 
@@ -38,6 +81,125 @@ impl HtmlParser {
             mode: InsertionMode::Initial,
             original_intersection_mode: InsertionMode::Initial,
             stack_of_open_elements: Vec::new(),
+            active_formatting_elements: Vec::new(),
+            form_element_pointer: None,
+            foster_parenting: false,
+            pending_table_characters: Vec::new(),
+            template_insertion_modes: Vec::new(),
+            skip_next_newline: false,
+            context: None,
+        }
+    }
+
+    /// Parses `html` as the content of the element `context`, as setting
+    /// `innerHTML` does, and returns the nodes it makes up. The nodes have
+    /// no parent.
+    pub fn parse_fragment(context: &Rc<RefCell<Node>>, html: String) -> Vec<Rc<RefCell<Node>>> {
+        let mut parser = HtmlParser::new(HtmlTokenizer::new(html));
+        parser.context = Some(context.clone());
+
+        // the fragment is in the mode of the document it goes into
+        let mut ancestor = Some(context.clone());
+        while let Some(node) = ancestor {
+            if let NodeKind::Document(ref d) = node.borrow().kind {
+                parser.set_quirks_mode(d.quirks_mode());
+            }
+            ancestor = node.borrow().parent().upgrade();
+        }
+
+        if let NodeKind::Element(ref e) = context.borrow().kind {
+            if e.namespace() == Namespace::Html {
+                if let Some(state) = fragment_content(&e.local_name()) {
+                    parser.t.switch_to(state);
+                }
+            }
+        }
+
+        parser.insert_element("html", Vec::new());
+        let root = parser.current_node();
+
+        // a form control in the fragment belongs to the form it goes into
+        let mut ancestor = Some(context.clone());
+        while let Some(node) = ancestor {
+            if node.borrow().element_kind() == Some(ElementKind::Form) {
+                parser.form_element_pointer = Some(node);
+                break;
+            }
+            ancestor = node.borrow().parent().upgrade();
+        }
+
+        if context.borrow().element_kind() == Some(ElementKind::Template) {
+            parser
+                .template_insertion_modes
+                .push(InsertionMode::InTemplate);
+        }
+        parser.reset_insertion_mode();
+        parser.update_cdata_allowed();
+        parser.finish();
+
+        let mut children = Vec::new();
+        let mut child = root.borrow().first_child();
+        while let Some(c) = child {
+            child = c.borrow().next_sibling();
+            children.push(c);
+        }
+        for c in children.iter() {
+            let result = node::remove_child(&root, c);
+            debug_assert!(result.is_ok());
+        }
+        children
+    }
+
+    /// Parses a whole document.
+    pub fn construct_tree(&mut self) -> Rc<RefCell<Window>> {
+        self.finish()
+    }
+
+    /// Parses the next chunk of the document, as far as it can be parsed
+    /// before the rest arrives. The tree built so far is in `window`.
+    pub fn feed(&mut self, chunk: &str) {
+        self.t.feed(chunk);
+        self.process_tokens();
+    }
+
+    /// Parses the next chunk of the document given as UTF-8 bytes.
+    pub fn feed_bytes(&mut self, bytes: &[u8]) {
+        self.t.feed_bytes(bytes);
+        self.process_tokens();
+    }
+
+    /// Parses what is left once the whole document has been fed.
+    pub fn finish(&mut self) -> Rc<RefCell<Window>> {
+        self.t.finish();
+        self.process_tokens();
+        self.process_token(HtmlToken::Eof);
+        self.window.clone()
+    }
+
+    pub fn window(&self) -> Rc<RefCell<Window>> {
+        self.window.clone()
+    }
+
+    /// Returns the errors the tokenizer has reported so far. None of them
+    /// stop parsing.
+    pub fn errors(&self) -> Vec<ParseError> {
+        self.t.errors()
+    }
+
+    fn quirks_mode(&self) -> QuirksMode {
+        let document = self.window.borrow().document();
+        let document = document.borrow();
+        match document.kind {
+            NodeKind::Document(ref d) => d.quirks_mode(),
+            _ => QuirksMode::NoQuirks,
+        }
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        let document = self.window.borrow().document();
+        let mut document = document.borrow_mut();
+        if let NodeKind::Document(ref mut d) = document.kind {
+            d.set_quirks_mode(mode);
         }
     }
 
@@ -70,268 +232,1147 @@ impl HtmlParser {
         }
     }
 
-    fn pop_current_node(&mut self, element_kind: ElementKind) -> bool {
-        let current = match self.stack_of_open_elements.last() {
-            Some(n) => n,
-            None => return false,
-        };
-
-        if current.borrow().element_kind() == Some(element_kind) {
-            self.stack_of_open_elements.pop();
-            return true;
+    /// Returns true if an element of `element_kind` is open and no element
+    /// that is a boundary of the scope is open inside it.
+    fn has_element_in_specific_scope(
+        &self,
+        element_kind: ElementKind,
+        is_boundary: fn(&Node) -> bool,
+    ) -> bool {
+        for node in self.stack_of_open_elements.iter().rev() {
+            let node = node.borrow();
+            if node.element_kind() == Some(element_kind) {
+                return true;
+            }
+            if is_boundary(&node) {
+                return false;
+            }
         }
 
         false
     }
 
-    fn create_element(&self, tag: &str, attributes: Vec<Attribute>) -> Node {
-        Node::new(NodeKind::Element(Element::new(tag, attributes)))
+    fn has_element_in_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_in_specific_scope(element_kind, is_scope_boundary)
     }
 
-    fn insert_element(&mut self, tag: &str, attributes: Vec<Attribute>) {
-        let window = self.window.borrow();
-        let mut current = window.document();
+    fn has_element_in_list_item_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_in_specific_scope(element_kind, |node| {
+            is_scope_boundary(node)
+                || matches!(
+                    node.element_kind(),
+                    Some(ElementKind::Ol) | Some(ElementKind::Ul)
+                )
+        })
+    }
+
+    fn has_element_in_button_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_in_specific_scope(element_kind, |node| {
+            is_scope_boundary(node) || node.element_kind() == Some(ElementKind::Button)
+        })
+    }
+
+    fn has_element_in_table_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_in_specific_scope(element_kind, |node| {
+            matches!(
+                node.element_kind(),
+                Some(ElementKind::Html) | Some(ElementKind::Table) | Some(ElementKind::Template)
+            )
+        })
+    }
+
+    /// Pops elements until the current node is one of `kinds`, `template` or
+    /// `html`.
+    fn clear_stack_back_to(&mut self, kinds: &[ElementKind]) {
         loop {
-            match self.stack_of_open_elements.last() {
-                Some(n) => {
-                    let node = n.clone();
-                    if let NodeKind::Text(_) = node.borrow().kind {
-                        self.stack_of_open_elements.pop();
-                        continue;
-                    };
-                    current = node;
-                    break;
-                }
-                None => {
-                    break;
-                }
+            let kind = match self.current_node().borrow().element_kind() {
+                Some(kind) => kind,
+                None => return,
+            };
+            if kinds.contains(&kind) || kind == ElementKind::Template || kind == ElementKind::Html {
+                return;
             }
+            self.stack_of_open_elements.pop();
         }
+    }
 
-        let node = Rc::new(RefCell::new(self.create_element(tag, attributes)));
+    /// Closes the open `td` or `th` element.
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        while let Some(node) = self.stack_of_open_elements.pop() {
+            if matches!(
+                node.borrow().element_kind(),
+                Some(ElementKind::Td) | Some(ElementKind::Th)
+            ) {
+                break;
+            }
+        }
+        self.clear_active_formatting_elements_to_last_marker();
+        self.mode = InsertionMode::InRow;
+    }
 
-        if current.borrow().first_child().is_some() {
-            let mut last_sibling = current.borrow().first_child();
-            loop {
-                last_sibling = match last_sibling {
-                    Some(ref node) => {
-                        if node.borrow().next_sibling().is_some() {
-                            node.borrow().next_sibling()
-                        } else {
-                            break;
-                        }
-                    }
-                    None => unimplemented!("last_sibling should be Some"),
+    fn close_caption(&mut self) {
+        self.generate_implied_end_tags(None);
+        self.pop_until(ElementKind::Caption);
+        self.clear_active_formatting_elements_to_last_marker();
+        self.mode = InsertionMode::InTable;
+    }
+
+    /// Closes the open `tbody`, `thead` or `tfoot` element. Returns false if
+    /// there is none.
+    fn close_table_section(&mut self) -> bool {
+        if !TABLE_SECTIONS
+            .iter()
+            .any(|kind| self.has_element_in_table_scope(*kind))
+        {
+            return false;
+        }
+        self.clear_stack_back_to(TABLE_SECTIONS);
+        self.stack_of_open_elements.pop();
+        self.mode = InsertionMode::InTable;
+        true
+    }
+
+    /// Closes the open `tr` element. Returns false if there is none.
+    fn close_row(&mut self) -> bool {
+        if !self.has_element_in_table_scope(ElementKind::Tr) {
+            return false;
+        }
+        self.clear_stack_back_to(&[ElementKind::Tr]);
+        self.stack_of_open_elements.pop();
+        self.mode = InsertionMode::InTableBody;
+        true
+    }
+
+    /// Picks the insertion mode from the open elements, after a table or a
+    /// part of it has been closed.
+    fn reset_insertion_mode(&mut self) {
+        for (i, node) in self.stack_of_open_elements.iter().enumerate().rev() {
+            let last = i == 0;
+            // a fragment is parsed as if its context element were open
+            let node = match self.context {
+                Some(ref context) if last => context,
+                _ => node,
+            };
+            let mode = match node.borrow().element_kind() {
+                Some(ElementKind::Td) | Some(ElementKind::Th) if !last => InsertionMode::InCell,
+                Some(ElementKind::Tr) => InsertionMode::InRow,
+                Some(ElementKind::Tbody) | Some(ElementKind::Thead) | Some(ElementKind::Tfoot) => {
+                    InsertionMode::InTableBody
                 }
+                Some(ElementKind::Caption) => InsertionMode::InCaption,
+                Some(ElementKind::Colgroup) => InsertionMode::InColumnGroup,
+                Some(ElementKind::Table) => InsertionMode::InTable,
+                Some(ElementKind::Template) => *self
+                    .template_insertion_modes
+                    .last()
+                    .unwrap_or(&InsertionMode::InTemplate),
+                Some(ElementKind::Head) if !last => InsertionMode::InHead,
+                Some(ElementKind::Body) => InsertionMode::InBody,
+                // the head is always parsed first, except in a fragment
+                Some(ElementKind::Html) if self.context.is_some() => InsertionMode::BeforeHead,
+                Some(ElementKind::Html) => InsertionMode::AfterHead,
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            self.mode = mode;
+            return;
+        }
+
+        self.mode = InsertionMode::InBody;
+    }
+
+    /// Pops the elements whose end tags can be left out, such as `li` and
+    /// `p`, except for `except`.
+    fn generate_implied_end_tags(&mut self, except: Option<ElementKind>) {
+        loop {
+            let kind = match self.current_node().borrow().element_kind() {
+                Some(kind) => kind,
+                None => return,
+            };
+            if Some(kind) == except || !has_implied_end_tag(kind) {
+                return;
             }
+            self.stack_of_open_elements.pop();
+        }
+    }
 
-            last_sibling
-                .as_ref()
-                .unwrap()
-                .borrow_mut()
-                .set_next_sibling(Some(node.clone()));
-            node.borrow_mut()
-                .set_previous_sibling(Rc::downgrade(&last_sibling.unwrap()));
-        } else {
-            current.borrow_mut().set_first_child(Some(node.clone()));
+    /// Pops every element whose end tag can be left out, including the
+    /// parts of a table, as closing a `template` does.
+    fn generate_all_implied_end_tags_thoroughly(&mut self) {
+        loop {
+            let kind = match self.current_node().borrow().element_kind() {
+                Some(kind) => kind,
+                None => return,
+            };
+            let implied = has_implied_end_tag(kind)
+                || matches!(
+                    kind,
+                    ElementKind::Caption
+                        | ElementKind::Colgroup
+                        | ElementKind::Tbody
+                        | ElementKind::Td
+                        | ElementKind::Tfoot
+                        | ElementKind::Th
+                        | ElementKind::Thead
+                        | ElementKind::Tr
+                );
+            if !implied {
+                return;
+            }
+            self.stack_of_open_elements.pop();
         }
+    }
 
-        current.borrow_mut().set_last_child(Rc::downgrade(&node));
-        node.borrow_mut().set_parent(Rc::downgrade(&current));
+    /// Closes the open `template` element and the elements in it.
+    fn close_template(&mut self) {
+        self.generate_all_implied_end_tags_thoroughly();
+        self.pop_until(ElementKind::Template);
+        self.clear_active_formatting_elements_to_last_marker();
+        self.template_insertion_modes.pop();
+        self.reset_insertion_mode();
+    }
 
-        self.stack_of_open_elements.push(node);
+    /// Returns the `head` element, once it has been inserted.
+    fn head_element(&self) -> Option<Rc<RefCell<Node>>> {
+        let html = self.stack_of_open_elements.first()?;
+        let mut child = html.borrow().first_child();
+        while let Some(c) = child {
+            if c.borrow().element_kind() == Some(ElementKind::Head) {
+                return Some(c);
+            }
+            child = c.borrow().next_sibling();
+        }
+        None
     }
 
-    fn create_char(&self, c: char) -> Node {
-        let mut s = String::new();
-        s.push(c);
-        Node::new(NodeKind::Text(s))
+    fn close_p_element(&mut self) {
+        self.generate_implied_end_tags(Some(ElementKind::P));
+        self.pop_until(ElementKind::P);
     }
 
-    fn insert_char(&mut self, c: char) {
-        let current = match self.stack_of_open_elements.last() {
-            Some(n) => n.clone(),
-            None => return,
-        };
+    /// Closes the open `p` element, as block elements do.
+    fn close_p_element_in_button_scope(&mut self) {
+        if self.has_element_in_button_scope(ElementKind::P) {
+            self.close_p_element();
+        }
+    }
 
-        if let NodeKind::Text(ref mut s) = current.borrow_mut().kind {
-            s.push(c);
-            return;
-        };
+    fn remove_from_stack(&mut self, node: &Rc<RefCell<Node>>) {
+        self.stack_of_open_elements.retain(|n| !Rc::ptr_eq(n, node));
+    }
 
-        if c == ' ' || c == '\n' {
-            return;
+    fn position_in_stack(&self, node: &Rc<RefCell<Node>>) -> Option<usize> {
+        self.stack_of_open_elements
+            .iter()
+            .position(|n| Rc::ptr_eq(n, node))
+    }
+
+    /// Returns true if `node` is open and no element that is a boundary of
+    /// the scope is open inside it.
+    fn has_node_in_scope(&self, node: &Rc<RefCell<Node>>) -> bool {
+        for n in self.stack_of_open_elements.iter().rev() {
+            if Rc::ptr_eq(n, node) {
+                return true;
+            }
+            if is_scope_boundary(&n.borrow()) {
+                return false;
+            }
         }
 
-        let node = Rc::new(RefCell::new(self.create_char(c)));
+        false
+    }
 
-        if current.borrow().first_child().is_some() {
-            let mut last_sibling = current.borrow().first_child();
-            loop {
-                last_sibling = match last_sibling {
-                    Some(ref node) => {
-                        if node.borrow().next_sibling().is_some() {
-                            node.borrow().next_sibling()
-                        } else {
-                            break;
-                        }
+    fn position_in_active_formatting_elements(&self, node: &Rc<RefCell<Node>>) -> Option<usize> {
+        self.active_formatting_elements
+            .iter()
+            .position(|e| matches!(e, ActiveFormattingElement::Element(n) if Rc::ptr_eq(n, node)))
+    }
+
+    /// Returns the index of the last formatting element named `tag` after
+    /// the last marker.
+    fn formatting_element_after_last_marker(&self, tag: &str) -> Option<usize> {
+        for (i, entry) in self.active_formatting_elements.iter().enumerate().rev() {
+            match entry {
+                ActiveFormattingElement::Marker => return None,
+                ActiveFormattingElement::Element(node) => {
+                    if local_name(node).as_deref() == Some(tag) {
+                        return Some(i);
                     }
-                    None => unimplemented!("last_sibling should be Some"),
                 }
             }
+        }
 
-            last_sibling
-                .as_ref()
-                .unwrap()
-                .borrow_mut()
-                .set_next_sibling(Some(node.clone()));
-            node.borrow_mut()
-                .set_previous_sibling(Rc::downgrade(&last_sibling.unwrap()));
-        } else {
-            current.borrow_mut().set_first_child(Some(node.clone()));
+        None
+    }
+
+    /// Adds the current node to the list of active formatting elements. Only
+    /// three identical elements are kept after the last marker, so repeating
+    /// `<b>` many times doesn't make every later element reopen all of them.
+    fn push_active_formatting_element(&mut self) {
+        let node = self.current_node();
+        let mut identical = Vec::new();
+        for (i, entry) in self.active_formatting_elements.iter().enumerate().rev() {
+            match entry {
+                ActiveFormattingElement::Marker => break,
+                ActiveFormattingElement::Element(n) => {
+                    if is_same_element(&n.borrow(), &node.borrow()) {
+                        identical.push(i);
+                    }
+                }
+            }
+        }
+        if identical.len() >= 3 {
+            // the earliest of them goes
+            self.active_formatting_elements
+                .remove(identical[identical.len() - 1]);
         }
 
-        current.borrow_mut().set_last_child(Rc::downgrade(&node));
-        node.borrow_mut().set_parent(Rc::downgrade(&current));
+        self.active_formatting_elements
+            .push(ActiveFormattingElement::Element(node));
+    }
 
-        self.stack_of_open_elements.push(node);
+    fn clear_active_formatting_elements_to_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting_elements.pop() {
+            if let ActiveFormattingElement::Marker = entry {
+                return;
+            }
+        }
     }
 
-    pub fn construct_tree(&mut self) -> Rc<RefCell<Window>> {
-        let mut token = self.t.next();
+    /// Reopens the formatting elements that were closed implicitly, such as
+    /// the `b` in `<p><b>x<p>y`, so that `y` is bold too.
+    fn reconstruct_active_formatting_elements(&mut self) {
+        // find the first entry after the last one that is a marker or open
+        let mut index = self.active_formatting_elements.len();
+        while index > 0 {
+            let is_open_or_marker = match self.active_formatting_elements[index - 1] {
+                ActiveFormattingElement::Marker => true,
+                ActiveFormattingElement::Element(ref node) => {
+                    self.position_in_stack(node).is_some()
+                }
+            };
+            if is_open_or_marker {
+                break;
+            }
+            index -= 1;
+        }
 
-        while token.is_some() {
-            match self.mode {
-                InsertionMode::Initial => {
-                    if let Some(HtmlToken::Char(_)) = token {
-                        token = self.t.next();
-                        continue;
-                    }
+        for i in index..self.active_formatting_elements.len() {
+            let node = match self.active_formatting_elements[i] {
+                ActiveFormattingElement::Element(ref node) => node.clone(),
+                ActiveFormattingElement::Marker => continue,
+            };
+            let (tag, attributes) = match node.borrow().kind {
+                NodeKind::Element(ref e) => (e.local_name(), e.attributes()),
+                _ => continue,
+            };
+            self.insert_element(&tag, attributes);
+            self.active_formatting_elements[i] =
+                ActiveFormattingElement::Element(self.current_node());
+        }
+    }
 
-                    self.mode = InsertionMode::BeforeHtml;
-                    continue;
+    /// The adoption agency algorithm, run for the end tag of a formatting
+    /// element. Misnested markup such as `<b><p>x</b>y` is fixed by closing
+    /// the formatting element and moving what was opened inside it into a
+    /// new copy of it. Returns false if the end tag is to be handled like
+    /// any other end tag.
+    fn run_adoption_agency(&mut self, subject: &str) -> bool {
+        let current = self.current_node();
+        if local_name(&current).as_deref() == Some(subject)
+            && self
+                .position_in_active_formatting_elements(&current)
+                .is_none()
+        {
+            self.stack_of_open_elements.pop();
+            return true;
+        }
+
+        for _ in 0..8 {
+            let formatting_index = match self.formatting_element_after_last_marker(subject) {
+                Some(i) => i,
+                None => return false,
+            };
+            let formatting_element = match self.active_formatting_elements[formatting_index] {
+                ActiveFormattingElement::Element(ref node) => node.clone(),
+                ActiveFormattingElement::Marker => return false,
+            };
+            let stack_index = match self.position_in_stack(&formatting_element) {
+                Some(i) if i > 0 => i,
+                _ => {
+                    self.active_formatting_elements.remove(formatting_index);
+                    return true;
                 }
-                InsertionMode::BeforeHtml => {
-                    match token {
-                        Some(HtmlToken::Char(c)) => {
-                            if c == ' ' || c == '\n' {
-                                token = self.t.next();
-                                continue;
-                            }
-                        }
-                        Some(HtmlToken::StartTag {
-                            ref tag,
-                            self_closing: _,
-                            ref attributes,
-                        }) => {
-                            if tag == "html" {
-                                self.insert_element(tag, attributes.to_vec());
-                                self.mode = InsertionMode::BeforeHead;
-                                token = self.t.next();
-                                continue;
-                            }
-                        }
-                        Some(HtmlToken::Eof) => {
-                            return self.window.clone();
-                        }
-                        _ => {}
-                    }
-                    self.insert_element("html", Vec::new());
-                    self.mode = InsertionMode::BeforeHead;
-                    continue;
+            };
+            if !self.has_node_in_scope(&formatting_element) {
+                return true;
+            }
+
+            // the furthest block is the first special element opened inside
+            // the formatting element
+            let furthest_block_index = (stack_index + 1..self.stack_of_open_elements.len())
+                .find(|i| is_special_node(&self.stack_of_open_elements[*i].borrow()));
+            let furthest_block_index = match furthest_block_index {
+                Some(i) => i,
+                None => {
+                    self.stack_of_open_elements.truncate(stack_index);
+                    self.active_formatting_elements.remove(formatting_index);
+                    return true;
                 }
-                InsertionMode::BeforeHead => {
-                    match token {
-                        Some(HtmlToken::Char(c)) => {
-                            if c == ' ' || c == '\n' {
-                                token = self.t.next();
-                                continue;
-                            }
-                        }
-                        Some(HtmlToken::StartTag {
-                            ref tag,
-                            self_closing: _,
-                            ref attributes,
-                        }) => {
-                            if tag == "head" {
-                                self.insert_element(tag, attributes.to_vec());
-                                self.mode = InsertionMode::InHead;
-                                token = self.t.next();
-                                continue;
-                            }
-                        }
-                        Some(HtmlToken::Eof) | None => return self.window.clone(),
-                        _ => {}
-                    }
-                    self.insert_element("head", Vec::new());
-                    self.mode = InsertionMode::InHead;
-                    continue;
+            };
+            let furthest_block = self.stack_of_open_elements[furthest_block_index].clone();
+            let common_ancestor = self.stack_of_open_elements[stack_index - 1].clone();
+
+            // where the new formatting element goes in the list
+            let mut bookmark = formatting_index;
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block.clone();
+            let mut inner_loop_counter = 0;
+            loop {
+                inner_loop_counter += 1;
+                node_index -= 1;
+                let node = self.stack_of_open_elements[node_index].clone();
+                if Rc::ptr_eq(&node, &formatting_element) {
+                    break;
                 }
-                InsertionMode::InHead => {
-                    match token {
-                        Some(HtmlToken::Char(c)) => {
-                            if c == ' ' || c == '\n' {
-                                self.insert_char(c);
-                                token = self.t.next();
-                                continue;
-                            }
-                        }
-                        Some(HtmlToken::StartTag {
-                            ref tag,
-                            self_closing: _,
-                            ref attributes,
-                        }) => {
-                            if tag == "style" {
-                                self.insert_element(tag, attributes.to_vec());
-                                self.original_intersection_mode = self.mode;
-                                self.mode = InsertionMode::Text;
-                                token = self.t.next();
-                                continue;
-                            }
 
-                            if tag == "body" {
-                                self.pop_until(ElementKind::Head);
-                                self.mode = InsertionMode::AfterHead;
-                                continue;
-                            }
-                        }
-                        Some(HtmlToken::EndTag { ref tag }) => {
-                            if tag == "head" {
-                                self.mode = InsertionMode::AfterHead;
-                                token = self.t.next();
-                                self.pop_until(ElementKind::Head);
-                                continue;
-                            }
+                let mut list_index = self.position_in_active_formatting_elements(&node);
+                if inner_loop_counter > 3 {
+                    if let Some(i) = list_index {
+                        self.active_formatting_elements.remove(i);
+                        if i < bookmark {
+                            bookmark -= 1;
                         }
-                        Some(HtmlToken::Eof) | None => return self.window.clone(),
+                        list_index = None;
                     }
-
-                    token = self.t.next();
-                    continue;
                 }
-                InsertionMode::AfterHead => {
-                    match token {
-                        Some(HtmlToken::Char(c)) => {
-                            if c == ' ' || c == '\n' {
-                                self.insert_char(c);
-                                token = self.t.next();
-                                continue;
-                            }
-                        }
-                        Some(HtmlToken::StartTag {
-                            ref tag,
-                            self_closing: _,
-                            ref attributes,
-                        }) => {
-                            if tag == "body" {
-                                self.insert_element("body", attributes.to_vec());
-                                token = self.t.next();
-                                self.mode = InsertionMode::InBody;
-                                continue;
-                            }
-                        }
-                        Some(HtmlToken::Eof) => return self.window.clone(),
-                        _ => {}
+                let list_index = match list_index {
+                    Some(i) => i,
+                    None => {
+                        self.stack_of_open_elements.remove(node_index);
+                        continue;
+                    }
+                };
+
+                let new_node = clone_element(&node);
+                self.active_formatting_elements[list_index] =
+                    ActiveFormattingElement::Element(new_node.clone());
+                self.stack_of_open_elements[node_index] = new_node.clone();
+                if Rc::ptr_eq(&last_node, &furthest_block) {
+                    bookmark = list_index + 1;
+                }
+                self.append_child(&new_node, last_node);
+                last_node = new_node;
+            }
+
+            self.insert_node(&common_ancestor, last_node);
+
+            // the children of the furthest block move into a new copy of the
+            // formatting element
+            let new_element = clone_element(&formatting_element);
+            loop {
+                let child = furthest_block.borrow().first_child();
+                match child {
+                    Some(child) => self.append_child(&new_element, child),
+                    None => break,
+                }
+            }
+            self.append_child(&furthest_block, new_element.clone());
+
+            if let Some(i) = self.position_in_active_formatting_elements(&formatting_element) {
+                self.active_formatting_elements.remove(i);
+                if i < bookmark {
+                    bookmark -= 1;
+                }
+            }
+            let bookmark = bookmark.min(self.active_formatting_elements.len());
+            self.active_formatting_elements.insert(
+                bookmark,
+                ActiveFormattingElement::Element(new_element.clone()),
+            );
+
+            self.remove_from_stack(&formatting_element);
+            if let Some(i) = self.position_in_stack(&furthest_block) {
+                self.stack_of_open_elements.insert(i + 1, new_element);
+            }
+        }
+
+        true
+    }
+
+    /// Closes the innermost open element named `tag`, unless an element that
+    /// can't be closed implicitly, such as a `div`, is open inside it.
+    fn close_element_by_end_tag(&mut self, tag: &str) {
+        for i in (0..self.stack_of_open_elements.len()).rev() {
+            let node = self.stack_of_open_elements[i].clone();
+            let (name, kind) = match node.borrow().kind {
+                NodeKind::Element(ref e) if e.namespace() == Namespace::Html => {
+                    (e.local_name(), e.kind())
+                }
+                NodeKind::Element(ref e) if is_special_foreign_element(e) => return,
+                _ => continue,
+            };
+
+            if name == tag {
+                self.generate_implied_end_tags(Some(kind));
+                self.stack_of_open_elements.truncate(i);
+                return;
+            }
+            if is_special(kind) {
+                return;
+            }
+        }
+    }
+
+    /// Adds the attributes of a repeated `<html>` or `<body>` start tag to
+    /// the open element at `index`, if it doesn't have them yet.
+    fn add_missing_attributes(&mut self, index: usize, attributes: &[Attribute]) {
+        let node = match self.stack_of_open_elements.get(index) {
+            Some(node) => node.clone(),
+            None => return,
+        };
+        for a in attributes {
+            let has_attribute = match node.borrow().kind {
+                NodeKind::Element(ref element) => element.has_attribute(&a.name()),
+                _ => true,
+            };
+            if !has_attribute {
+                node::set_attribute(&node, &a.name(), &a.value());
+            }
+        }
+    }
+
+    fn create_element(&self, namespace: Namespace, tag: &str, attributes: Vec<Attribute>) -> Node {
+        Node::new(NodeKind::Element(Element::new_in_namespace(
+            namespace, tag, attributes,
+        )))
+    }
+
+    /// Returns the current node, or the document when the stack is empty.
+    fn current_node(&self) -> Rc<RefCell<Node>> {
+        match self.stack_of_open_elements.last() {
+            Some(n) => n.clone(),
+            None => self.window.borrow().document(),
+        }
+    }
+
+    /// Appends `node` as the last child of `parent`, moving it from where it
+    /// is.
+    fn append_child(&self, parent: &Rc<RefCell<Node>>, node: Rc<RefCell<Node>>) {
+        let result = node::append_child(parent, node);
+        debug_assert!(result.is_ok(), "{:?}", result.err());
+    }
+
+    /// Returns where a node inserted into `target` goes: the parent and the
+    /// child it goes before, if it doesn't go last. While foster parenting,
+    /// content that would go into a table goes right before the table
+    /// instead. Content that goes into a `template` goes into its contents.
+    fn appropriate_place(
+        &self,
+        target: &Rc<RefCell<Node>>,
+    ) -> (Rc<RefCell<Node>>, Option<Rc<RefCell<Node>>>) {
+        let (parent, next) = self.foster_parent_place(target);
+        match template_content(&parent) {
+            Some(content) => (content, None),
+            None => (parent, next),
+        }
+    }
+
+    fn foster_parent_place(
+        &self,
+        target: &Rc<RefCell<Node>>,
+    ) -> (Rc<RefCell<Node>>, Option<Rc<RefCell<Node>>>) {
+        let in_table = target.borrow().element_kind().is_some_and(|kind| {
+            matches!(
+                kind,
+                ElementKind::Table
+                    | ElementKind::Tbody
+                    | ElementKind::Tfoot
+                    | ElementKind::Thead
+                    | ElementKind::Tr
+            )
+        });
+        if !self.foster_parenting || !in_table {
+            return (target.clone(), None);
+        }
+
+        let last_index = |kind| {
+            self.stack_of_open_elements
+                .iter()
+                .rposition(|n| n.borrow().element_kind() == Some(kind))
+        };
+        let template_index = last_index(ElementKind::Template);
+        let table_index = last_index(ElementKind::Table);
+        if let Some(i) = template_index {
+            let above_table = match table_index {
+                Some(table) => i > table,
+                None => true,
+            };
+            if above_table {
+                return (self.stack_of_open_elements[i].clone(), None);
+            }
+        }
+
+        let table_index = match table_index {
+            Some(i) if i > 0 => i,
+            _ => return (self.stack_of_open_elements[0].clone(), None),
+        };
+        let table = self.stack_of_open_elements[table_index].clone();
+        let parent = table.borrow().parent().upgrade();
+        match parent {
+            Some(parent) => (parent, Some(table)),
+            None => (self.stack_of_open_elements[table_index - 1].clone(), None),
+        }
+    }
+
+    /// Inserts `node` at the appropriate place for inserting into `target`.
+    fn insert_node(&mut self, target: &Rc<RefCell<Node>>, node: Rc<RefCell<Node>>) {
+        match self.appropriate_place(target) {
+            (parent, Some(next)) => self.insert_before(&parent, node, &next),
+            (parent, None) => self.append_child(&parent, node),
+        }
+    }
+
+    /// Inserts `node` into `parent` right before its child `reference`.
+    fn insert_before(
+        &self,
+        parent: &Rc<RefCell<Node>>,
+        node: Rc<RefCell<Node>>,
+        reference: &Rc<RefCell<Node>>,
+    ) {
+        let result = node::insert_before(parent, node, Some(reference));
+        debug_assert!(result.is_ok(), "{:?}", result.err());
+    }
+
+    fn insert_element(&mut self, tag: &str, attributes: Vec<Attribute>) {
+        self.insert_element_in_namespace(Namespace::Html, tag, attributes);
+    }
+
+    fn insert_element_in_namespace(
+        &mut self,
+        namespace: Namespace,
+        tag: &str,
+        attributes: Vec<Attribute>,
+    ) {
+        let current = self.current_node();
+
+        let node = Rc::new(RefCell::new(
+            self.create_element(namespace, tag, attributes),
+        ));
+        self.insert_node(&current, node.clone());
+
+        self.stack_of_open_elements.push(node);
+    }
+
+    /// Inserts an SVG or MathML element, with the capital letters of its
+    /// name and attributes put back. A self-closing one isn't left open.
+    fn insert_foreign_element(
+        &mut self,
+        namespace: Namespace,
+        tag: &str,
+        attributes: Vec<Attribute>,
+        self_closing: bool,
+    ) {
+        let tag = adjust_tag_name(namespace, tag);
+        let attributes = adjust_attributes(namespace, attributes);
+        self.insert_element_in_namespace(namespace, &tag, attributes);
+        if self_closing {
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    /// Inserts an element that can't have content, so it isn't left open.
+    fn insert_void_element(&mut self, tag: &str, attributes: Vec<Attribute>) {
+        self.insert_element(tag, attributes);
+        self.stack_of_open_elements.pop();
+    }
+
+    /// The generic raw text and RCDATA element parsing algorithms: inserts
+    /// the element and reads what follows as its text, up to its end tag.
+    fn insert_text_element(
+        &mut self,
+        tag: &str,
+        attributes: Vec<Attribute>,
+        state: TokenizerState,
+    ) {
+        self.insert_element(tag, attributes);
+        self.t.switch_to(state);
+        self.original_intersection_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    /// Inserts a comment as the last child of `parent`, or of the current
+    /// node if `parent` is `None`.
+    fn insert_comment(&mut self, data: &str, parent: Option<Rc<RefCell<Node>>>) {
+        let node = Rc::new(RefCell::new(Node::new(NodeKind::Comment(data.to_string()))));
+        match parent {
+            Some(p) => self.append_child(&p, node),
+            None => {
+                let current = self.current_node();
+                self.insert_node(&current, node);
+            }
+        }
+    }
+
+    fn insert_doctype(
+        &mut self,
+        name: &Option<String>,
+        public_id: &Option<String>,
+        system_id: &Option<String>,
+    ) {
+        let node = Rc::new(RefCell::new(Node::new(NodeKind::DocumentType {
+            name: name.clone().unwrap_or_default(),
+            public_id: public_id.clone().unwrap_or_default(),
+            system_id: system_id.clone().unwrap_or_default(),
+        })));
+        let document = self.window.borrow().document();
+        self.append_child(&document, node);
+    }
+
+    /// Associates the current node with the form element pointer, unless it
+    /// names its form owner with a `form` attribute.
+    fn associate_with_form(&mut self) {
+        let form = match self.form_element_pointer {
+            Some(ref form) => Rc::downgrade(form),
+            None => return,
+        };
+
+        let current = self.current_node();
+        let mut current = current.borrow_mut();
+        if let NodeKind::Element(ref mut element) = current.kind {
+            if !element.has_attribute("form") {
+                element.set_form_owner(form);
+            }
+        }
+    }
+
+    fn create_char(&self, c: char) -> Node {
+        let mut s = String::new();
+        s.push(c);
+        Node::new(NodeKind::Text(s))
+    }
+
+    /// Inserts a character into the current node. It is added to the text
+    /// node right before where it goes, if there is one, so that adjacent
+    /// text makes up a single node.
+    fn insert_char(&mut self, c: char) {
+        let (parent, next) = self.appropriate_place(&self.current_node());
+        if let NodeKind::Document(_) = parent.borrow().kind {
+            return;
+        }
+
+        let previous = match next {
+            Some(ref next) => next.borrow().previous_sibling().upgrade(),
+            None => parent.borrow().last_child().upgrade(),
+        };
+        if let Some(previous) = previous {
+            if let NodeKind::Text(ref mut s) = previous.borrow_mut().kind {
+                s.push(c);
+                return;
+            }
+        }
+
+        let node = Rc::new(RefCell::new(self.create_char(c)));
+        match next {
+            Some(next) => self.insert_before(&parent, node, &next),
+            None => self.append_child(&parent, node),
+        }
+    }
+
+    /// Processes every token the input fed so far makes up. Tokens are taken
+    /// one at a time because a token can change how the next one is
+    /// tokenized.
+    fn process_tokens(&mut self) {
+        while let Some(token) = self.t.next() {
+            self.process_token(token);
+        }
+    }
+
+    /// Runs the tree construction stage for one token. `HtmlToken::Eof` ends
+    /// the document.
+    fn process_token(&mut self, token: HtmlToken) {
+        if self.skip_next_newline {
+            self.skip_next_newline = false;
+            if token == HtmlToken::Char('\n') {
+                return;
+            }
+        }
+
+        if self.is_in_foreign_content(&token) {
+            self.process_foreign_content(token);
+        } else {
+            let mode = self.mode;
+            self.process_token_using(mode, token);
+        }
+        self.update_cdata_allowed();
+    }
+
+    /// Returns the current node, or the context element while parsing a
+    /// fragment and only the root is open.
+    fn adjusted_current_node(&self) -> Option<Rc<RefCell<Node>>> {
+        match self.context {
+            Some(ref context) if self.stack_of_open_elements.len() == 1 => Some(context.clone()),
+            _ => self.stack_of_open_elements.last().cloned(),
+        }
+    }
+
+    /// Returns true if `token` is processed with the rules for SVG and MathML
+    /// content rather than with those of the insertion mode.
+    fn is_in_foreign_content(&self, token: &HtmlToken) -> bool {
+        let node = match self.adjusted_current_node() {
+            Some(node) => node,
+            None => return false,
+        };
+        let node = node.borrow();
+        let e = match node.kind {
+            NodeKind::Element(ref e) if e.namespace() != Namespace::Html => e,
+            _ => return false,
+        };
+
+        match token {
+            HtmlToken::StartTag { ref tag, .. } => {
+                let html_start_tag =
+                    is_mathml_text_integration_point(e) && tag != "mglyph" && tag != "malignmark"
+                        || e.namespace() == Namespace::MathMl
+                            && e.local_name() == "annotation-xml"
+                            && tag == "svg"
+                        || is_html_integration_point(e);
+                !html_start_tag
+            }
+            HtmlToken::Char(_) => {
+                !is_mathml_text_integration_point(e) && !is_html_integration_point(e)
+            }
+            HtmlToken::Eof => false,
+            _ => true,
+        }
+    }
+
+    fn update_cdata_allowed(&mut self) {
+        let foreign = self
+            .adjusted_current_node()
+            .is_some_and(|node| match node.borrow().kind {
+                NodeKind::Element(ref e) => e.namespace() != Namespace::Html,
+                _ => false,
+            });
+        self.t.set_cdata_allowed(foreign);
+    }
+
+    /// The rules for tokens in SVG and MathML content. HTML elements that
+    /// can't be in it close the foreign elements they are in.
+    fn process_foreign_content(&mut self, token: HtmlToken) {
+        match token {
+            HtmlToken::Char(c) => {
+                let c = if c == '\0' { '\u{FFFD}' } else { c };
+                self.insert_char(c);
+            }
+            HtmlToken::Comment(ref data) => self.insert_comment(data, None),
+            HtmlToken::Doctype { .. } => {
+                // a DOCTYPE is only allowed at the start
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                self_closing,
+                ref attributes,
+            } => {
+                if is_breakout_tag(tag, attributes) {
+                    self.break_out_of_foreign_content(token);
+                    return;
+                }
+
+                let namespace = match self.adjusted_current_node() {
+                    Some(node) => match node.borrow().kind {
+                        NodeKind::Element(ref e) => e.namespace(),
+                        _ => Namespace::Html,
+                    },
+                    None => Namespace::Html,
+                };
+                self.insert_foreign_element(namespace, tag, attributes.to_vec(), self_closing);
+            }
+            HtmlToken::EndTag { ref tag } if tag == "br" || tag == "p" => {
+                self.break_out_of_foreign_content(token);
+            }
+            HtmlToken::EndTag { ref tag } => {
+                // closes the innermost element of that name, unless an HTML
+                // element is open inside it
+                let mut i = self.stack_of_open_elements.len() - 1;
+                loop {
+                    if i == 0 {
+                        return;
+                    }
+                    let node = self.stack_of_open_elements[i].clone();
+                    if local_name(&node).is_some_and(|name| name.eq_ignore_ascii_case(tag)) {
+                        self.stack_of_open_elements.truncate(i);
+                        return;
+                    }
+                    i -= 1;
+                    let is_html = match self.stack_of_open_elements[i].borrow().kind {
+                        NodeKind::Element(ref e) => e.namespace() == Namespace::Html,
+                        _ => false,
+                    };
+                    if is_html {
+                        let mode = self.mode;
+                        self.process_token_using(mode, token);
+                        return;
+                    }
+                }
+            }
+            HtmlToken::Eof => unreachable!("the end is processed as in HTML content"),
+        }
+    }
+
+    /// Pops the foreign elements until the current node is an HTML element
+    /// or one that can have HTML in it, then processes `token` as in HTML
+    /// content.
+    fn break_out_of_foreign_content(&mut self, token: HtmlToken) {
+        loop {
+            let current = self.current_node();
+            let done = match current.borrow().kind {
+                NodeKind::Element(ref e) => {
+                    e.namespace() == Namespace::Html
+                        || is_mathml_text_integration_point(e)
+                        || is_html_integration_point(e)
+                }
+                _ => true,
+            };
+            if done {
+                break;
+            }
+            self.stack_of_open_elements.pop();
+        }
+
+        let mode = self.mode;
+        self.process_token_using(mode, token);
+    }
+
+    /// Processes a token with the rules of `mode`, which the rules of some
+    /// modes borrow. A token that is reprocessed goes through the current
+    /// insertion mode.
+    fn process_token_using(&mut self, mode: InsertionMode, token: HtmlToken) {
+        let mut rules = Some(mode);
+        loop {
+            match rules.take().unwrap_or(self.mode) {
+                InsertionMode::Initial => {
+                    match token {
+                        HtmlToken::Char(c) if is_whitespace(c) => {
+                            return;
+                        }
+                        HtmlToken::Comment(ref data) => {
+                            let document = self.window.borrow().document();
+                            self.insert_comment(data, Some(document));
+                            return;
+                        }
+                        HtmlToken::Doctype {
+                            ref name,
+                            ref public_id,
+                            ref system_id,
+                            force_quirks,
+                        } => {
+                            self.insert_doctype(name, public_id, system_id);
+                            self.set_quirks_mode(quirks_mode(
+                                name.as_deref(),
+                                public_id.as_deref(),
+                                system_id.as_deref(),
+                                force_quirks,
+                            ));
+                            self.mode = InsertionMode::BeforeHtml;
+                            return;
+                        }
+                        _ => {}
+                    }
+
+                    // a document without a DOCTYPE is an old one
+                    self.set_quirks_mode(QuirksMode::Quirks);
+                    self.mode = InsertionMode::BeforeHtml;
+                    continue;
+                }
+                InsertionMode::BeforeHtml => {
+                    match token {
+                        HtmlToken::Char(c) => {
+                            if is_whitespace(c) {
+                                return;
+                            }
+                        }
+                        HtmlToken::StartTag {
+                            ref tag,
+                            self_closing: _,
+                            ref attributes,
+                        } => {
+                            if tag == "html" {
+                                self.insert_element(tag, attributes.to_vec());
+                                self.mode = InsertionMode::BeforeHead;
+                                return;
+                            }
+                        }
+                        HtmlToken::Comment(ref data) => {
+                            let document = self.window.borrow().document();
+                            self.insert_comment(data, Some(document));
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        _ => {}
+                    }
+                    self.insert_element("html", Vec::new());
+                    self.mode = InsertionMode::BeforeHead;
+                    continue;
+                }
+                InsertionMode::BeforeHead => {
+                    match token {
+                        HtmlToken::Char(c) => {
+                            if is_whitespace(c) {
+                                return;
+                            }
+                        }
+                        HtmlToken::StartTag {
+                            ref tag,
+                            self_closing: _,
+                            ref attributes,
+                        } => {
+                            if tag == "head" {
+                                self.insert_element(tag, attributes.to_vec());
+                                self.mode = InsertionMode::InHead;
+                                return;
+                            }
+                        }
+                        HtmlToken::Comment(ref data) => {
+                            self.insert_comment(data, None);
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        _ => {}
+                    }
+                    self.insert_element("head", Vec::new());
+                    self.mode = InsertionMode::InHead;
+                    continue;
+                }
+                InsertionMode::InHead => {
+                    match token {
+                        HtmlToken::Char(c) => {
+                            if is_whitespace(c) {
+                                self.insert_char(c);
+                                return;
+                            }
+                        }
+                        HtmlToken::StartTag {
+                            ref tag,
+                            self_closing: _,
+                            ref attributes,
+                        } => {
+                            if is_void_head_element(tag) {
+                                self.insert_void_element(tag, attributes.to_vec());
+                                return;
+                            }
+
+                            if let Some(state) = head_text_content(tag) {
+                                self.insert_text_element(tag, attributes.to_vec(), state);
+                                return;
+                            }
+
+                            if tag == "html" {
+                                self.add_missing_attributes(0, attributes);
+                                return;
+                            }
+
+                            if tag == "head" {
+                                return;
+                            }
+
+                            if tag == "template" {
+                                self.insert_element(tag, attributes.to_vec());
+                                self.active_formatting_elements
+                                    .push(ActiveFormattingElement::Marker);
+                                self.mode = InsertionMode::InTemplate;
+                                self.template_insertion_modes
+                                    .push(InsertionMode::InTemplate);
+                                return;
+                            }
+                        }
+                        HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                            "head" => {
+                                self.mode = InsertionMode::AfterHead;
+                                self.pop_until(ElementKind::Head);
+                                return;
+                            }
+                            "template" => {
+                                if self.contain_in_stack(ElementKind::Template) {
+                                    self.close_template();
+                                }
+                                return;
+                            }
+                            // handled like anything else below
+                            "body" | "html" | "br" => {}
+                            _ => return,
+                        },
+                        HtmlToken::Comment(ref data) => {
+                            self.insert_comment(data, None);
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        HtmlToken::Eof => {}
+                    }
+
+                    // anything else closes the head
+                    self.pop_until(ElementKind::Head);
+                    self.mode = InsertionMode::AfterHead;
+                    continue;
+                }
+                InsertionMode::AfterHead => {
+                    match token {
+                        HtmlToken::Char(c) => {
+                            if is_whitespace(c) {
+                                self.insert_char(c);
+                                return;
+                            }
+                        }
+                        HtmlToken::StartTag {
+                            ref tag,
+                            self_closing: _,
+                            ref attributes,
+                        } => {
+                            if tag == "body" {
+                                self.insert_element("body", attributes.to_vec());
+                                self.mode = InsertionMode::InBody;
+                                return;
+                            }
+
+                            if tag == "template" {
+                                // the template goes into the head, as if the
+                                // head were still open
+                                if let Some(head) = self.head_element() {
+                                    self.stack_of_open_elements.push(head.clone());
+                                    self.process_token_using(InsertionMode::InHead, token);
+                                    self.remove_from_stack(&head);
+                                    return;
+                                }
+                            }
+                        }
+                        HtmlToken::Comment(ref data) => {
+                            self.insert_comment(data, None);
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        HtmlToken::EndTag { ref tag } => {
+                            if tag == "template" {
+                                self.process_token_using(InsertionMode::InHead, token);
+                                return;
+                            }
+                            if tag != "body" && tag != "html" && tag != "br" {
+                                return;
+                            }
+                        }
+                        HtmlToken::Eof => {}
                     }
 
                     self.insert_element("body", Vec::new());
@@ -340,96 +1381,971 @@ impl HtmlParser {
                 }
                 InsertionMode::InBody => {
                     match token {
-                        Some(HtmlToken::StartTag {
+                        HtmlToken::StartTag {
                             ref tag,
-                            self_closing: _,
+                            self_closing,
                             ref attributes,
-                        }) => match tag.as_str() {
-                            "p" | "a" | "h1" | "h2" => {
+                        } => match tag.as_str() {
+                            "html" => {
+                                self.add_missing_attributes(0, attributes);
+                                return;
+                            }
+                            "body" => {
+                                let has_body =
+                                    self.stack_of_open_elements.get(1).is_some_and(|n| {
+                                        n.borrow().element_kind() == Some(ElementKind::Body)
+                                    });
+                                if has_body {
+                                    self.add_missing_attributes(1, attributes);
+                                }
+                                return;
+                            }
+                            "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                                // processed as in the head
+                                self.insert_void_element(tag, attributes.to_vec());
+                                return;
+                            }
+                            "title" | "style" | "script" | "noframes" => {
+                                // processed as in the head
+                                let state =
+                                    head_text_content(tag).unwrap_or(TokenizerState::Rawtext);
+                                self.insert_text_element(tag, attributes.to_vec(), state);
+                                return;
+                            }
+                            "template" => {
+                                self.process_token_using(InsertionMode::InHead, token);
+                                return;
+                            }
+                            "address" | "article" | "aside" | "blockquote" | "center"
+                            | "details" | "dialog" | "dir" | "div" | "dl" | "fieldset"
+                            | "figcaption" | "figure" | "footer" | "header" | "hgroup" | "main"
+                            | "menu" | "nav" | "ol" | "p" | "search" | "section" | "summary"
+                            | "ul" => {
+                                self.close_p_element_in_button_scope();
                                 self.insert_element(tag, attributes.to_vec());
-                                token = self.t.next();
-                                continue;
+                                return;
+                            }
+                            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                                self.close_p_element_in_button_scope();
+                                // headings don't nest
+                                if self
+                                    .current_node()
+                                    .borrow()
+                                    .element_kind()
+                                    .is_some_and(is_heading)
+                                {
+                                    self.stack_of_open_elements.pop();
+                                }
+                                self.insert_element(tag, attributes.to_vec());
+                                return;
+                            }
+                            "pre" | "listing" => {
+                                self.close_p_element_in_button_scope();
+                                self.insert_element(tag, attributes.to_vec());
+                                // a newline right after the start tag is not
+                                // part of the content
+                                self.skip_next_newline = true;
+                                return;
+                            }
+                            "form" => {
+                                // a form in a template doesn't become the form
+                                // later fields belong to
+                                let in_template = self.contain_in_stack(ElementKind::Template);
+                                if self.form_element_pointer.is_some() && !in_template {
+                                    return;
+                                }
+                                self.close_p_element_in_button_scope();
+                                self.insert_element(tag, attributes.to_vec());
+                                if !in_template {
+                                    self.form_element_pointer = Some(self.current_node());
+                                }
+                                return;
+                            }
+                            "li" | "dd" | "dt" => {
+                                // a new item closes the open one, unless it is
+                                // in a nested block
+                                let closes: &[ElementKind] = if tag == "li" {
+                                    &[ElementKind::Li]
+                                } else {
+                                    &[ElementKind::Dd, ElementKind::Dt]
+                                };
+                                for node in self.stack_of_open_elements.clone().iter().rev() {
+                                    let kind = match node.borrow().element_kind() {
+                                        Some(kind) => kind,
+                                        None => continue,
+                                    };
+                                    if closes.contains(&kind) {
+                                        self.generate_implied_end_tags(Some(kind));
+                                        self.pop_until(kind);
+                                        break;
+                                    }
+                                    if is_special_node(&node.borrow())
+                                        && !matches!(
+                                            kind,
+                                            ElementKind::Address
+                                                | ElementKind::Div
+                                                | ElementKind::P
+                                        )
+                                    {
+                                        break;
+                                    }
+                                }
+                                self.close_p_element_in_button_scope();
+                                self.insert_element(tag, attributes.to_vec());
+                                return;
+                            }
+                            "table" => {
+                                // old browsers put a table inside a paragraph
+                                if self.quirks_mode() != QuirksMode::Quirks {
+                                    self.close_p_element_in_button_scope();
+                                }
+                                self.insert_element(tag, attributes.to_vec());
+                                self.mode = InsertionMode::InTable;
+                                return;
+                            }
+                            "plaintext" => {
+                                // there is no end tag, the rest of the input is
+                                // its text
+                                self.close_p_element_in_button_scope();
+                                self.insert_element(tag, attributes.to_vec());
+                                self.t.switch_to(TokenizerState::Plaintext);
+                                return;
                             }
+                            "button" => {
+                                if self.has_element_in_scope(ElementKind::Button) {
+                                    self.generate_implied_end_tags(None);
+                                    self.pop_until(ElementKind::Button);
+                                }
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_element(tag, attributes.to_vec());
+                                self.associate_with_form();
+                                return;
+                            }
+                            "area" | "br" | "embed" | "img" | "keygen" | "wbr" | "param"
+                            | "source" | "track" => {
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_void_element(tag, attributes.to_vec());
+                                return;
+                            }
+                            "input" => {
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_element(tag, attributes.to_vec());
+                                self.associate_with_form();
+                                self.stack_of_open_elements.pop();
+                                return;
+                            }
+                            "hr" => {
+                                self.close_p_element_in_button_scope();
+                                self.insert_void_element(tag, attributes.to_vec());
+                                return;
+                            }
+                            "image" => {
+                                // an old name of img
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_void_element("img", attributes.to_vec());
+                                return;
+                            }
+                            "textarea" => {
+                                self.insert_text_element(
+                                    tag,
+                                    attributes.to_vec(),
+                                    TokenizerState::Rcdata,
+                                );
+                                self.associate_with_form();
+                                // a newline right after the start tag is not
+                                // part of the value
+                                self.skip_next_newline = true;
+                                return;
+                            }
+                            "xmp" => {
+                                self.close_p_element_in_button_scope();
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_text_element(
+                                    tag,
+                                    attributes.to_vec(),
+                                    TokenizerState::Rawtext,
+                                );
+                                return;
+                            }
+                            "iframe" | "noembed" => {
+                                self.insert_text_element(
+                                    tag,
+                                    attributes.to_vec(),
+                                    TokenizerState::Rawtext,
+                                );
+                                return;
+                            }
+                            "select" => {
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_element(tag, attributes.to_vec());
+                                self.associate_with_form();
+                                return;
+                            }
+                            "optgroup" | "option" => {
+                                if self.current_node().borrow().element_kind()
+                                    == Some(ElementKind::Option)
+                                {
+                                    self.stack_of_open_elements.pop();
+                                }
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_element(tag, attributes.to_vec());
+                                return;
+                            }
+                            "a" => {
+                                // an `a` can't be inside another one
+                                if let Some(i) = self.formatting_element_after_last_marker(tag) {
+                                    let a = self.active_formatting_elements[i].clone();
+                                    self.run_adoption_agency(tag);
+                                    if let ActiveFormattingElement::Element(ref a) = a {
+                                        if let Some(i) =
+                                            self.position_in_active_formatting_elements(a)
+                                        {
+                                            self.active_formatting_elements.remove(i);
+                                        }
+                                        self.remove_from_stack(a);
+                                    }
+                                }
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_element(tag, attributes.to_vec());
+                                self.push_active_formatting_element();
+                                return;
+                            }
+                            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small"
+                            | "strike" | "strong" | "tt" | "u" => {
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_element(tag, attributes.to_vec());
+                                self.push_active_formatting_element();
+                                return;
+                            }
+                            "nobr" => {
+                                self.reconstruct_active_formatting_elements();
+                                if self.has_element_in_scope(ElementKind::Nobr) {
+                                    self.run_adoption_agency(tag);
+                                    self.reconstruct_active_formatting_elements();
+                                }
+                                self.insert_element(tag, attributes.to_vec());
+                                self.push_active_formatting_element();
+                                return;
+                            }
+                            "applet" | "marquee" | "object" => {
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_element(tag, attributes.to_vec());
+                                self.active_formatting_elements
+                                    .push(ActiveFormattingElement::Marker);
+                                return;
+                            }
+                            "math" | "svg" => {
+                                self.reconstruct_active_formatting_elements();
+                                let namespace = if tag == "math" {
+                                    Namespace::MathMl
+                                } else {
+                                    Namespace::Svg
+                                };
+                                self.insert_foreign_element(
+                                    namespace,
+                                    tag,
+                                    attributes.to_vec(),
+                                    self_closing,
+                                );
+                                return;
+                            }
+                            "rb" | "rtc" | "rp" | "rt" => {
+                                if self.has_element_in_scope(ElementKind::Ruby) {
+                                    let except = if tag == "rp" || tag == "rt" {
+                                        Some(ElementKind::Rtc)
+                                    } else {
+                                        None
+                                    };
+                                    self.generate_implied_end_tags(except);
+                                }
+                                self.insert_element(tag, attributes.to_vec());
+                                return;
+                            }
+                            // only allowed in other contexts
+                            "caption" | "col" | "colgroup" | "frame" | "frameset" | "head"
+                            | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr" => return,
                             _ => {
-                                token = self.t.next();
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_element(tag, attributes.to_vec());
+                                return;
                             }
                         },
-                        Some(HtmlToken::EndTag { ref tag }) => match tag.as_str() {
+                        HtmlToken::EndTag { ref tag } => match tag.as_str() {
                             "body" => {
-                                self.mode = InsertionMode::AfterBody;
-                                token = self.t.next();
-                                if !self.contain_in_stack(ElementKind::Body) {
-                                    continue;
+                                if self.has_element_in_scope(ElementKind::Body) {
+                                    self.mode = InsertionMode::AfterBody;
                                 }
-                                self.pop_until(ElementKind::Body);
-                                continue;
+                                return;
                             }
                             "html" => {
-                                if self.pop_current_node(ElementKind::Body) {
-                                    self.mode = InsertionMode::AfterAfterBody;
-                                    assert!(self.pop_current_node(ElementKind::Html));
+                                if !self.has_element_in_scope(ElementKind::Body) {
+                                    return;
+                                }
+                                // handled like `</body>` followed by `</html>`
+                                self.mode = InsertionMode::AfterBody;
+                                continue;
+                            }
+                            "address" | "article" | "aside" | "blockquote" | "button"
+                            | "center" | "details" | "dialog" | "dir" | "div" | "dl"
+                            | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+                            | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+                            | "search" | "section" | "select" | "summary" | "ul" => {
+                                // `select` is here as there is no "in select"
+                                // mode yet
+                                let element_kind = match ElementKind::from_str(tag) {
+                                    Ok(kind) => kind,
+                                    Err(_) => return,
                                 };
+                                if self.has_element_in_scope(element_kind) {
+                                    self.generate_implied_end_tags(None);
+                                    self.pop_until(element_kind);
+                                }
+                                return;
                             }
-                            "p" | "a" | "h1" | "h2" => {
-                                let element_kind = ElementKind::from_str(tag)
-                                    .expect("failed to convert string to ElementKind");
-                                self.pop_until(element_kind);
-                                token = self.t.next();
-                                continue;
+                            "template" => {
+                                self.process_token_using(InsertionMode::InHead, token);
+                                return;
+                            }
+                            "form" => {
+                                if self.contain_in_stack(ElementKind::Template) {
+                                    if self.has_element_in_scope(ElementKind::Form) {
+                                        self.generate_implied_end_tags(None);
+                                        self.pop_until(ElementKind::Form);
+                                    }
+                                    return;
+                                }
+                                let form = self.form_element_pointer.take();
+                                if let Some(form) = form {
+                                    if self.has_element_in_scope(ElementKind::Form) {
+                                        self.generate_implied_end_tags(None);
+                                        self.remove_from_stack(&form);
+                                    }
+                                }
+                                return;
+                            }
+                            "p" => {
+                                if !self.has_element_in_button_scope(ElementKind::P) {
+                                    // `</p>` without `<p>` is an empty paragraph
+                                    self.insert_element("p", Vec::new());
+                                }
+                                self.close_p_element();
+                                return;
+                            }
+                            "li" => {
+                                if self.has_element_in_list_item_scope(ElementKind::Li) {
+                                    self.generate_implied_end_tags(Some(ElementKind::Li));
+                                    self.pop_until(ElementKind::Li);
+                                }
+                                return;
+                            }
+                            "dd" | "dt" => {
+                                let element_kind = if tag == "dd" {
+                                    ElementKind::Dd
+                                } else {
+                                    ElementKind::Dt
+                                };
+                                if self.has_element_in_scope(element_kind) {
+                                    self.generate_implied_end_tags(Some(element_kind));
+                                    self.pop_until(element_kind);
+                                }
+                                return;
+                            }
+                            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                                // any heading closes the open one
+                                if HEADINGS.iter().any(|h| self.has_element_in_scope(*h)) {
+                                    self.generate_implied_end_tags(None);
+                                    while let Some(node) = self.stack_of_open_elements.pop() {
+                                        if node.borrow().element_kind().is_some_and(is_heading) {
+                                            break;
+                                        }
+                                    }
+                                }
+                                return;
+                            }
+                            "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s"
+                            | "small" | "strike" | "strong" | "tt" | "u" => {
+                                if !self.run_adoption_agency(tag) {
+                                    self.close_element_by_end_tag(tag);
+                                }
+                                return;
+                            }
+                            "applet" | "marquee" | "object" => {
+                                let element_kind = match ElementKind::from_str(tag) {
+                                    Ok(kind) => kind,
+                                    Err(_) => return,
+                                };
+                                if self.has_element_in_scope(element_kind) {
+                                    self.generate_implied_end_tags(None);
+                                    self.pop_until(element_kind);
+                                    self.clear_active_formatting_elements_to_last_marker();
+                                }
+                                return;
+                            }
+                            "br" => {
+                                // `</br>` is treated as `<br>`
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_void_element("br", Vec::new());
+                                return;
                             }
                             _ => {
-                                token = self.t.next();
+                                self.close_element_by_end_tag(tag);
+                                return;
                             }
                         },
-                        Some(HtmlToken::Char(c)) => {
+                        HtmlToken::Char(c) => {
+                            if c != '\0' {
+                                self.reconstruct_active_formatting_elements();
+                                self.insert_char(c);
+                            }
+                            return;
+                        }
+                        HtmlToken::Comment(ref data) => {
+                            self.insert_comment(data, None);
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        HtmlToken::Eof => {
+                            if !self.template_insertion_modes.is_empty() {
+                                self.process_token_using(InsertionMode::InTemplate, token);
+                            }
+                            return;
+                        }
+                    }
+                }
+                InsertionMode::Text => {
+                    match token {
+                        HtmlToken::Char(c) => {
                             self.insert_char(c);
-                            token = self.t.next();
+                            return;
+                        }
+                        HtmlToken::EndTag { tag: _ } => {
+                            // the tokenizer only ends the text at the
+                            // element's own end tag
+                            self.stack_of_open_elements.pop();
+                            self.mode = self.original_intersection_mode;
+                            return;
+                        }
+                        HtmlToken::Eof => {
+                            self.stack_of_open_elements.pop();
+                            self.mode = self.original_intersection_mode;
                             continue;
                         }
-                        Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                        _ => {
+                            return;
+                        }
+                    }
+                }
+                InsertionMode::InTable => {
+                    match token {
+                        HtmlToken::Char(_) => {
+                            let current = self.current_node().borrow().element_kind();
+                            if matches!(
+                                current,
+                                Some(ElementKind::Table)
+                                    | Some(ElementKind::Tbody)
+                                    | Some(ElementKind::Template)
+                                    | Some(ElementKind::Tfoot)
+                                    | Some(ElementKind::Thead)
+                                    | Some(ElementKind::Tr)
+                            ) {
+                                self.pending_table_characters.clear();
+                                self.original_intersection_mode = self.mode;
+                                self.mode = InsertionMode::InTableText;
+                                continue;
+                            }
+                        }
+                        HtmlToken::Comment(ref data) => {
+                            self.insert_comment(data, None);
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        HtmlToken::StartTag {
+                            ref tag,
+                            self_closing: _,
+                            ref attributes,
+                        } => match tag.as_str() {
+                            "caption" => {
+                                self.clear_stack_back_to(&[ElementKind::Table]);
+                                self.active_formatting_elements
+                                    .push(ActiveFormattingElement::Marker);
+                                self.insert_element(tag, attributes.to_vec());
+                                self.mode = InsertionMode::InCaption;
+                                return;
+                            }
+                            "colgroup" => {
+                                self.clear_stack_back_to(&[ElementKind::Table]);
+                                self.insert_element(tag, attributes.to_vec());
+                                self.mode = InsertionMode::InColumnGroup;
+                                return;
+                            }
+                            "col" => {
+                                self.clear_stack_back_to(&[ElementKind::Table]);
+                                self.insert_element("colgroup", Vec::new());
+                                self.mode = InsertionMode::InColumnGroup;
+                                continue;
+                            }
+                            "tbody" | "tfoot" | "thead" => {
+                                self.clear_stack_back_to(&[ElementKind::Table]);
+                                self.insert_element(tag, attributes.to_vec());
+                                self.mode = InsertionMode::InTableBody;
+                                return;
+                            }
+                            "td" | "th" | "tr" => {
+                                self.clear_stack_back_to(&[ElementKind::Table]);
+                                self.insert_element("tbody", Vec::new());
+                                self.mode = InsertionMode::InTableBody;
+                                continue;
+                            }
+                            "table" => {
+                                // a table can't be nested directly, so this
+                                // closes the open one
+                                if !self.has_element_in_table_scope(ElementKind::Table) {
+                                    return;
+                                }
+                                self.pop_until(ElementKind::Table);
+                                self.reset_insertion_mode();
+                                continue;
+                            }
+                            "style" | "script" | "template" => {
+                                self.process_token_using(InsertionMode::InHead, token);
+                                return;
+                            }
+                            "input" => {
+                                // only a hidden input can be inside a table
+                                let hidden = attributes.iter().any(|a| {
+                                    a.name().eq_ignore_ascii_case("type")
+                                        && a.value().eq_ignore_ascii_case("hidden")
+                                });
+                                if hidden {
+                                    self.insert_element(tag, attributes.to_vec());
+                                    self.associate_with_form();
+                                    self.stack_of_open_elements.pop();
+                                    return;
+                                }
+                            }
+                            "form" => {
+                                // the form is inserted empty, its fields are
+                                // associated with it
+                                if self.form_element_pointer.is_none()
+                                    && !self.contain_in_stack(ElementKind::Template)
+                                {
+                                    self.insert_element(tag, attributes.to_vec());
+                                    self.form_element_pointer = Some(self.current_node());
+                                    self.stack_of_open_elements.pop();
+                                }
+                                return;
+                            }
+                            _ => {}
+                        },
+                        HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                            "table" => {
+                                if self.has_element_in_table_scope(ElementKind::Table) {
+                                    self.pop_until(ElementKind::Table);
+                                    self.reset_insertion_mode();
+                                }
+                                return;
+                            }
+                            "template" => {
+                                self.process_token_using(InsertionMode::InHead, token);
+                                return;
+                            }
+                            "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td"
+                            | "tfoot" | "th" | "thead" | "tr" => return,
+                            _ => {}
+                        },
+                        HtmlToken::Eof => {
+                            self.process_token_using(InsertionMode::InBody, token);
+                            return;
+                        }
+                    }
+
+                    // anything else is moved out of the table, before it
+                    self.foster_parenting = true;
+                    self.process_token_using(InsertionMode::InBody, token);
+                    self.foster_parenting = false;
+                    return;
+                }
+                InsertionMode::InTableText => {
+                    if let HtmlToken::Char(c) = token {
+                        if c != '\0' {
+                            self.pending_table_characters.push(c);
                         }
+                        return;
                     }
 
+                    let chars = core::mem::take(&mut self.pending_table_characters);
+                    if chars.iter().all(|c| is_whitespace(*c)) {
+                        for c in chars {
+                            self.insert_char(c);
+                        }
+                    } else {
+                        // text in a table is moved out of it, before it
+                        self.foster_parenting = true;
+                        for c in chars {
+                            self.process_token_using(InsertionMode::InBody, HtmlToken::Char(c));
+                        }
+                        self.foster_parenting = false;
+                    }
+                    self.mode = self.original_intersection_mode;
                     continue;
                 }
-                InsertionMode::Text => {
+                InsertionMode::InCaption => {
                     match token {
-                        Some(HtmlToken::Eof) | None => return self.window.clone(),
-                        Some(HtmlToken::EndTag { ref tag }) => {
-                            if tag == "style" {
-                                self.pop_until(ElementKind::Style);
-                                self.mode = self.original_intersection_mode;
-                                token = self.t.next();
+                        HtmlToken::StartTag { ref tag, .. } => {
+                            if matches!(
+                                tag.as_str(),
+                                "caption"
+                                    | "col"
+                                    | "colgroup"
+                                    | "tbody"
+                                    | "td"
+                                    | "tfoot"
+                                    | "th"
+                                    | "thead"
+                                    | "tr"
+                            ) {
+                                // a part of the table closes the caption
+                                if !self.has_element_in_table_scope(ElementKind::Caption) {
+                                    return;
+                                }
+                                self.close_caption();
                                 continue;
                             }
                         }
-                        Some(HtmlToken::Char(c)) => {
+                        HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                            "caption" => {
+                                if self.has_element_in_table_scope(ElementKind::Caption) {
+                                    self.close_caption();
+                                }
+                                return;
+                            }
+                            "table" => {
+                                if !self.has_element_in_table_scope(ElementKind::Caption) {
+                                    return;
+                                }
+                                self.close_caption();
+                                continue;
+                            }
+                            "body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot"
+                            | "th" | "thead" | "tr" => return,
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+
+                    self.process_token_using(InsertionMode::InBody, token);
+                    return;
+                }
+                InsertionMode::InColumnGroup => {
+                    match token {
+                        HtmlToken::Char(c) if is_whitespace(c) => {
                             self.insert_char(c);
-                            token = self.t.next();
-                            continue;
+                            return;
+                        }
+                        HtmlToken::Comment(ref data) => {
+                            self.insert_comment(data, None);
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        HtmlToken::StartTag {
+                            ref tag,
+                            self_closing: _,
+                            ref attributes,
+                        } => match tag.as_str() {
+                            "html" => {
+                                self.process_token_using(InsertionMode::InBody, token);
+                                return;
+                            }
+                            "col" => {
+                                self.insert_void_element(tag, attributes.to_vec());
+                                return;
+                            }
+                            "template" => {
+                                self.process_token_using(InsertionMode::InHead, token);
+                                return;
+                            }
+                            _ => {}
+                        },
+                        HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                            "colgroup" => {
+                                if self.current_node().borrow().element_kind()
+                                    == Some(ElementKind::Colgroup)
+                                {
+                                    self.stack_of_open_elements.pop();
+                                    self.mode = InsertionMode::InTable;
+                                }
+                                return;
+                            }
+                            "col" => return,
+                            "template" => {
+                                self.process_token_using(InsertionMode::InHead, token);
+                                return;
+                            }
+                            _ => {}
+                        },
+                        HtmlToken::Eof => {
+                            self.process_token_using(InsertionMode::InBody, token);
+                            return;
+                        }
+                        _ => {}
+                    }
+
+                    // anything else closes the column group
+                    if self.current_node().borrow().element_kind() != Some(ElementKind::Colgroup) {
+                        return;
+                    }
+                    self.stack_of_open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                    continue;
+                }
+                InsertionMode::InTableBody => {
+                    match token {
+                        HtmlToken::StartTag {
+                            ref tag,
+                            self_closing: _,
+                            ref attributes,
+                        } => match tag.as_str() {
+                            "tr" => {
+                                self.clear_stack_back_to(TABLE_SECTIONS);
+                                self.insert_element(tag, attributes.to_vec());
+                                self.mode = InsertionMode::InRow;
+                                return;
+                            }
+                            "th" | "td" => {
+                                self.clear_stack_back_to(TABLE_SECTIONS);
+                                self.insert_element("tr", Vec::new());
+                                self.mode = InsertionMode::InRow;
+                                continue;
+                            }
+                            "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" => {
+                                if !self.close_table_section() {
+                                    return;
+                                }
+                                continue;
+                            }
+                            _ => {}
+                        },
+                        HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                            "tbody" | "tfoot" | "thead" => {
+                                let element_kind = match ElementKind::from_str(tag) {
+                                    Ok(kind) => kind,
+                                    Err(_) => return,
+                                };
+                                if self.has_element_in_table_scope(element_kind) {
+                                    self.clear_stack_back_to(TABLE_SECTIONS);
+                                    self.stack_of_open_elements.pop();
+                                    self.mode = InsertionMode::InTable;
+                                }
+                                return;
+                            }
+                            "table" => {
+                                if !self.close_table_section() {
+                                    return;
+                                }
+                                continue;
+                            }
+                            "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th"
+                            | "tr" => return,
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+
+                    self.process_token_using(InsertionMode::InTable, token);
+                    return;
+                }
+                InsertionMode::InRow => {
+                    match token {
+                        HtmlToken::StartTag {
+                            ref tag,
+                            self_closing: _,
+                            ref attributes,
+                        } => match tag.as_str() {
+                            "th" | "td" => {
+                                self.clear_stack_back_to(&[ElementKind::Tr]);
+                                self.insert_element(tag, attributes.to_vec());
+                                self.mode = InsertionMode::InCell;
+                                self.active_formatting_elements
+                                    .push(ActiveFormattingElement::Marker);
+                                return;
+                            }
+                            "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr" => {
+                                if !self.close_row() {
+                                    return;
+                                }
+                                continue;
+                            }
+                            _ => {}
+                        },
+                        HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                            "tr" => {
+                                self.close_row();
+                                return;
+                            }
+                            "table" => {
+                                if !self.close_row() {
+                                    return;
+                                }
+                                continue;
+                            }
+                            "tbody" | "tfoot" | "thead" => {
+                                let element_kind = match ElementKind::from_str(tag) {
+                                    Ok(kind) => kind,
+                                    Err(_) => return,
+                                };
+                                if !self.has_element_in_table_scope(element_kind)
+                                    || !self.close_row()
+                                {
+                                    return;
+                                }
+                                continue;
+                            }
+                            "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" => {
+                                return
+                            }
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+
+                    self.process_token_using(InsertionMode::InTable, token);
+                    return;
+                }
+                InsertionMode::InCell => {
+                    match token {
+                        HtmlToken::StartTag { ref tag, .. } => {
+                            if matches!(
+                                tag.as_str(),
+                                "caption"
+                                    | "col"
+                                    | "colgroup"
+                                    | "tbody"
+                                    | "td"
+                                    | "tfoot"
+                                    | "th"
+                                    | "thead"
+                                    | "tr"
+                            ) {
+                                // a part of the table closes the cell
+                                if !self.has_element_in_table_scope(ElementKind::Td)
+                                    && !self.has_element_in_table_scope(ElementKind::Th)
+                                {
+                                    return;
+                                }
+                                self.close_cell();
+                                continue;
+                            }
                         }
+                        HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                            "td" | "th" => {
+                                let element_kind = match ElementKind::from_str(tag) {
+                                    Ok(kind) => kind,
+                                    Err(_) => return,
+                                };
+                                if self.has_element_in_table_scope(element_kind) {
+                                    self.generate_implied_end_tags(None);
+                                    self.pop_until(element_kind);
+                                    self.clear_active_formatting_elements_to_last_marker();
+                                    self.mode = InsertionMode::InRow;
+                                }
+                                return;
+                            }
+                            "body" | "caption" | "col" | "colgroup" | "html" => return,
+                            "table" | "tbody" | "tfoot" | "thead" | "tr" => {
+                                let element_kind = match ElementKind::from_str(tag) {
+                                    Ok(kind) => kind,
+                                    Err(_) => return,
+                                };
+                                if !self.has_element_in_table_scope(element_kind) {
+                                    return;
+                                }
+                                self.close_cell();
+                                continue;
+                            }
+                            _ => {}
+                        },
                         _ => {}
                     }
 
-                    self.mode = self.original_intersection_mode;
+                    self.process_token_using(InsertionMode::InBody, token);
+                    return;
+                }
+                InsertionMode::InTemplate => {
+                    let mode = match token {
+                        HtmlToken::Char(_) | HtmlToken::Comment(_) | HtmlToken::Doctype { .. } => {
+                            self.process_token_using(InsertionMode::InBody, token);
+                            return;
+                        }
+                        HtmlToken::StartTag { ref tag, .. } => match tag.as_str() {
+                            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes"
+                            | "script" | "style" | "template" | "title" => {
+                                self.process_token_using(InsertionMode::InHead, token);
+                                return;
+                            }
+                            "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => {
+                                InsertionMode::InTable
+                            }
+                            "col" => InsertionMode::InColumnGroup,
+                            "tr" => InsertionMode::InTableBody,
+                            "td" | "th" => InsertionMode::InRow,
+                            _ => InsertionMode::InBody,
+                        },
+                        HtmlToken::EndTag { ref tag } => {
+                            if tag == "template" {
+                                self.process_token_using(InsertionMode::InHead, token);
+                            }
+                            return;
+                        }
+                        HtmlToken::Eof => {
+                            if !self.contain_in_stack(ElementKind::Template) {
+                                return;
+                            }
+                            // an unclosed template is closed at the end
+                            self.close_template();
+                            continue;
+                        }
+                    };
+
+                    // the first start tag decides what the contents are
+                    self.template_insertion_modes.pop();
+                    self.template_insertion_modes.push(mode);
+                    self.mode = mode;
                     continue;
                 }
                 InsertionMode::AfterBody => {
                     match token {
-                        Some(HtmlToken::Char(_)) => {
-                            token = self.t.next();
-                            continue;
+                        HtmlToken::Char(c) if is_whitespace(c) => {
+                            self.process_token_using(InsertionMode::InBody, token);
+                            return;
                         }
-                        Some(HtmlToken::EndTag { ref tag }) => {
+                        HtmlToken::EndTag { ref tag } => {
                             if tag == "html" {
-                                self.mode = InsertionMode::AfterAfterBody;
-                                token = self.t.next();
-                                continue;
+                                if self.context.is_none() {
+                                    self.mode = InsertionMode::AfterAfterBody;
+                                }
+                                return;
                             }
                         }
-                        Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                        HtmlToken::Comment(ref data) => {
+                            let html = self.stack_of_open_elements.first().cloned();
+                            self.insert_comment(data, html);
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        HtmlToken::Eof => {
+                            return;
                         }
                         _ => {}
                     }
@@ -438,12 +2354,21 @@ impl HtmlParser {
                 }
                 InsertionMode::AfterAfterBody => {
                     match token {
-                        Some(HtmlToken::Char(_)) => {
-                            token = self.t.next();
-                            continue;
+                        HtmlToken::Char(c) if is_whitespace(c) => {
+                            self.process_token_using(InsertionMode::InBody, token);
+                            return;
                         }
-                        Some(HtmlToken::Eof) | None => {
-                            return self.window.clone();
+                        HtmlToken::Comment(ref data) => {
+                            let document = self.window.borrow().document();
+                            self.insert_comment(data, Some(document));
+                            return;
+                        }
+                        HtmlToken::Doctype { .. } => {
+                            // a DOCTYPE is only allowed at the start
+                            return;
+                        }
+                        HtmlToken::Eof => {
+                            return;
                         }
                         _ => {}
                     }
@@ -452,15 +2377,240 @@ impl HtmlParser {
                 }
             }
         }
+    }
+}
 
-        self.window.clone()
+const HEADINGS: [ElementKind; 6] = [
+    ElementKind::H1,
+    ElementKind::H2,
+    ElementKind::H3,
+    ElementKind::H4,
+    ElementKind::H5,
+    ElementKind::H6,
+];
+
+const TABLE_SECTIONS: &[ElementKind] =
+    &[ElementKind::Tbody, ElementKind::Tfoot, ElementKind::Thead];
+
+/// Returns the contents of `node` if it is a `template` element.
+fn template_content(node: &Rc<RefCell<Node>>) -> Option<Rc<RefCell<Node>>> {
+    match node.borrow().kind {
+        NodeKind::Element(ref e) => e.template_content(),
+        _ => None,
+    }
+}
+
+fn is_heading(kind: ElementKind) -> bool {
+    HEADINGS.contains(&kind)
+}
+
+/// Elements in the head that can't have content.
+fn is_void_head_element(tag: &str) -> bool {
+    matches!(tag, "base" | "basefont" | "bgsound" | "link" | "meta")
+}
+
+/// The elements that limit the scope the parser looks into for an open
+/// element.
+fn is_scope_boundary(node: &Node) -> bool {
+    let e = match node.kind {
+        NodeKind::Element(ref e) => e,
+        _ => return false,
+    };
+    is_special_foreign_element(e)
+        || matches!(
+            e.kind(),
+            ElementKind::Applet
+                | ElementKind::Caption
+                | ElementKind::Html
+                | ElementKind::Table
+                | ElementKind::Td
+                | ElementKind::Th
+                | ElementKind::Marquee
+                | ElementKind::Object
+                | ElementKind::Template
+        )
+}
+
+fn has_implied_end_tag(kind: ElementKind) -> bool {
+    matches!(
+        kind,
+        ElementKind::Dd
+            | ElementKind::Dt
+            | ElementKind::Li
+            | ElementKind::Optgroup
+            | ElementKind::Option
+            | ElementKind::P
+            | ElementKind::Rb
+            | ElementKind::Rp
+            | ElementKind::Rt
+            | ElementKind::Rtc
+    )
+}
+
+/// Returns true for special elements, including the special SVG and MathML
+/// elements.
+fn is_special_node(node: &Node) -> bool {
+    match node.kind {
+        NodeKind::Element(ref e) if e.namespace() == Namespace::Html => is_special(e.kind()),
+        NodeKind::Element(ref e) => is_special_foreign_element(e),
+        _ => false,
+    }
+}
+
+/// The elements the spec calls special, which an end tag of another element
+/// never closes implicitly.
+fn is_special(kind: ElementKind) -> bool {
+    matches!(
+        kind,
+        ElementKind::Address
+            | ElementKind::Applet
+            | ElementKind::Area
+            | ElementKind::Article
+            | ElementKind::Aside
+            | ElementKind::Base
+            | ElementKind::Basefont
+            | ElementKind::Bgsound
+            | ElementKind::Blockquote
+            | ElementKind::Body
+            | ElementKind::Br
+            | ElementKind::Button
+            | ElementKind::Caption
+            | ElementKind::Center
+            | ElementKind::Col
+            | ElementKind::Colgroup
+            | ElementKind::Dd
+            | ElementKind::Details
+            | ElementKind::Dir
+            | ElementKind::Div
+            | ElementKind::Dl
+            | ElementKind::Dt
+            | ElementKind::Embed
+            | ElementKind::Fieldset
+            | ElementKind::Figcaption
+            | ElementKind::Figure
+            | ElementKind::Footer
+            | ElementKind::Form
+            | ElementKind::Frame
+            | ElementKind::Frameset
+            | ElementKind::H1
+            | ElementKind::H2
+            | ElementKind::H3
+            | ElementKind::H4
+            | ElementKind::H5
+            | ElementKind::H6
+            | ElementKind::Head
+            | ElementKind::Header
+            | ElementKind::Hgroup
+            | ElementKind::Hr
+            | ElementKind::Html
+            | ElementKind::Iframe
+            | ElementKind::Img
+            | ElementKind::Input
+            | ElementKind::Keygen
+            | ElementKind::Li
+            | ElementKind::Link
+            | ElementKind::Listing
+            | ElementKind::Main
+            | ElementKind::Marquee
+            | ElementKind::Menu
+            | ElementKind::Meta
+            | ElementKind::Nav
+            | ElementKind::Noembed
+            | ElementKind::Noframes
+            | ElementKind::Noscript
+            | ElementKind::Object
+            | ElementKind::Ol
+            | ElementKind::P
+            | ElementKind::Param
+            | ElementKind::Plaintext
+            | ElementKind::Pre
+            | ElementKind::Script
+            | ElementKind::Search
+            | ElementKind::Section
+            | ElementKind::Select
+            | ElementKind::Source
+            | ElementKind::Style
+            | ElementKind::Summary
+            | ElementKind::Table
+            | ElementKind::Tbody
+            | ElementKind::Td
+            | ElementKind::Template
+            | ElementKind::Textarea
+            | ElementKind::Tfoot
+            | ElementKind::Th
+            | ElementKind::Thead
+            | ElementKind::Title
+            | ElementKind::Tr
+            | ElementKind::Track
+            | ElementKind::Ul
+            | ElementKind::Wbr
+            | ElementKind::Xmp
+    )
+}
+
+fn local_name(node: &Rc<RefCell<Node>>) -> Option<String> {
+    match node.borrow().kind {
+        NodeKind::Element(ref e) => Some(e.local_name()),
+        _ => None,
+    }
+}
+
+/// Returns true if both are elements with the same name and attributes, in
+/// any order.
+fn is_same_element(a: &Node, b: &Node) -> bool {
+    match (&a.kind, &b.kind) {
+        (NodeKind::Element(a), NodeKind::Element(b)) => {
+            a.local_name() == b.local_name()
+                && a.namespace() == b.namespace()
+                && a.attributes().len() == b.attributes().len()
+                && a.attribute_iter()
+                    .all(|attr| b.get_attribute(&attr.name()) == Some(attr.value()))
+        }
+        _ => false,
+    }
+}
+
+/// Creates an element with the same name and attributes as the formatting
+/// element `node`.
+fn clone_element(node: &Rc<RefCell<Node>>) -> Rc<RefCell<Node>> {
+    let element = match node.borrow().kind {
+        NodeKind::Element(ref e) => {
+            Element::new_in_namespace(e.namespace(), &e.local_name(), e.attributes())
+        }
+        _ => unreachable!("only elements are formatting elements"),
+    };
+    Rc::new(RefCell::new(Node::new(NodeKind::Element(element))))
+}
+
+/// Returns the content model of a fragment in the element `tag`, if it is
+/// read as text.
+fn fragment_content(tag: &str) -> Option<TokenizerState> {
+    match tag {
+        "title" | "textarea" => Some(TokenizerState::Rcdata),
+        "style" | "xmp" | "iframe" | "noembed" | "noframes" => Some(TokenizerState::Rawtext),
+        "script" => Some(TokenizerState::ScriptData),
+        "plaintext" => Some(TokenizerState::Plaintext),
+        _ => None,
+    }
+}
+
+/// Returns the content model of an element that is read as text when it
+/// appears in the head.
+fn head_text_content(tag: &str) -> Option<TokenizerState> {
+    match tag {
+        "title" => Some(TokenizerState::Rcdata),
+        "style" | "noframes" => Some(TokenizerState::Rawtext),
+        "script" => Some(TokenizerState::ScriptData),
+        _ => None,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::alloc::string::ToString;
+    use crate::alloc::{format, string::ToString};
+    use crate::renderer::dom::node::Document;
+    use crate::renderer::html::serializer::serialize_children;
 
     #[test]
     fn test_empty() {
@@ -471,10 +2621,23 @@ mod tests {
         let document = window.borrow().document();
         assert_eq!(
             document,
-            Rc::new(RefCell::new(Node::new(NodeKind::Document)))
+            Rc::new(RefCell::new(Node::new(NodeKind::Document(Document::new()))))
         );
     }
 
+    #[test]
+    fn test_documents_without_elements() {
+        for html in ["", " \n", "<!-- c -->"] {
+            let window = HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree();
+            let document = window.borrow().document();
+            assert!(
+                serialize_children(&document).ends_with("<html><head></head><body></body></html>"),
+                "{:?}",
+                html
+            );
+        }
+    }
+
     #[test]
     fn test_html_head_body() {
         let html = "<html><head></head><body></body></html>".to_string();
@@ -484,7 +2647,7 @@ mod tests {
         let document = window.borrow().document();
         assert_eq!(
             document,
-            Rc::new(RefCell::new(Node::new(NodeKind::Document)))
+            Rc::new(RefCell::new(Node::new(NodeKind::Document(Document::new()))))
         );
 
         let html = document.borrow().first_child().unwrap();
@@ -524,7 +2687,7 @@ mod tests {
         let document = window.borrow().document();
         assert_eq!(
             document,
-            Rc::new(RefCell::new(Node::new(NodeKind::Document)))
+            Rc::new(RefCell::new(Node::new(NodeKind::Document(Document::new()))))
         );
 
         let html = document.borrow().first_child().unwrap();
@@ -704,4 +2867,458 @@ mod tests {
         assert!(hello.borrow().previous_sibling().ptr_eq(&Rc::downgrade(&a)));
         assert!(p.borrow().previous_sibling().ptr_eq(&Rc::downgrade(&hello)));
     }
+
+    #[test]
+    fn test_doctype_and_comments() {
+        let html = "<!DOCTYPE html><!-- top --><html><head></head><body><!-- in body --><p>hi</p></body></html><!-- end -->".to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+
+        let document = window.borrow().document();
+        let doctype = document.borrow().first_child().unwrap();
+        assert_eq!(
+            doctype,
+            Rc::new(RefCell::new(Node::new(NodeKind::DocumentType {
+                name: "html".to_string(),
+                public_id: String::new(),
+                system_id: String::new(),
+            })))
+        );
+
+        let top = doctype.borrow().next_sibling().unwrap();
+        assert_eq!(
+            top,
+            Rc::new(RefCell::new(Node::new(NodeKind::Comment(
+                " top ".to_string()
+            ))))
+        );
+
+        let html = top.borrow().next_sibling().unwrap();
+        let body = html
+            .borrow()
+            .first_child()
+            .unwrap() // head
+            .borrow()
+            .next_sibling()
+            .unwrap();
+        let comment = body.borrow().first_child().unwrap();
+        assert_eq!(
+            comment,
+            Rc::new(RefCell::new(Node::new(NodeKind::Comment(
+                " in body ".to_string()
+            ))))
+        );
+        let p = comment.borrow().next_sibling().unwrap();
+        assert_eq!(
+            p,
+            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
+                "p",
+                Vec::new()
+            )))))
+        );
+
+        let end = html.borrow().next_sibling().unwrap();
+        assert_eq!(
+            end,
+            Rc::new(RefCell::new(Node::new(NodeKind::Comment(
+                " end ".to_string()
+            ))))
+        );
+    }
+
+    fn body_of(html: &str) -> Rc<RefCell<Node>> {
+        let window = HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree();
+        let document = window.borrow().document();
+        // the html element comes after the DOCTYPE, if there is one
+        let html = document.borrow().last_child().upgrade().unwrap();
+        let head = html.borrow().first_child().unwrap();
+        let body = head.borrow().next_sibling().unwrap();
+        body
+    }
+
+    #[test]
+    fn test_textarea_and_script_content_is_text() {
+        let body = body_of(
+            "<html><head></head><body><textarea>\n</p>&amp;</textarea><script>a < b</script><p></p></body></html>",
+        );
+
+        let textarea = body.borrow().first_child().unwrap();
+        assert_eq!(
+            textarea.borrow().first_child().unwrap(),
+            Rc::new(RefCell::new(Node::new(NodeKind::Text("</p>&".to_string()))))
+        );
+
+        let script = textarea.borrow().next_sibling().unwrap();
+        assert_eq!(
+            script,
+            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
+                "script",
+                Vec::new()
+            )))))
+        );
+        assert_eq!(
+            script.borrow().first_child().unwrap(),
+            Rc::new(RefCell::new(Node::new(NodeKind::Text("a < b".to_string()))))
+        );
+
+        let p = script.borrow().next_sibling().unwrap();
+        assert_eq!(
+            p,
+            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
+                "p",
+                Vec::new()
+            )))))
+        );
+    }
+
+    #[test]
+    fn test_title_in_head() {
+        let window = HtmlParser::new(HtmlTokenizer::new(
+            "<html><head><title>a &amp; <b>b</b></title></head></html>".to_string(),
+        ))
+        .construct_tree();
+        let document = window.borrow().document();
+        let head = document
+            .borrow()
+            .first_child()
+            .unwrap()
+            .borrow()
+            .first_child()
+            .unwrap();
+        let title = head.borrow().first_child().unwrap();
+
+        assert_eq!(
+            title.borrow().first_child().unwrap(),
+            Rc::new(RefCell::new(Node::new(NodeKind::Text(
+                "a & <b>b</b>".to_string()
+            ))))
+        );
+    }
+
+    #[test]
+    fn test_parse_in_chunks() {
+        let mut parser = HtmlParser::new(HtmlTokenizer::streaming());
+        parser.feed("<html><head></head><body><p>hel");
+
+        // what has arrived is already in the tree
+        let document = parser.window().borrow().document();
+        let html = document.borrow().first_child().unwrap();
+        let body = html.borrow().last_child().upgrade().unwrap();
+        let p = body.borrow().first_child().unwrap();
+        assert_eq!(
+            p.borrow().first_child().unwrap(),
+            Rc::new(RefCell::new(Node::new(NodeKind::Text("hel".to_string()))))
+        );
+
+        parser.feed_bytes("lo &eacu".as_bytes());
+        parser.feed_bytes(&"te; \u{3042}</p>".as_bytes()[..7]);
+        parser.feed_bytes(&"te; \u{3042}</p>".as_bytes()[7..]);
+        parser.finish();
+
+        assert_eq!(
+            p.borrow().first_child().unwrap(),
+            Rc::new(RefCell::new(Node::new(NodeKind::Text(
+                "hello \u{e9} \u{3042}".to_string()
+            ))))
+        );
+    }
+
+    /// Describes the subtree at `node` like `ul(li("a") li)`.
+    fn describe(node: &Rc<RefCell<Node>>) -> String {
+        let mut result = match node.borrow().kind {
+            NodeKind::Element(ref e) => e.local_name(),
+            NodeKind::Text(ref s) => format!("{:?}", s),
+            _ => "?".to_string(),
+        };
+        let mut child = node.borrow().first_child();
+        if child.is_some() {
+            let mut children = Vec::new();
+            while let Some(c) = child {
+                children.push(describe(&c));
+                child = c.borrow().next_sibling();
+            }
+            result.push_str(&format!("({})", children.join(" ")));
+        }
+        result
+    }
+
+    fn describe_body(html: &str) -> String {
+        describe(&body_of(&format!(
+            "<!DOCTYPE html><html><head></head><body>{}",
+            html
+        )))
+    }
+
+    #[test]
+    fn test_implied_end_tags() {
+        assert_eq!(
+            describe_body("<ul><li>a<li>b<ul><li>c</ul><li>d</ul>"),
+            r#"body(ul(li("a") li("b" ul(li("c"))) li("d")))"#
+        );
+        assert_eq!(
+            describe_body("<dl><dt>a<dd>b<dt>c</dl>"),
+            r#"body(dl(dt("a") dd("b") dt("c")))"#
+        );
+        assert_eq!(
+            describe_body("<p>a<div>b</div><p>c<h3>d</p>"),
+            r#"body(p("a") div("b") p("c") h3("d" p))"#
+        );
+        assert_eq!(
+            describe_body("<h1>a<h2>b</h1>c"),
+            r#"body(h1("a") h2("b") "c")"#
+        );
+    }
+
+    #[test]
+    fn test_misnested_formatting_elements() {
+        assert_eq!(
+            describe_body("<b>a<i>b</b>c</i>d"),
+            r#"body(b("a" i("b")) i("c") "d")"#
+        );
+        assert_eq!(
+            describe_body("<b>a<p>b</b>c</p>"),
+            r#"body(b("a") p(b("b") "c"))"#
+        );
+        assert_eq!(
+            describe_body("<a href=x>a<div>b<a>c</a>d</div>"),
+            r#"body(a("a") div(a("b") a("c") "d"))"#
+        );
+        // formatting closed by a block is reopened in the next one
+        assert_eq!(
+            describe_body("<p><em>a<p>b"),
+            r#"body(p(em("a")) p(em("b")))"#
+        );
+        // only three identical elements are reopened
+        assert_eq!(
+            describe_body("<p><b><b><b><b>a<p>b"),
+            r#"body(p(b(b(b(b("a"))))) p(b(b(b("b")))))"#
+        );
+        // formatting doesn't leak into an object
+        assert_eq!(
+            describe_body("<u>a<object>b</u>c</object>d"),
+            r#"body(u("a" object("bc") "d"))"#
+        );
+    }
+
+    #[test]
+    fn test_tables() {
+        assert_eq!(
+            describe_body("<table><tr><td>a<td>b<tr><th>c</table>d"),
+            r#"body(table(tbody(tr(td("a") td("b")) tr(th("c")))) "d")"#
+        );
+        assert_eq!(
+            describe_body(
+                "<table><caption>a</caption><col><thead><tr><td>b<tbody><tr><td>c</table>"
+            ),
+            r#"body(table(caption("a") colgroup(col) thead(tr(td("b"))) tbody(tr(td("c")))))"#
+        );
+        assert_eq!(
+            describe_body("<table><tr><td><table><tr><td>a</table>b</td></tr></table>"),
+            r#"body(table(tbody(tr(td(table(tbody(tr(td("a")))) "b")))))"#
+        );
+        // a table doesn't nest directly and closes a paragraph
+        assert_eq!(
+            describe_body("<p>a<table><table>"),
+            r#"body(p("a") table table)"#
+        );
+    }
+
+    #[test]
+    fn test_quirks_mode() {
+        let quirks_mode = |html: &str| {
+            let window = HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree();
+            let document = window.borrow().document();
+            let document = document.borrow();
+            match document.kind {
+                NodeKind::Document(ref d) => d.quirks_mode(),
+                _ => panic!("not a document"),
+            }
+        };
+        assert_eq!(quirks_mode("<!DOCTYPE html><p>a"), QuirksMode::NoQuirks);
+        assert_eq!(quirks_mode("<p>a"), QuirksMode::Quirks);
+        assert_eq!(
+            quirks_mode(
+                r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN" "http://www.w3.org/TR/html4/loose.dtd">"#
+            ),
+            QuirksMode::LimitedQuirks
+        );
+
+        // in quirks mode, a table doesn't close a paragraph
+        let body = body_of("<p>a<table></table>");
+        assert_eq!(describe(&body), r#"body(p("a" table))"#);
+    }
+
+    #[test]
+    fn test_parse_fragment() {
+        let fragment = |context: &str, html: &str| {
+            let context = Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
+                context,
+                Vec::new(),
+            )))));
+            HtmlParser::parse_fragment(&context, html.to_string())
+                .iter()
+                .map(describe)
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+
+        // there is no div to close
+        assert_eq!(fragment("div", "<p>a<p>b</div>c"), r#"p("a") p("bc")"#);
+        // a row is parsed as in a table
+        assert_eq!(fragment("tr", "<td>a<td>b"), r#"td("a") td("b")"#);
+        assert_eq!(fragment("table", "<tr><td>a"), r#"tbody(tr(td("a")))"#);
+        // the content of a textarea is text
+        assert_eq!(
+            fragment("textarea", "<b>&amp;</textarea>"),
+            r#""<b>&</textarea>""#
+        );
+        assert_eq!(
+            fragment("html", "<title>t</title><p>a"),
+            r#"head(title("t")) body(p("a"))"#
+        );
+    }
+
+    #[test]
+    fn test_foreign_content() {
+        let body = body_of(
+            "<!DOCTYPE html><p><svg viewbox='0 0 1 1'><clippath/><title>t</title><circle><b>x</b>",
+        );
+        let p = body.borrow().first_child().unwrap();
+        let svg = p.borrow().first_child().unwrap();
+        match svg.borrow().kind {
+            NodeKind::Element(ref e) => {
+                assert_eq!(e.namespace(), Namespace::Svg);
+                assert_eq!(e.get_attribute("viewBox"), Some("0 0 1 1".to_string()));
+            }
+            _ => unreachable!(),
+        }
+        // an HTML element closes the SVG elements it is in
+        assert_eq!(
+            describe(&body),
+            r#"body(p(svg(clipPath title("t") circle) b("x")))"#
+        );
+        let title = svg
+            .borrow()
+            .first_child()
+            .unwrap()
+            .borrow()
+            .next_sibling()
+            .unwrap();
+        assert_eq!(title.borrow().element_kind(), Some(ElementKind::Unknown));
+
+        // elements in an integration point are HTML
+        let body = body_of("<!DOCTYPE html><math><mi><p>a</p></mi><mtext/></math>");
+        assert_eq!(describe(&body), r#"body(math(mi(p("a")) mtext))"#);
+        let math = body.borrow().first_child().unwrap();
+        let mi = math.borrow().first_child().unwrap();
+        assert_eq!(
+            mi.borrow().first_child().unwrap().borrow().element_kind(),
+            Some(ElementKind::P)
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let body = body_of(
+            "<!DOCTYPE html><body><template><li>a<form><input></form></template><form><input></form>",
+        );
+        // the contents are kept apart from the children
+        let template = body.borrow().first_child().unwrap();
+        assert_eq!(template.borrow().first_child(), None);
+        let content = match template.borrow().kind {
+            NodeKind::Element(ref e) => e.template_content().unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(content.borrow().kind, NodeKind::DocumentFragment);
+        assert_eq!(
+            describe(&content.borrow().first_child().unwrap()),
+            r#"li("a" form(input))"#
+        );
+        // the form in the template doesn't keep the next one from being
+        // inserted
+        assert_eq!(describe(&body), "body(template form(input))");
+
+        // an unclosed template is closed at the end
+        let body = body_of("<!DOCTYPE html><body><template><table><tr>");
+        let template = body.borrow().first_child().unwrap();
+        let content = match template.borrow().kind {
+            NodeKind::Element(ref e) => e.template_content().unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            describe(&content.borrow().first_child().unwrap()),
+            "table(tbody(tr))"
+        );
+
+        // the contents of a template can be parts of a table
+        let context = Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
+            "template",
+            Vec::new(),
+        )))));
+        let children = HtmlParser::parse_fragment(&context, "<td>a<td>b".to_string());
+        let children: Vec<String> = children.iter().map(describe).collect();
+        assert_eq!(children.join(" "), r#"td("a") td("b")"#);
+    }
+
+    #[test]
+    fn test_foster_parenting() {
+        assert_eq!(
+            describe_body("<table>a<tr><td>b</td>c</tr></table>"),
+            r#"body("ac" table(tbody(tr(td("b")))))"#
+        );
+        assert_eq!(
+            describe_body("<div><table><b>a</b><tr><td>b</table></div>"),
+            r#"body(div(b("a") table(tbody(tr(td("b"))))))"#
+        );
+        // formatting doesn't leak into a cell
+        assert_eq!(
+            describe_body("<table><tr><td><i>a</td><td>b</table>"),
+            r#"body(table(tbody(tr(td(i("a")) td("b")))))"#
+        );
+        // only a hidden input stays in the table
+        assert_eq!(
+            describe_body("<table><input type=hidden><input></table>"),
+            r#"body(input table(input))"#
+        );
+    }
+
+    #[test]
+    fn test_text_and_whitespace() {
+        assert_eq!(describe_body("a <b>c</b> d"), r#"body("a " b("c") " d")"#);
+        assert_eq!(
+            describe_body("<ul>\n  <li>a</li>\n</ul>"),
+            r#"body(ul("\n  " li("a") "\n"))"#
+        );
+        // whitespace after the body goes into it, other text too
+        assert_eq!(
+            describe_body("<p>a</p></body> </html>\nb"),
+            r#"body(p("a") " \nb")"#
+        );
+        // text split by a comment makes up two nodes
+        assert_eq!(describe_body("a<!--c-->b"), r#"body("a" ? "b")"#);
+    }
+
+    #[test]
+    fn test_void_and_unknown_elements() {
+        assert_eq!(
+            describe_body("<p>a<br>b<img src=x>c</br><hr>d<my-widget><span>e</span></my-widget>"),
+            r#"body(p("a" br "b" img "c" br) hr "d" my-widget(span("e")))"#
+        );
+        // an end tag doesn't close past a special element
+        assert_eq!(
+            describe_body("<span><div>a</span></div>b"),
+            r#"body(span(div("a") "b"))"#
+        );
+    }
+
+    #[test]
+    fn test_head_closed_implicitly() {
+        let window = HtmlParser::new(HtmlTokenizer::new("<meta charset=utf-8><p>x".to_string()))
+            .construct_tree();
+        let document = window.borrow().document();
+        assert_eq!(
+            describe(&document.borrow().first_child().unwrap()),
+            r#"html(head(meta) body(p("x")))"#
+        );
+    }
 }
@@ -0,0 +1,191 @@
+//! Decides the compatibility mode of a document from its DOCTYPE, following
+//! <https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode>.
+
+use crate::renderer::dom::node::QuirksMode;
+
+/// Public identifiers that put a document in quirks mode when they start
+/// its public identifier.
+const QUIRKS_PUBLIC_ID_PREFIXES: [&str; 55] = [
+    "+//Silmaril//dtd html Pro v0r11 19970101//",
+    "-//AS//DTD HTML 3.0 asWedit + extensions//",
+    "-//AdvaSoft Ltd//DTD HTML 3.0 asWedit + extensions//",
+    "-//IETF//DTD HTML 2.0 Level 1//",
+    "-//IETF//DTD HTML 2.0 Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict Level 1//",
+    "-//IETF//DTD HTML 2.0 Strict Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict//",
+    "-//IETF//DTD HTML 2.0//",
+    "-//IETF//DTD HTML 2.1E//",
+    "-//IETF//DTD HTML 3.0//",
+    "-//IETF//DTD HTML 3.2 Final//",
+    "-//IETF//DTD HTML 3.2//",
+    "-//IETF//DTD HTML 3//",
+    "-//IETF//DTD HTML Level 0//",
+    "-//IETF//DTD HTML Level 1//",
+    "-//IETF//DTD HTML Level 2//",
+    "-//IETF//DTD HTML Level 3//",
+    "-//IETF//DTD HTML Strict Level 0//",
+    "-//IETF//DTD HTML Strict Level 1//",
+    "-//IETF//DTD HTML Strict Level 2//",
+    "-//IETF//DTD HTML Strict Level 3//",
+    "-//IETF//DTD HTML Strict//",
+    "-//IETF//DTD HTML//",
+    "-//Metrius//DTD Metrius Presentational//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 2.0 Tables//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 3.0 Tables//",
+    "-//Netscape Comm. Corp.//DTD HTML//",
+    "-//Netscape Comm. Corp.//DTD Strict HTML//",
+    "-//O'Reilly and Associates//DTD HTML 2.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended 1.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended Relaxed 1.0//",
+    "-//SQ//DTD HTML 2.0 HoTMetaL + extensions//",
+    "-//SoftQuad Software//DTD HoTMetaL PRO 6.0::19990601::extensions to HTML 4.0//",
+    "-//SoftQuad//DTD HoTMetaL PRO 4.0::19970916::extensions to HTML 4.0//",
+    "-//Spyglass//DTD HTML 2.0 Extended//",
+    "-//Sun Microsystems Corp.//DTD HotJava HTML//",
+    "-//Sun Microsystems Corp.//DTD HotJava Strict HTML//",
+    "-//W3C//DTD HTML 3 1995-03-24//",
+    "-//W3C//DTD HTML 3.2 Draft//",
+    "-//W3C//DTD HTML 3.2 Final//",
+    "-//W3C//DTD HTML 3.2//",
+    "-//W3C//DTD HTML 3.2S Draft//",
+    "-//W3C//DTD HTML 4.0 Frameset//",
+    "-//W3C//DTD HTML 4.0 Transitional//",
+    "-//W3C//DTD HTML Experimental 19960712//",
+    "-//W3C//DTD HTML Experimental 970421//",
+    "-//W3C//DTD W3 HTML//",
+    "-//W3O//DTD W3 HTML 3.0//",
+    "-//WebTechs//DTD Mozilla HTML 2.0//",
+    "-//WebTechs//DTD Mozilla HTML//",
+];
+
+/// Public identifiers that put a document in quirks mode.
+const QUIRKS_PUBLIC_IDS: [&str; 3] = [
+    "-//W3O//DTD W3 HTML Strict 3.0//EN//",
+    "-/W3C/DTD HTML 4.0 Transitional/EN",
+    "HTML",
+];
+
+const QUIRKS_SYSTEM_ID: &str = "http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd";
+
+/// HTML 4.01 DOCTYPEs, which are quirks without a system identifier and
+/// limited quirks with one.
+const HTML4_PUBLIC_ID_PREFIXES: [&str; 2] = [
+    "-//W3C//DTD HTML 4.01 Frameset//",
+    "-//W3C//DTD HTML 4.01 Transitional//",
+];
+
+const LIMITED_QUIRKS_PUBLIC_ID_PREFIXES: [&str; 2] = [
+    "-//W3C//DTD XHTML 1.0 Frameset//",
+    "-//W3C//DTD XHTML 1.0 Transitional//",
+];
+
+/// Returns the mode a DOCTYPE puts the document in. Identifiers are compared
+/// case-insensitively.
+pub fn quirks_mode(
+    name: Option<&str>,
+    public_id: Option<&str>,
+    system_id: Option<&str>,
+    force_quirks: bool,
+) -> QuirksMode {
+    let starts_with = |id: &str, prefix: &str| {
+        id.len() >= prefix.len()
+            && id.is_char_boundary(prefix.len())
+            && id[..prefix.len()].eq_ignore_ascii_case(prefix)
+    };
+    let public = public_id.unwrap_or("");
+
+    if force_quirks
+        || name != Some("html")
+        || QUIRKS_PUBLIC_IDS
+            .iter()
+            .any(|id| public.eq_ignore_ascii_case(id))
+        || system_id.is_some_and(|id| id.eq_ignore_ascii_case(QUIRKS_SYSTEM_ID))
+        || QUIRKS_PUBLIC_ID_PREFIXES
+            .iter()
+            .any(|prefix| starts_with(public, prefix))
+        || system_id.is_none()
+            && HTML4_PUBLIC_ID_PREFIXES
+                .iter()
+                .any(|prefix| starts_with(public, prefix))
+    {
+        return QuirksMode::Quirks;
+    }
+
+    if LIMITED_QUIRKS_PUBLIC_ID_PREFIXES
+        .iter()
+        .chain(HTML4_PUBLIC_ID_PREFIXES.iter())
+        .any(|prefix| starts_with(public, prefix))
+    {
+        return QuirksMode::LimitedQuirks;
+    }
+
+    QuirksMode::NoQuirks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quirks_mode() {
+        assert_eq!(
+            quirks_mode(Some("html"), None, None, false),
+            QuirksMode::NoQuirks
+        );
+        assert_eq!(
+            quirks_mode(
+                Some("html"),
+                Some("-//W3C//DTD HTML 4.01//EN"),
+                Some("http://www.w3.org/TR/html4/strict.dtd"),
+                false
+            ),
+            QuirksMode::NoQuirks
+        );
+        assert_eq!(quirks_mode(None, None, None, true), QuirksMode::Quirks);
+        assert_eq!(
+            quirks_mode(Some("svg"), None, None, false),
+            QuirksMode::Quirks
+        );
+        assert_eq!(
+            quirks_mode(
+                Some("html"),
+                Some("-//w3c//dtd html 3.2 final//en"),
+                None,
+                false
+            ),
+            QuirksMode::Quirks
+        );
+        assert_eq!(
+            quirks_mode(
+                Some("html"),
+                Some("-//W3C//DTD HTML 4.01 Transitional//EN"),
+                None,
+                false
+            ),
+            QuirksMode::Quirks
+        );
+        assert_eq!(
+            quirks_mode(
+                Some("html"),
+                Some("-//W3C//DTD HTML 4.01 Transitional//EN"),
+                Some("http://www.w3.org/TR/html4/loose.dtd"),
+                false
+            ),
+            QuirksMode::LimitedQuirks
+        );
+        assert_eq!(
+            quirks_mode(
+                Some("html"),
+                Some("-//W3C//DTD XHTML 1.0 Strict//EN"),
+                Some("http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd"),
+                false
+            ),
+            QuirksMode::NoQuirks
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Turns nodes back into HTML, following
+//! <https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments>.
+
+use core::cell::RefCell;
+
+use alloc::{rc::Rc, string::String};
+
+use crate::renderer::dom::node::{Namespace, Node, NodeKind};
+
+/// Returns the HTML for the children of `node`, as `innerHTML` does.
+/// Parsing it as a fragment in `node` gives the same children back. For a
+/// document, this is the whole page.
+pub fn serialize_children(node: &Rc<RefCell<Node>>) -> String {
+    let mut html = String::new();
+    write_children(node, &mut html);
+    html
+}
+
+/// Returns the HTML for `node` itself and its descendants, as `outerHTML`
+/// does.
+pub fn serialize_node(node: &Rc<RefCell<Node>>) -> String {
+    let mut html = String::new();
+    write_node(node, &mut html);
+    html
+}
+
+fn write_children(node: &Rc<RefCell<Node>>, html: &mut String) {
+    // the children of a template are its contents
+    let content = match node.borrow().kind {
+        NodeKind::Element(ref e) => e.template_content(),
+        _ => None,
+    };
+    let node = content.as_ref().unwrap_or(node);
+
+    let mut child = node.borrow().first_child();
+    while let Some(c) = child {
+        write_node(&c, html);
+        child = c.borrow().next_sibling();
+    }
+}
+
+fn write_node(node: &Rc<RefCell<Node>>, html: &mut String) {
+    match node.borrow().kind {
+        NodeKind::Element(ref e) => {
+            html.push('<');
+            html.push_str(&e.local_name());
+            for a in e.attribute_iter() {
+                html.push(' ');
+                html.push_str(&a.name());
+                html.push_str("=\"");
+                escape(&a.value(), true, html);
+                html.push('"');
+            }
+            html.push('>');
+
+            if e.namespace() == Namespace::Html && is_void_element(&e.local_name()) {
+                return;
+            }
+            write_children(node, html);
+            html.push_str("</");
+            html.push_str(&e.local_name());
+            html.push('>');
+        }
+        NodeKind::Text(ref s) => {
+            let is_raw_text =
+                node.borrow()
+                    .parent()
+                    .upgrade()
+                    .is_some_and(|parent| match parent.borrow().kind {
+                        NodeKind::Element(ref e) => {
+                            e.namespace() == Namespace::Html && is_raw_text_element(&e.local_name())
+                        }
+                        _ => false,
+                    });
+            if is_raw_text {
+                html.push_str(s);
+            } else {
+                escape(s, false, html);
+            }
+        }
+        NodeKind::Comment(ref s) => {
+            html.push_str("<!--");
+            html.push_str(s);
+            html.push_str("-->");
+        }
+        NodeKind::DocumentType { ref name, .. } => {
+            html.push_str("<!DOCTYPE ");
+            html.push_str(name);
+            html.push('>');
+        }
+        NodeKind::Document(_) | NodeKind::DocumentFragment => write_children(node, html),
+    }
+}
+
+/// Escapes `s` so that it reads back as text, or as an attribute value if
+/// `in_attribute` is set.
+fn escape(s: &str, in_attribute: bool, html: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '\u{a0}' => html.push_str("&nbsp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' if in_attribute => html.push_str("&quot;"),
+            c => html.push(c),
+        }
+    }
+}
+
+/// Elements that can't have content, so they have no end tag.
+fn is_void_element(tag: &str) -> bool {
+    matches!(
+        tag,
+        "area"
+            | "base"
+            | "basefont"
+            | "bgsound"
+            | "br"
+            | "col"
+            | "embed"
+            | "frame"
+            | "hr"
+            | "img"
+            | "input"
+            | "keygen"
+            | "link"
+            | "meta"
+            | "param"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
+
+/// Elements whose text is not escaped, as the parser doesn't read character
+/// references in it.
+fn is_raw_text_element(tag: &str) -> bool {
+    matches!(
+        tag,
+        "style" | "script" | "xmp" | "iframe" | "noembed" | "noframes" | "plaintext"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::string::ToString;
+    use crate::renderer::{
+        dom::node::Element,
+        html::{parser::HtmlParser, token::HtmlTokenizer},
+    };
+
+    fn element(tag: &str) -> Rc<RefCell<Node>> {
+        Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
+            tag,
+            alloc::vec::Vec::new(),
+        )))))
+    }
+
+    fn inner_html(context: &str, html: &str) -> String {
+        let context = element(context);
+        let children = HtmlParser::parse_fragment(&context, html.to_string());
+        children.iter().map(serialize_node).collect()
+    }
+
+    fn parse(html: &str) -> Rc<RefCell<Node>> {
+        let window = HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree();
+        let document = window.borrow().document();
+        document
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let html = r#"<p class="a">x &amp; y<br><img src="b.png" alt="&quot;q&quot;"></p><!--c-->"#;
+        assert_eq!(inner_html("div", html), html);
+    }
+
+    #[test]
+    fn test_serialize_document() {
+        let html = "<!DOCTYPE html><html lang=\"en\"><head><title>a &amp; b</title></head><body><p>x<br>y</p><textarea>&lt;p&gt;</textarea></body></html>";
+        let document = parse(html);
+        assert_eq!(serialize_children(&document), html);
+        // what is left out of the source is there once parsed
+        assert_eq!(
+            serialize_children(&parse("<!DOCTYPE html><p>a<li>b")),
+            "<!DOCTYPE html><html><head></head><body><p>a</p><li>b</li></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_outer_html() {
+        let document = parse("<!DOCTYPE html><body><ul id=list><li>a<li>b</ul>");
+        let html = document.borrow().last_child().upgrade().unwrap();
+        let body = html.borrow().last_child().upgrade().unwrap();
+        let ul = body.borrow().first_child().unwrap();
+        assert_eq!(
+            serialize_node(&ul),
+            "<ul id=\"list\"><li>a</li><li>b</li></ul>"
+        );
+        assert_eq!(serialize_children(&ul), "<li>a</li><li>b</li>");
+        let img = element("img");
+        assert_eq!(serialize_node(&img), "<img>");
+    }
+
+    #[test]
+    fn test_template_contents() {
+        let html = "<template><tr><td>a</td></tr></template>";
+        assert_eq!(inner_html("div", html), html);
+    }
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(
+            inner_html("div", "<p title='a&lt;b'>1 &lt; 2&nbsp;</p>"),
+            "<p title=\"a&lt;b\">1 &lt; 2&nbsp;</p>"
+        );
+        // the text of a script is kept as it is
+        assert_eq!(
+            inner_html("div", "<script>if (a < b && c) {}</script>"),
+            "<script>if (a < b && c) {}</script>"
+        );
+    }
+}
@@ -1,5 +1,9 @@
-use crate::renderer::html::attribute::Attribute;
-use alloc::{string::String, vec::Vec};
+use crate::renderer::html::{
+    attribute::Attribute,
+    entities,
+    parse_error::{ParseError, ParseErrorKind, SourcePosition, SourceSpan},
+};
+use alloc::{collections::VecDeque, string::String, vec::Vec};
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum HtmlToken {
@@ -12,12 +16,60 @@ pub enum HtmlToken {
         tag: String,
     },
     Char(char),
+    Comment(String),
+    /// A DOCTYPE. A missing name or identifier is `None`, which is different
+    /// from an empty one.
+    Doctype {
+        name: Option<String>,
+        public_id: Option<String>,
+        system_id: Option<String>,
+        force_quirks: bool,
+    },
     Eof,
 }
 
-#[derive(PartialEq)]
+/// A token and the part of the source it came from. A character produced by a
+/// character reference spans the whole reference.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SpannedToken {
+    token: HtmlToken,
+    span: SourceSpan,
+}
+
+impl SpannedToken {
+    pub fn token(&self) -> HtmlToken {
+        self.token.clone()
+    }
+
+    pub fn span(&self) -> SourceSpan {
+        self.span
+    }
+}
+
+/// The content models the tree builder can switch the tokenizer to, depending
+/// on the element it has just inserted.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TokenizerState {
+    Data,
+    /// Text with character references but no tags, as in `<title>` and
+    /// `<textarea>`.
+    Rcdata,
+    /// Text without character references or tags, as in `<style>`.
+    Rawtext,
+    ScriptData,
+    /// Everything up to the end of the input is text.
+    Plaintext,
+    /// The content of `<![CDATA[`, which is text up to `]]>`.
+    CdataSection,
+}
+
+#[derive(PartialEq, Clone, Copy)]
 enum State {
     Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
     TagOpen,
     EndTagOpen,
     TagName,
@@ -30,42 +82,421 @@ enum State {
     AttributeValueUnquoted,
     AfterAttributeValueQuoted,
     SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
+    RcdataLessThanSign,
+    RcdataEndTagOpen,
+    RcdataEndTagName,
+    RawtextLessThanSign,
+    RawtextEndTagOpen,
+    RawtextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+}
+
+/// The characters that numeric references to the C1 control range stand for,
+/// as if they were windows-1252 bytes.
+fn c1_replacement(code: u32) -> Option<char> {
+    let c = match code {
+        0x80 => '\u{20AC}',
+        0x82 => '\u{201A}',
+        0x83 => '\u{0192}',
+        0x84 => '\u{201E}',
+        0x85 => '\u{2026}',
+        0x86 => '\u{2020}',
+        0x87 => '\u{2021}',
+        0x88 => '\u{02C6}',
+        0x89 => '\u{2030}',
+        0x8A => '\u{0160}',
+        0x8B => '\u{2039}',
+        0x8C => '\u{0152}',
+        0x8E => '\u{017D}',
+        0x91 => '\u{2018}',
+        0x92 => '\u{2019}',
+        0x93 => '\u{201C}',
+        0x94 => '\u{201D}',
+        0x95 => '\u{2022}',
+        0x96 => '\u{2013}',
+        0x97 => '\u{2014}',
+        0x98 => '\u{02DC}',
+        0x99 => '\u{2122}',
+        0x9A => '\u{0161}',
+        0x9B => '\u{203A}',
+        0x9C => '\u{0153}',
+        0x9E => '\u{017E}',
+        0x9F => '\u{0178}',
+        _ => return None,
+    };
+    Some(c)
+}
+
+pub(super) fn is_whitespace(c: char) -> bool {
+    c == '\t' || c == '\n' || c == '\x0C' || c == ' '
+}
+
+fn is_noncharacter(code: u32) -> bool {
+    (0xFDD0..=0xFDEF).contains(&code) || code & 0xFFFE == 0xFFFE
+}
+
+/// Controls other than NUL and whitespace, which are errors in the input and
+/// in numeric character references.
+fn is_unexpected_control(code: u32) -> bool {
+    let is_control = code < 0x20 || (0x7F..=0x9F).contains(&code);
+    is_control && code != 0 && !char::from_u32(code).is_some_and(is_whitespace)
 }
 
+/// Tokenizes a document that can arrive in chunks. Fed input is tokenized as
+/// far as it can be; `next` returns `None` when more input is needed, and
+/// only means the end of the document after `finish`.
 pub struct HtmlTokenizer {
+    /// The input that hasn't been consumed yet, plus the character before it
+    /// so that it can be reconsumed.
     input: Vec<char>,
+    /// The offset in the document of `input[0]`.
+    base: usize,
     pos: usize,
+    /// Bytes at the end of the last chunk that don't make up a character
+    /// yet.
+    undecoded: Vec<u8>,
+    /// Whether the last character fed was `\r`, so that a `\n` right after
+    /// it, even in the next chunk, is dropped.
+    last_was_cr: bool,
+    finished: bool,
     state: State,
     re_consume: bool,
     last_token: Option<HtmlToken>,
+    /// Tokens that are already complete but not returned yet, for the states
+    /// that emit more than one token at once.
+    pending_tokens: VecDeque<SpannedToken>,
+    /// The state to go back to after a character reference.
+    return_state: State,
+    temporary_buffer: String,
+    character_reference_code: u32,
+    /// The name of the last start tag emitted, to tell which end tag closes
+    /// RCDATA, RAWTEXT and script data.
+    last_start_tag_name: String,
+    /// The offsets where each line starts, to turn offsets into positions.
+    line_starts: Vec<usize>,
+    /// The offset where the token being built starts.
+    token_start: usize,
+    /// Whether the end tag being built had attributes or a trailing `/`,
+    /// which are dropped from the token but are still errors.
+    end_tag_has_attributes: bool,
+    end_tag_has_trailing_solidus: bool,
+    /// Whether `<![CDATA[` starts a CDATA section, which it only does in SVG
+    /// and MathML content.
+    cdata_allowed: bool,
+    errors: Vec<ParseError>,
 }
 
 impl HtmlTokenizer {
+    /// Creates a tokenizer for a whole document.
     pub fn new(html: String) -> Self {
+        let mut tokenizer = Self::streaming();
+        tokenizer.feed(&html);
+        tokenizer.finish();
+        tokenizer
+    }
+
+    /// Creates a tokenizer without any input yet, to be given the document
+    /// with `feed` as it arrives.
+    pub fn streaming() -> Self {
         Self {
-            input: html.chars().collect(),
+            input: Vec::new(),
+            base: 0,
             pos: 0,
+            undecoded: Vec::new(),
+            last_was_cr: false,
+            finished: false,
             state: State::Data,
             re_consume: false,
             last_token: None,
+            pending_tokens: VecDeque::new(),
+            return_state: State::Data,
+            temporary_buffer: String::new(),
+            character_reference_code: 0,
+            last_start_tag_name: String::new(),
+            line_starts: Vec::from([0]),
+            token_start: 0,
+            end_tag_has_attributes: false,
+            end_tag_has_trailing_solidus: false,
+            cdata_allowed: false,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Adds the next chunk of the document.
+    pub fn feed(&mut self, chunk: &str) {
+        // Drop what has been consumed, except the character that may be
+        // reconsumed.
+        if self.pos > 1 {
+            let consumed = self.pos - 1;
+            self.input.drain(..consumed);
+            self.base += consumed;
+            self.pos -= consumed;
+        }
+
+        for c in chunk.chars() {
+            // Line breaks are normalized to `\n` before tokenizing.
+            if self.last_was_cr && c == '\n' {
+                self.last_was_cr = false;
+                continue;
+            }
+            self.last_was_cr = c == '\r';
+            let c = if c == '\r' { '\n' } else { c };
+
+            self.input.push(c);
+            if c == '\n' {
+                self.line_starts.push(self.base + self.input.len());
+            }
         }
     }
 
-    fn consume_next_input(&mut self) -> char {
-        let c = self.input[self.pos];
-        self.pos += 1;
-        c
+    /// Adds the next chunk of the document as UTF-8 bytes. A character may be
+    /// split between chunks; invalid bytes become U+FFFD.
+    pub fn feed_bytes(&mut self, bytes: &[u8]) {
+        self.undecoded.extend_from_slice(bytes);
+        let undecoded = core::mem::take(&mut self.undecoded);
+
+        let mut rest = undecoded.as_slice();
+        let mut decoded = String::new();
+        loop {
+            match core::str::from_utf8(rest) {
+                Ok(s) => {
+                    decoded.push_str(s);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    // `valid` has just been checked
+                    decoded.push_str(core::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        Some(len) => {
+                            decoded.push('\u{FFFD}');
+                            rest = &invalid[len..];
+                        }
+                        // the character continues in the next chunk
+                        None => {
+                            self.undecoded = invalid.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.feed(&decoded);
     }
 
-    fn re_consume_input(&mut self) -> char {
+    /// Marks the end of the document.
+    pub fn finish(&mut self) {
+        if !self.undecoded.is_empty() {
+            self.undecoded.clear();
+            self.feed("\u{FFFD}");
+        }
+        self.finished = true;
+    }
+
+    /// Returns true if telling what the input from `start` means needs
+    /// `len` characters but fewer have been fed so far.
+    fn waits_for_input(&self, start: usize, len: usize) -> bool {
+        !self.finished && self.input.len() < start + len
+    }
+
+    /// Returns the parse errors found so far, in the order of the input.
+    pub fn errors(&self) -> Vec<ParseError> {
+        self.errors.clone()
+    }
+
+    fn source_position(&self, offset: usize) -> SourcePosition {
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        let column = offset - self.line_starts[line - 1] + 1;
+        SourcePosition::new(line, column, offset)
+    }
+
+    /// Reports an error at the character just consumed, or at the end of the
+    /// input.
+    fn error(&mut self, kind: ParseErrorKind) {
+        let offset = self.base + self.pos.saturating_sub(1).min(self.input.len());
+        self.errors
+            .push(ParseError::new(kind, self.source_position(offset)));
+    }
+
+    /// Returns the span from the start of the current token to the last
+    /// character that belongs to it.
+    fn current_span(&self) -> SourceSpan {
+        let end = if self.re_consume {
+            self.pos - 1
+        } else {
+            self.pos
+        };
+        let end = self.base + end.min(self.input.len());
+        SourceSpan::new(
+            self.source_position(self.token_start),
+            self.source_position(end),
+        )
+    }
+
+    fn emit(&mut self, token: HtmlToken) -> Option<SpannedToken> {
+        Some(SpannedToken {
+            token,
+            span: self.current_span(),
+        })
+    }
+
+    fn push_pending(&mut self, token: HtmlToken) {
+        let span = self.current_span();
+        self.pending_tokens.push_back(SpannedToken { token, span });
+    }
+
+    /// Returns true in the states where each character may be a token of its
+    /// own, so that the next token starts at the next character.
+    fn starts_token(&self) -> bool {
+        matches!(
+            self.state,
+            State::Data
+                | State::Rcdata
+                | State::Rawtext
+                | State::ScriptData
+                | State::Plaintext
+                | State::CdataSection
+                | State::ScriptDataEscapeStart
+                | State::ScriptDataEscapeStartDash
+                | State::ScriptDataEscaped
+                | State::ScriptDataEscapedDash
+                | State::ScriptDataEscapedDashDash
+                | State::ScriptDataDoubleEscapeStart
+                | State::ScriptDataDoubleEscaped
+                | State::ScriptDataDoubleEscapedDash
+                | State::ScriptDataDoubleEscapedDashDash
+                | State::ScriptDataDoubleEscapedLessThanSign
+                | State::ScriptDataDoubleEscapeEnd
+                | State::AmbiguousAmpersand
+        )
+    }
+
+    /// Sets the name of the last start tag, which decides the end tag that
+    /// ends text content. For tokenizing text content without its start tag.
+    pub fn set_last_start_tag(&mut self, tag: &str) {
+        self.last_start_tag_name = String::from(tag);
+    }
+
+    /// Switches to the content model of the element the tree builder has just
+    /// inserted. Takes effect from the next token.
+    pub fn switch_to(&mut self, state: TokenizerState) {
+        self.state = match state {
+            TokenizerState::Data => State::Data,
+            TokenizerState::Rcdata => State::Rcdata,
+            TokenizerState::Rawtext => State::Rawtext,
+            TokenizerState::ScriptData => State::ScriptData,
+            TokenizerState::Plaintext => State::Plaintext,
+            TokenizerState::CdataSection => State::CdataSection,
+        };
+    }
+
+    /// Tells whether the tree builder is in SVG or MathML content, where
+    /// `<![CDATA[` starts a CDATA section instead of a bogus comment.
+    pub fn set_cdata_allowed(&mut self, allowed: bool) {
+        self.cdata_allowed = allowed;
+    }
+
+    /// Returns the next input character, or `None` at the end of the input.
+    fn consume_next_input(&mut self) -> Option<char> {
+        if self.pos < self.input.len() {
+            let c = self.input[self.pos];
+            self.pos += 1;
+            if is_unexpected_control(c as u32) {
+                self.error(ParseErrorKind::ControlCharacterInInputStream);
+            } else if is_noncharacter(c as u32) {
+                self.error(ParseErrorKind::NoncharacterInInputStream);
+            }
+            Some(c)
+        } else {
+            // Remember that the end of the input itself has been consumed, so
+            // that it can be reconsumed.
+            self.pos = self.input.len() + 1;
+            None
+        }
+    }
+
+    fn re_consume_input(&mut self) -> Option<char> {
         self.re_consume = false;
-        self.input[self.pos - 1]
+        if self.is_eof() {
+            None
+        } else {
+            Some(self.input[self.pos - 1])
+        }
     }
 
     fn is_eof(&self) -> bool {
         self.pos > self.input.len()
     }
 
+    /// Returns true if the input from `start` matches `s`, ignoring ASCII
+    /// case.
+    fn input_matches(&self, start: usize, s: &str) -> bool {
+        let mut pos = start;
+        for expected in s.chars() {
+            match self.input.get(pos) {
+                Some(c) if c.eq_ignore_ascii_case(&expected) => pos += 1,
+                _ => return false,
+            }
+        }
+        true
+    }
+
     fn create_start_tag(&mut self) {
         self.last_token = Some(HtmlToken::StartTag {
             tag: String::new(),
@@ -78,324 +509,1432 @@ impl HtmlTokenizer {
         self.last_token = Some(HtmlToken::EndTag { tag: String::new() })
     }
 
-    fn take_last_token(&mut self) -> HtmlToken {
-        assert!(self.last_token.is_some());
+    fn create_comment(&mut self) {
+        self.last_token = Some(HtmlToken::Comment(String::new()))
+    }
+
+    fn create_doctype(&mut self) {
+        self.last_token = Some(HtmlToken::Doctype {
+            name: None,
+            public_id: None,
+            system_id: None,
+            force_quirks: false,
+        })
+    }
+
+    fn emit_last_token(&mut self) -> Option<SpannedToken> {
+        let mut token = self.last_token.take()?;
+        match token {
+            HtmlToken::StartTag {
+                ref tag,
+                self_closing: _,
+                ref mut attributes,
+            } => {
+                self.last_start_tag_name = tag.clone();
+                // Only the first of attributes with the same name is kept.
+                let mut names: Vec<String> = Vec::new();
+                attributes.retain(|a| {
+                    let is_first = !names.contains(&a.name());
+                    names.push(a.name());
+                    is_first
+                });
+            }
+            HtmlToken::EndTag { tag: _ } => {
+                if core::mem::take(&mut self.end_tag_has_attributes) {
+                    self.error(ParseErrorKind::EndTagWithAttributes);
+                }
+                if core::mem::take(&mut self.end_tag_has_trailing_solidus) {
+                    self.error(ParseErrorKind::EndTagWithTrailingSolidus);
+                }
+            }
+            _ => {}
+        }
+        self.emit(token)
+    }
+
+    /// Returns true if the end tag being built closes the last start tag.
+    fn is_appropriate_end_tag(&self) -> bool {
+        match self.last_token {
+            Some(HtmlToken::EndTag { ref tag }) => *tag == self.last_start_tag_name,
+            _ => false,
+        }
+    }
 
-        let token = self.last_token.clone().unwrap();
+    /// Gives up on an end tag in text content: what looked like it is
+    /// emitted as text and `c` is reconsumed in `text_state`.
+    fn emit_end_tag_as_text(&mut self, text_state: State) {
         self.last_token = None;
-        token
+        self.end_tag_has_attributes = false;
+        self.end_tag_has_trailing_solidus = false;
+        self.push_pending(HtmlToken::Char('<'));
+        self.push_pending(HtmlToken::Char('/'));
+        let buffer = core::mem::take(&mut self.temporary_buffer);
+        for c in buffer.chars() {
+            self.push_pending(HtmlToken::Char(c));
+        }
+        self.re_consume = true;
+        self.state = text_state;
+    }
+
+    /// The end tag name states of RCDATA, RAWTEXT and script data, which only
+    /// end the text when the tag closes the element the text is in.
+    fn text_end_tag_name(&mut self, c: Option<char>, text_state: State) -> Option<SpannedToken> {
+        match c {
+            Some(c) if is_whitespace(c) && self.is_appropriate_end_tag() => {
+                self.state = State::BeforeAttributeName
+            }
+            Some('/') if self.is_appropriate_end_tag() => self.state = State::SelfClosingStartTag,
+            Some('>') if self.is_appropriate_end_tag() => {
+                self.state = State::Data;
+                return self.emit_last_token();
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.append_tag_name(c.to_ascii_lowercase());
+                self.temporary_buffer.push(c);
+            }
+            _ => self.emit_end_tag_as_text(text_state),
+        }
+        None
     }
 
+    // The helpers below change the token being built. The states only call
+    // them while building a token of the right kind; if that were ever not
+    // the case, the change is dropped rather than crashing on bad input.
+
     fn append_tag_name(&mut self, c: char) {
-        assert!(self.last_token.is_some());
+        if let Some(
+            HtmlToken::StartTag {
+                ref mut tag,
+                self_closing: _,
+                attributes: _,
+            }
+            | HtmlToken::EndTag { ref mut tag },
+        ) = self.last_token
+        {
+            tag.push(c);
+        }
+    }
 
-        if let Some(token) = self.last_token.as_mut() {
-            match token {
-                HtmlToken::StartTag {
-                    ref mut tag,
-                    self_closing: _,
-                    attributes: _,
+    fn set_self_closing_flag(&mut self) {
+        match self.last_token {
+            Some(HtmlToken::StartTag {
+                tag: _,
+                ref mut self_closing,
+                attributes: _,
+            }) => *self_closing = true,
+            // an end tag can't be self-closing, the flag is ignored
+            Some(HtmlToken::EndTag { tag: _ }) => self.end_tag_has_trailing_solidus = true,
+            _ => {}
+        }
+    }
+
+    fn start_new_attribute(&mut self) {
+        match self.last_token {
+            Some(HtmlToken::StartTag {
+                tag: _,
+                self_closing: _,
+                ref mut attributes,
+            }) => attributes.push(Attribute::new()),
+            // attributes on an end tag are dropped
+            Some(HtmlToken::EndTag { tag: _ }) => self.end_tag_has_attributes = true,
+            _ => {}
+        }
+    }
+
+    fn append_attribute_char(&mut self, c: char, is_name: bool) {
+        if let Some(HtmlToken::StartTag {
+            tag: _,
+            self_closing: _,
+            ref mut attributes,
+        }) = self.last_token
+        {
+            if let Some(attribute) = attributes.last_mut() {
+                if is_name {
+                    attribute.add_name_char(c);
+                } else {
+                    attribute.add_value_char(c);
                 }
-                | HtmlToken::EndTag { ref mut tag } => {
-                    tag.push(c);
+            }
+        }
+    }
+
+    /// Reports the attribute whose name has just been read if an earlier one
+    /// has the same name. It is removed when the tag is emitted.
+    fn check_duplicate_attribute(&mut self) {
+        if let Some(HtmlToken::StartTag {
+            tag: _,
+            self_closing: _,
+            ref attributes,
+        }) = self.last_token
+        {
+            if let Some((last, others)) = attributes.split_last() {
+                if others.iter().any(|a| a.name() == last.name()) {
+                    self.error(ParseErrorKind::DuplicateAttribute);
                 }
-                _ => panic!("`last_token` should be either StartTag or EndTag"),
             }
         }
     }
 
-    fn set_self_closing_flag(&mut self) {
-        assert!(self.last_token.is_some());
+    fn append_comment(&mut self, s: &str) {
+        if let Some(HtmlToken::Comment(ref mut data)) = self.last_token {
+            data.push_str(s);
+        }
+    }
 
-        if let Some(token) = self.last_token.as_mut() {
-            match token {
-                HtmlToken::StartTag {
-                    tag: _,
-                    ref mut self_closing,
-                    attributes: _,
-                } => *self_closing = true,
-                _ => panic!("`last_token` should be either StartTag"),
+    fn append_comment_char(&mut self, c: char) {
+        let mut s = [0u8; 4];
+        self.append_comment(c.encode_utf8(&mut s));
+    }
+
+    fn set_force_quirks(&mut self) {
+        if let Some(HtmlToken::Doctype {
+            ref mut force_quirks,
+            ..
+        }) = self.last_token
+        {
+            *force_quirks = true;
+        }
+    }
+
+    fn append_doctype_name(&mut self, c: char) {
+        if let Some(HtmlToken::Doctype { ref mut name, .. }) = self.last_token {
+            name.get_or_insert_with(String::new).push(c);
+        }
+    }
+
+    /// Starts an empty public identifier, or a system identifier when
+    /// `is_public` is false.
+    fn start_doctype_identifier(&mut self, is_public: bool) {
+        if let Some(HtmlToken::Doctype {
+            ref mut public_id,
+            ref mut system_id,
+            ..
+        }) = self.last_token
+        {
+            if is_public {
+                *public_id = Some(String::new());
+            } else {
+                *system_id = Some(String::new());
             }
         }
     }
 
-    fn start_new_attribute(&mut self) {
-        assert!(self.last_token.is_some());
+    fn append_doctype_identifier(&mut self, c: char, is_public: bool) {
+        if let Some(HtmlToken::Doctype {
+            ref mut public_id,
+            ref mut system_id,
+            ..
+        }) = self.last_token
+        {
+            let id = if is_public { public_id } else { system_id };
+            id.get_or_insert_with(String::new).push(c);
+        }
+    }
 
-        if let Some(token) = self.last_token.as_mut() {
-            match token {
-                HtmlToken::StartTag {
-                    tag: _,
-                    self_closing: _,
-                    ref mut attributes,
-                } => {
-                    attributes.push(Attribute::new());
-                }
-                _ => panic!("`last_token` should be either StartTag"),
+    /// Emits the DOCTYPE being built with its force-quirks flag set, as done
+    /// when the input ends inside it.
+    fn emit_quirks_doctype(&mut self) -> Option<SpannedToken> {
+        self.set_force_quirks();
+        self.emit_last_token()
+    }
+
+    fn is_in_attribute_value(&self) -> bool {
+        self.return_state == State::AttributeValueDoubleQuoted
+            || self.return_state == State::AttributeValueSingleQuoted
+            || self.return_state == State::AttributeValueUnquoted
+    }
+
+    /// Starts a character reference at `&`, coming back to the current state
+    /// afterwards.
+    fn start_character_reference(&mut self) {
+        self.return_state = self.state;
+        self.state = State::CharacterReference;
+        self.temporary_buffer = String::from("&");
+    }
+
+    /// Adds the characters of the temporary buffer to the attribute value, or
+    /// emits them as text, and goes back to the return state.
+    fn flush_character_reference(&mut self) {
+        let buffer = core::mem::take(&mut self.temporary_buffer);
+        if self.is_in_attribute_value() {
+            for c in buffer.chars() {
+                self.append_attribute_char(c, false);
+            }
+        } else {
+            for c in buffer.chars() {
+                self.push_pending(HtmlToken::Char(c));
             }
         }
+        self.state = self.return_state;
     }
 
-    fn append_attribute_char(&mut self, c: char, is_name: bool) {
-        assert!(self.last_token.is_some());
+    /// Consumes the longest named character reference that starts at the
+    /// character just consumed. Returns false, consuming nothing, if there is
+    /// none.
+    fn consume_named_character_reference(&mut self) -> bool {
+        let start = self.pos - 1;
+        let mut candidate = String::new();
+        for c in self.input[start..].iter() {
+            if candidate.len() >= entities::LONGEST_NAME_LENGTH {
+                break;
+            }
+            candidate.push(*c);
+            if !c.is_ascii_alphanumeric() {
+                break;
+            }
+        }
 
-        if let Some(token) = self.last_token.as_mut() {
-            match token {
-                HtmlToken::StartTag {
-                    tag: _,
-                    self_closing: _,
-                    ref mut attributes,
-                } => {
-                    let len = attributes.len();
-                    assert!(len > 0);
-
-                    if is_name {
-                        attributes[len - 1].add_name_char(c);
-                    } else {
-                        attributes[len - 1].add_value_char(c);
-                    }
+        while !candidate.is_empty() {
+            if let Some(value) = entities::lookup(&candidate) {
+                let next = self.input.get(start + candidate.len());
+                // For compatibility, `&copy=` or `&notit` in an attribute
+                // value stay as they are when the semicolon is missing.
+                if self.is_in_attribute_value()
+                    && !candidate.ends_with(';')
+                    && matches!(next, Some(c) if *c == '=' || c.is_ascii_alphanumeric())
+                {
+                    return false;
+                }
+
+                self.pos = start + candidate.len();
+                if !candidate.ends_with(';') {
+                    self.error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
                 }
-                _ => panic!("`last_token` should be either StartTag"),
+                self.temporary_buffer = String::from(value);
+                return true;
             }
+            candidate.pop();
         }
-    }
-}
 
-impl Iterator for HtmlTokenizer {
-    type Item = HtmlToken;
+        false
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.pos >= self.input.len() {
-            return None;
+    /// Returns the character that a numeric reference stands for.
+    fn numeric_character_reference(&mut self) -> char {
+        let code = self.character_reference_code;
+        let error = if code == 0 {
+            Some(ParseErrorKind::NullCharacterReference)
+        } else if code > 0x10FFFF {
+            Some(ParseErrorKind::CharacterReferenceOutsideUnicodeRange)
+        } else if (0xD800..=0xDFFF).contains(&code) {
+            Some(ParseErrorKind::SurrogateCharacterReference)
+        } else if is_noncharacter(code) {
+            Some(ParseErrorKind::NoncharacterCharacterReference)
+        } else if code == 0x0D || is_unexpected_control(code) {
+            Some(ParseErrorKind::ControlCharacterReference)
+        } else {
+            None
         };
+        if let Some(error) = error {
+            self.error(error);
+        }
+
+        if code == 0 || code > 0x10FFFF || (0xD800..=0xDFFF).contains(&code) {
+            return '\u{FFFD}';
+        }
+        if let Some(c) = c1_replacement(code) {
+            return c;
+        }
+        char::from_u32(code).unwrap_or('\u{FFFD}')
+    }
+}
 
+impl HtmlTokenizer {
+    /// Returns the next token along with the part of the source it came from.
+    pub fn next_spanned(&mut self) -> Option<SpannedToken> {
         loop {
+            if let Some(token) = self.pending_tokens.pop_front() {
+                return Some(token);
+            }
+
+            // This state looks ahead without consuming anything.
+            if self.state == State::MarkupDeclarationOpen {
+                if self.waits_for_input(self.pos, "[CDATA[".len()) {
+                    return None;
+                }
+
+                if self.input_matches(self.pos, "--") {
+                    self.pos += 2;
+                    self.create_comment();
+                    self.state = State::CommentStart;
+                } else if self.input_matches(self.pos, "doctype") {
+                    self.pos += 7;
+                    self.state = State::Doctype;
+                } else if self.input[self.pos..].starts_with(&['[', 'C', 'D', 'A', 'T', 'A', '['])
+                    && self.cdata_allowed
+                {
+                    self.pos += 7;
+                    self.state = State::CdataSection;
+                } else if self.input[self.pos..].starts_with(&['[', 'C', 'D', 'A', 'T', 'A', '[']) {
+                    // CDATA sections are only allowed in foreign content, in
+                    // HTML they are bogus comments.
+                    self.error(ParseErrorKind::CdataInHtmlContent);
+                    self.pos += 7;
+                    self.create_comment();
+                    self.append_comment("[CDATA[");
+                    self.state = State::BogusComment;
+                } else {
+                    self.error(ParseErrorKind::IncorrectlyOpenedComment);
+                    self.create_comment();
+                    self.state = State::BogusComment;
+                }
+                continue;
+            }
+
+            // This state doesn't consume anything either.
+            if self.state == State::NumericCharacterReferenceEnd {
+                let c = self.numeric_character_reference();
+                self.temporary_buffer = String::new();
+                self.temporary_buffer.push(c);
+                self.flush_character_reference();
+                continue;
+            }
+
+            if !self.re_consume && self.waits_for_input(self.pos, 1) {
+                return None;
+            }
+
+            if self.starts_token() {
+                self.token_start = self.base
+                    + if self.re_consume {
+                        self.pos - 1
+                    } else {
+                        self.pos
+                    };
+            }
+
             let c = match self.re_consume {
                 true => self.re_consume_input(),
                 false => self.consume_next_input(),
             };
 
             match self.state {
-                State::Data => {
-                    if c == '<' {
-                        self.state = State::TagOpen;
-                        continue;
+                State::Data => match c {
+                    Some('&') => self.start_character_reference(),
+                    Some('<') => self.state = State::TagOpen,
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        return self.emit(HtmlToken::Char('\0'));
                     }
-
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                    Some(c) => return self.emit(HtmlToken::Char(c)),
+                    None => return None,
+                },
+                State::Rcdata => match c {
+                    Some('&') => self.start_character_reference(),
+                    Some('<') => self.state = State::RcdataLessThanSign,
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        return self.emit(HtmlToken::Char('\u{FFFD}'));
+                    }
+                    Some(c) => return self.emit(HtmlToken::Char(c)),
+                    None => return None,
+                },
+                State::Rawtext => match c {
+                    Some('<') => self.state = State::RawtextLessThanSign,
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        return self.emit(HtmlToken::Char('\u{FFFD}'));
+                    }
+                    Some(c) => return self.emit(HtmlToken::Char(c)),
+                    None => return None,
+                },
+                State::ScriptData => match c {
+                    Some('<') => self.state = State::ScriptDataLessThanSign,
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        return self.emit(HtmlToken::Char('\u{FFFD}'));
+                    }
+                    Some(c) => return self.emit(HtmlToken::Char(c)),
+                    None => return None,
+                },
+                State::Plaintext => match c {
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        return self.emit(HtmlToken::Char('\u{FFFD}'));
+                    }
+                    Some(c) => return self.emit(HtmlToken::Char(c)),
+                    None => return None,
+                },
+                State::CdataSection => match c {
+                    Some(']') => self.state = State::CdataSectionBracket,
+                    Some(c) => return self.emit(HtmlToken::Char(c)),
+                    None => {
+                        self.error(ParseErrorKind::EofInCdata);
+                        return None;
+                    }
+                },
+                State::CdataSectionBracket => match c {
+                    Some(']') => self.state = State::CdataSectionEnd,
+                    _ => {
+                        self.re_consume = true;
+                        self.state = State::CdataSection;
+                        return self.emit(HtmlToken::Char(']'));
+                    }
+                },
+                State::CdataSectionEnd => match c {
+                    Some(']') => return self.emit(HtmlToken::Char(']')),
+                    Some('>') => self.state = State::Data,
+                    _ => {
+                        // the brackets were not the end after all
+                        self.re_consume = true;
+                        self.state = State::CdataSection;
+                        self.push_pending(HtmlToken::Char(']'));
+                        return self.emit(HtmlToken::Char(']'));
+                    }
+                },
+                State::RcdataLessThanSign
+                | State::RawtextLessThanSign
+                | State::ScriptDataLessThanSign => {
+                    let (text_state, end_tag_open) = match self.state {
+                        State::RcdataLessThanSign => (State::Rcdata, State::RcdataEndTagOpen),
+                        State::RawtextLessThanSign => (State::Rawtext, State::RawtextEndTagOpen),
+                        _ => (State::ScriptData, State::ScriptDataEndTagOpen),
+                    };
+                    match c {
+                        Some('/') => {
+                            self.temporary_buffer = String::new();
+                            self.state = end_tag_open;
+                        }
+                        Some('!') if text_state == State::ScriptData => {
+                            self.state = State::ScriptDataEscapeStart;
+                            self.push_pending(HtmlToken::Char('!'));
+                            return self.emit(HtmlToken::Char('<'));
+                        }
+                        _ => {
+                            self.re_consume = true;
+                            self.state = text_state;
+                            return self.emit(HtmlToken::Char('<'));
+                        }
+                    }
+                }
+                State::RcdataEndTagOpen
+                | State::RawtextEndTagOpen
+                | State::ScriptDataEndTagOpen
+                | State::ScriptDataEscapedEndTagOpen => {
+                    let (text_state, end_tag_name) = match self.state {
+                        State::RcdataEndTagOpen => (State::Rcdata, State::RcdataEndTagName),
+                        State::RawtextEndTagOpen => (State::Rawtext, State::RawtextEndTagName),
+                        State::ScriptDataEndTagOpen => {
+                            (State::ScriptData, State::ScriptDataEndTagName)
+                        }
+                        _ => (State::ScriptDataEscaped, State::ScriptDataEscapedEndTagName),
+                    };
+                    match c {
+                        Some(c) if c.is_ascii_alphabetic() => {
+                            self.create_end_tag();
+                            self.re_consume = true;
+                            self.state = end_tag_name;
+                        }
+                        _ => {
+                            self.re_consume = true;
+                            self.state = text_state;
+                            self.push_pending(HtmlToken::Char('/'));
+                            return self.emit(HtmlToken::Char('<'));
+                        }
+                    }
+                }
+                State::RcdataEndTagName => {
+                    if let Some(token) = self.text_end_tag_name(c, State::Rcdata) {
+                        return Some(token);
+                    }
+                }
+                State::RawtextEndTagName => {
+                    if let Some(token) = self.text_end_tag_name(c, State::Rawtext) {
+                        return Some(token);
+                    }
+                }
+                State::ScriptDataEndTagName => {
+                    if let Some(token) = self.text_end_tag_name(c, State::ScriptData) {
+                        return Some(token);
+                    }
+                }
+                State::ScriptDataEscapedEndTagName => {
+                    if let Some(token) = self.text_end_tag_name(c, State::ScriptDataEscaped) {
+                        return Some(token);
+                    }
+                }
+                State::ScriptDataEscapeStart | State::ScriptDataEscapeStartDash => match c {
+                    Some('-') => {
+                        self.state = if self.state == State::ScriptDataEscapeStart {
+                            State::ScriptDataEscapeStartDash
+                        } else {
+                            State::ScriptDataEscapedDashDash
+                        };
+                        return self.emit(HtmlToken::Char('-'));
+                    }
+                    _ => {
+                        self.re_consume = true;
+                        self.state = State::ScriptData;
+                    }
+                },
+                State::ScriptDataEscaped
+                | State::ScriptDataEscapedDash
+                | State::ScriptDataEscapedDashDash => match c {
+                    Some('-') => {
+                        self.state = match self.state {
+                            State::ScriptDataEscaped => State::ScriptDataEscapedDash,
+                            _ => State::ScriptDataEscapedDashDash,
+                        };
+                        return self.emit(HtmlToken::Char('-'));
+                    }
+                    Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                    // `-->` ends the escaped section
+                    Some('>') if self.state == State::ScriptDataEscapedDashDash => {
+                        self.state = State::ScriptData;
+                        return self.emit(HtmlToken::Char('>'));
+                    }
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.state = State::ScriptDataEscaped;
+                        return self.emit(HtmlToken::Char('\u{FFFD}'));
+                    }
+                    Some(c) => {
+                        self.state = State::ScriptDataEscaped;
+                        return self.emit(HtmlToken::Char(c));
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofInScriptHtmlCommentLikeText);
+                        return None;
+                    }
+                },
+                State::ScriptDataEscapedLessThanSign => match c {
+                    Some('/') => {
+                        self.temporary_buffer = String::new();
+                        self.state = State::ScriptDataEscapedEndTagOpen;
+                    }
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        self.temporary_buffer = String::new();
+                        self.re_consume = true;
+                        self.state = State::ScriptDataDoubleEscapeStart;
+                        return self.emit(HtmlToken::Char('<'));
+                    }
+                    _ => {
+                        self.re_consume = true;
+                        self.state = State::ScriptDataEscaped;
+                        return self.emit(HtmlToken::Char('<'));
+                    }
+                },
+                State::ScriptDataDoubleEscapeStart | State::ScriptDataDoubleEscapeEnd => {
+                    // `<script>` inside an escaped section starts a double
+                    // escaped one, and `</script>` ends it.
+                    let (matched, unmatched) = if self.state == State::ScriptDataDoubleEscapeStart {
+                        (State::ScriptDataDoubleEscaped, State::ScriptDataEscaped)
+                    } else {
+                        (State::ScriptDataEscaped, State::ScriptDataDoubleEscaped)
+                    };
+                    match c {
+                        Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                            self.state = if self.temporary_buffer == "script" {
+                                matched
+                            } else {
+                                unmatched
+                            };
+                            return self.emit(HtmlToken::Char(c));
+                        }
+                        Some(c) if c.is_ascii_alphabetic() => {
+                            self.temporary_buffer.push(c.to_ascii_lowercase());
+                            return self.emit(HtmlToken::Char(c));
+                        }
+                        _ => {
+                            self.re_consume = true;
+                            self.state = unmatched;
+                        }
+                    }
+                }
+                State::ScriptDataDoubleEscaped
+                | State::ScriptDataDoubleEscapedDash
+                | State::ScriptDataDoubleEscapedDashDash => match c {
+                    Some('-') => {
+                        self.state = match self.state {
+                            State::ScriptDataDoubleEscaped => State::ScriptDataDoubleEscapedDash,
+                            _ => State::ScriptDataDoubleEscapedDashDash,
+                        };
+                        return self.emit(HtmlToken::Char('-'));
+                    }
+                    Some('<') => {
+                        self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                        return self.emit(HtmlToken::Char('<'));
+                    }
+                    Some('>') if self.state == State::ScriptDataDoubleEscapedDashDash => {
+                        self.state = State::ScriptData;
+                        return self.emit(HtmlToken::Char('>'));
+                    }
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.state = State::ScriptDataDoubleEscaped;
+                        return self.emit(HtmlToken::Char('\u{FFFD}'));
+                    }
+                    Some(c) => {
+                        self.state = State::ScriptDataDoubleEscaped;
+                        return self.emit(HtmlToken::Char(c));
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofInScriptHtmlCommentLikeText);
+                        return None;
+                    }
+                },
+                State::ScriptDataDoubleEscapedLessThanSign => match c {
+                    Some('/') => {
+                        self.temporary_buffer = String::new();
+                        self.state = State::ScriptDataDoubleEscapeEnd;
+                        return self.emit(HtmlToken::Char('/'));
+                    }
+                    _ => {
+                        self.re_consume = true;
+                        self.state = State::ScriptDataDoubleEscaped;
+                    }
+                },
+                State::TagOpen => match c {
+                    Some('!') => self.state = State::MarkupDeclarationOpen,
+                    Some('/') => self.state = State::EndTagOpen,
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        self.re_consume = true;
+                        self.state = State::TagName;
+                        self.create_start_tag();
+                    }
+                    Some('?') => {
+                        self.error(ParseErrorKind::UnexpectedQuestionMarkInsteadOfTagName);
+                        self.re_consume = true;
+                        self.state = State::BogusComment;
+                        self.create_comment();
+                    }
+                    Some(_) => {
+                        // not a tag, `<` is text
+                        self.error(ParseErrorKind::InvalidFirstCharacterOfTagName);
+                        self.re_consume = true;
+                        self.state = State::Data;
+                        return self.emit(HtmlToken::Char('<'));
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofBeforeTagName);
+                        self.re_consume = true;
+                        self.state = State::Data;
+                        return self.emit(HtmlToken::Char('<'));
+                    }
+                },
+                State::EndTagOpen => match c {
+                    Some(c) if c.is_ascii_alphabetic() => {
+                        self.re_consume = true;
+                        self.state = State::TagName;
+                        self.create_end_tag();
+                    }
+                    // `</>` is dropped
+                    Some('>') => {
+                        self.error(ParseErrorKind::MissingEndTagName);
+                        self.state = State::Data;
+                    }
+                    Some(_) => {
+                        self.error(ParseErrorKind::InvalidFirstCharacterOfTagName);
+                        self.re_consume = true;
+                        self.state = State::BogusComment;
+                        self.create_comment();
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofBeforeTagName);
+                        self.state = State::Data;
+                        self.push_pending(HtmlToken::Char('/'));
+                        return self.emit(HtmlToken::Char('<'));
+                    }
+                },
+                State::TagName => match c {
+                    Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                    Some('/') => self.state = State::SelfClosingStartTag,
+                    Some('>') => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_tag_name('\u{FFFD}');
+                    }
+                    Some(c) => self.append_tag_name(c.to_ascii_lowercase()),
+                    None => {
+                        self.error(ParseErrorKind::EofInTag);
+                        return None;
+                    }
+                },
+                State::BeforeAttributeName => match c {
+                    Some(c) if is_whitespace(c) => {}
+                    Some('/') | Some('>') | None => {
+                        self.re_consume = true;
+                        self.state = State::AfterAttributeName;
+                    }
+                    Some('=') => {
+                        // `=` starts the name of the attribute
+                        self.error(ParseErrorKind::UnexpectedEqualsSignBeforeAttributeName);
+                        self.start_new_attribute();
+                        self.append_attribute_char('=', true);
+                        self.state = State::AttributeName;
+                    }
+                    Some(_) => {
+                        self.re_consume = true;
+                        self.state = State::AttributeName;
+                        self.start_new_attribute();
+                    }
+                },
+                State::AttributeName => match c {
+                    Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                        self.check_duplicate_attribute();
+                        self.re_consume = true;
+                        self.state = State::AfterAttributeName;
+                    }
+                    None => {
+                        self.check_duplicate_attribute();
+                        self.re_consume = true;
+                        self.state = State::AfterAttributeName;
+                    }
+                    Some('=') => {
+                        self.check_duplicate_attribute();
+                        self.state = State::BeforeAttributeValue;
+                    }
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_attribute_char('\u{FFFD}', true);
+                    }
+                    Some(c) => {
+                        if c == '"' || c == '\'' || c == '<' {
+                            self.error(ParseErrorKind::UnexpectedCharacterInAttributeName);
+                        }
+                        self.append_attribute_char(c.to_ascii_lowercase(), true);
+                    }
+                },
+                State::AfterAttributeName => match c {
+                    Some(c) if is_whitespace(c) => {}
+                    Some('/') => self.state = State::SelfClosingStartTag,
+                    Some('=') => self.state = State::BeforeAttributeValue,
+                    Some('>') => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some(_) => {
+                        self.re_consume = true;
+                        self.state = State::AttributeName;
+                        self.start_new_attribute();
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofInTag);
+                        return None;
+                    }
+                },
+                State::BeforeAttributeValue => match c {
+                    Some(c) if is_whitespace(c) => {}
+                    Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                    Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                    Some('>') => {
+                        self.error(ParseErrorKind::MissingAttributeValue);
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    _ => {
+                        self.re_consume = true;
+                        self.state = State::AttributeValueUnquoted;
+                    }
+                },
+                State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                    let quote = if self.state == State::AttributeValueDoubleQuoted {
+                        '"'
+                    } else {
+                        '\''
+                    };
+                    match c {
+                        Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+                        Some('&') => self.start_character_reference(),
+                        Some('\0') => {
+                            self.error(ParseErrorKind::UnexpectedNullCharacter);
+                            self.append_attribute_char('\u{FFFD}', false);
+                        }
+                        Some(c) => self.append_attribute_char(c, false),
+                        None => {
+                            self.error(ParseErrorKind::EofInTag);
+                            return None;
+                        }
+                    }
+                }
+                State::AttributeValueUnquoted => match c {
+                    Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                    Some('&') => self.start_character_reference(),
+                    Some('>') => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_attribute_char('\u{FFFD}', false);
+                    }
+                    Some(c) => {
+                        if matches!(c, '"' | '\'' | '<' | '=' | '`') {
+                            self.error(ParseErrorKind::UnexpectedCharacterInUnquotedAttributeValue);
+                        }
+                        self.append_attribute_char(c, false);
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofInTag);
+                        return None;
+                    }
+                },
+                State::AfterAttributeValueQuoted => match c {
+                    Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                    Some('/') => self.state = State::SelfClosingStartTag,
+                    Some('>') => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some(_) => {
+                        self.error(ParseErrorKind::MissingWhitespaceBetweenAttributes);
+                        self.re_consume = true;
+                        self.state = State::BeforeAttributeName;
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofInTag);
+                        return None;
+                    }
+                },
+                State::SelfClosingStartTag => match c {
+                    Some('>') => {
+                        self.set_self_closing_flag();
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some(_) => {
+                        self.error(ParseErrorKind::UnexpectedSolidusInTag);
+                        self.re_consume = true;
+                        self.state = State::BeforeAttributeName;
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofInTag);
+                        return None;
+                    }
+                },
+                State::BogusComment => match c {
+                    Some('>') => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_comment_char('\u{FFFD}');
+                    }
+                    Some(c) => self.append_comment_char(c),
+                    None => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                },
+                State::MarkupDeclarationOpen => unreachable!("handled before consuming"),
+                State::CommentStart => match c {
+                    Some('-') => self.state = State::CommentStartDash,
+                    // `<!-->` is an empty comment
+                    Some('>') => {
+                        self.error(ParseErrorKind::AbruptClosingOfEmptyComment);
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    _ => {
+                        self.re_consume = true;
+                        self.state = State::Comment;
+                    }
+                },
+                State::CommentStartDash => match c {
+                    Some('-') => self.state = State::CommentEnd,
+                    Some('>') => {
+                        self.error(ParseErrorKind::AbruptClosingOfEmptyComment);
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some(_) => {
+                        self.append_comment("-");
+                        self.re_consume = true;
+                        self.state = State::Comment;
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofInComment);
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                },
+                State::Comment => match c {
+                    Some('<') => {
+                        self.append_comment("<");
+                        self.state = State::CommentLessThanSign;
+                    }
+                    Some('-') => self.state = State::CommentEndDash,
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_comment_char('\u{FFFD}');
+                    }
+                    Some(c) => self.append_comment_char(c),
+                    None => {
+                        self.error(ParseErrorKind::EofInComment);
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                },
+                State::CommentLessThanSign => match c {
+                    Some('!') => {
+                        self.append_comment("!");
+                        self.state = State::CommentLessThanSignBang;
+                    }
+                    Some('<') => self.append_comment("<"),
+                    _ => {
+                        self.re_consume = true;
+                        self.state = State::Comment;
                     }
-
-                    return Some(HtmlToken::Char(c));
-                }
-                State::TagOpen => {
-                    if c == '/' {
-                        self.state = State::EndTagOpen;
-                        continue;
+                },
+                State::CommentLessThanSignBang => match c {
+                    Some('-') => self.state = State::CommentLessThanSignBangDash,
+                    _ => {
+                        self.re_consume = true;
+                        self.state = State::Comment;
                     }
-
-                    if c.is_ascii_alphabetic() {
+                },
+                State::CommentLessThanSignBangDash => match c {
+                    Some('-') => self.state = State::CommentLessThanSignBangDashDash,
+                    _ => {
                         self.re_consume = true;
-                        self.state = State::TagName;
-                        self.create_start_tag();
-                        continue;
+                        self.state = State::CommentEndDash;
                     }
-
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                },
+                State::CommentLessThanSignBangDashDash => {
+                    // `<!--` nested in a comment doesn't start a new one
+                    if !matches!(c, Some('>') | None) {
+                        self.error(ParseErrorKind::NestedComment);
                     }
-
                     self.re_consume = true;
-                    self.state = State::Data;
+                    self.state = State::CommentEnd;
                 }
-                State::EndTagOpen => {
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
-                    }
-
-                    if c.is_ascii_alphabetic() {
+                State::CommentEndDash => match c {
+                    Some('-') => self.state = State::CommentEnd,
+                    Some(_) => {
+                        self.append_comment("-");
                         self.re_consume = true;
-                        self.state = State::TagName;
-                        self.create_end_tag();
-                        continue;
+                        self.state = State::Comment;
                     }
-                }
-                State::TagName => {
-                    if c == ' ' {
-                        self.state = State::BeforeAttributeName;
-                        continue;
+                    None => {
+                        self.error(ParseErrorKind::EofInComment);
+                        self.state = State::Data;
+                        return self.emit_last_token();
                     }
-                    if c == '/' {
-                        self.state = State::SelfClosingStartTag;
-                        continue;
+                },
+                State::CommentEnd => match c {
+                    Some('>') => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
                     }
-
-                    if c == '>' {
+                    Some('!') => self.state = State::CommentEndBang,
+                    Some('-') => self.append_comment("-"),
+                    Some(_) => {
+                        self.append_comment("--");
+                        self.re_consume = true;
+                        self.state = State::Comment;
+                    }
+                    None => {
+                        self.error(ParseErrorKind::EofInComment);
                         self.state = State::Data;
-                        return Some(self.take_last_token());
+                        return self.emit_last_token();
                     }
-
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                },
+                State::CommentEndBang => match c {
+                    Some('-') => {
+                        self.append_comment("--!");
+                        self.state = State::CommentEndDash;
                     }
-
-                    self.append_tag_name(c.to_ascii_lowercase());
-                }
-                State::BeforeAttributeName => {
-                    if c == '/' || c == '>' || self.is_eof() {
+                    Some('>') => {
+                        self.error(ParseErrorKind::IncorrectlyClosedComment);
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some(_) => {
+                        self.append_comment("--!");
                         self.re_consume = true;
-                        self.state = State::AfterAttributeName;
-                        continue;
+                        self.state = State::Comment;
                     }
-
-                    self.re_consume = true;
-                    self.state = State::AttributeName;
-                    self.start_new_attribute();
-                }
-                State::AttributeName => {
-                    if c == ' ' || c == '/' || c == '>' || self.is_eof() {
+                    None => {
+                        self.error(ParseErrorKind::EofInComment);
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                },
+                State::Doctype => match c {
+                    Some(c) if is_whitespace(c) => self.state = State::BeforeDoctypeName,
+                    Some('>') => {
                         self.re_consume = true;
-                        self.state = State::AfterAttributeName;
-                        continue;
+                        self.state = State::BeforeDoctypeName;
                     }
-
-                    if c == '=' {
-                        self.state = State::BeforeAttributeValue;
-                        continue;
+                    Some(_) => {
+                        self.error(ParseErrorKind::MissingWhitespaceBeforeDoctypeName);
+                        self.re_consume = true;
+                        self.state = State::BeforeDoctypeName;
                     }
-
-                    if c.is_ascii_uppercase() {
-                        self.append_attribute_char(c.to_ascii_lowercase(), true);
-                        continue;
+                    None => {
+                        self.error(ParseErrorKind::EofInDoctype);
+                        self.create_doctype();
+                        self.state = State::Data;
+                        return self.emit_quirks_doctype();
                     }
-
-                    self.append_attribute_char(c, true);
-                }
-                State::AfterAttributeName => {
-                    if c == ' ' {
-                        continue;
+                },
+                State::BeforeDoctypeName => match c {
+                    Some(c) if is_whitespace(c) => {}
+                    Some('>') => {
+                        self.error(ParseErrorKind::MissingDoctypeName);
+                        self.create_doctype();
+                        self.state = State::Data;
+                        return self.emit_quirks_doctype();
                     }
-
-                    if c == '/' {
-                        self.state = State::SelfClosingStartTag;
-                        continue;
+                    Some(c) => {
+                        self.create_doctype();
+                        let c = if c == '\0' {
+                            self.error(ParseErrorKind::UnexpectedNullCharacter);
+                            '\u{FFFD}'
+                        } else {
+                            c
+                        };
+                        self.append_doctype_name(c.to_ascii_lowercase());
+                        self.state = State::DoctypeName;
                     }
-
-                    if c == '=' {
-                        self.state = State::BeforeAttributeValue;
-                        continue;
+                    None => {
+                        self.error(ParseErrorKind::EofInDoctype);
+                        self.create_doctype();
+                        self.state = State::Data;
+                        return self.emit_quirks_doctype();
                     }
-
-                    if c == '>' {
+                },
+                State::DoctypeName => match c {
+                    Some(c) if is_whitespace(c) => self.state = State::AfterDoctypeName,
+                    Some('>') => {
                         self.state = State::Data;
-                        return Some(self.take_last_token());
+                        return self.emit_last_token();
                     }
-
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                    Some('\0') => {
+                        self.error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_doctype_name('\u{FFFD}');
                     }
-
-                    self.re_consume = true;
-                    self.state = State::AttributeName;
-                    self.start_new_attribute();
-                }
-                State::BeforeAttributeValue => {
-                    if c == ' ' {
-                        continue;
+                    Some(c) => self.append_doctype_name(c.to_ascii_lowercase()),
+                    None => {
+                        self.error(ParseErrorKind::EofInDoctype);
+                        self.state = State::Data;
+                        return self.emit_quirks_doctype();
                     }
-
-                    if c == '"' {
-                        self.state = State::AttributeValueDoubleQuoted;
-                        continue;
+                },
+                State::AfterDoctypeName => match c {
+                    Some(c) if is_whitespace(c) => {}
+                    Some('>') => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
                     }
-
-                    if c == '\'' {
-                        self.state = State::AttributeValueSingleQuoted;
-                        continue;
+                    Some(_) => {
+                        // the keyword starts at the character just consumed
+                        if self.waits_for_input(self.pos - 1, "public".len()) {
+                            self.re_consume = true;
+                            return None;
+                        }
+
+                        if self.input_matches(self.pos - 1, "public") {
+                            self.pos += 5;
+                            self.state = State::AfterDoctypePublicKeyword;
+                        } else if self.input_matches(self.pos - 1, "system") {
+                            self.pos += 5;
+                            self.state = State::AfterDoctypeSystemKeyword;
+                        } else {
+                            self.error(ParseErrorKind::InvalidCharacterSequenceAfterDoctypeName);
+                            self.set_force_quirks();
+                            self.re_consume = true;
+                            self.state = State::BogusDoctype;
+                        }
                     }
-
-                    self.re_consume = true;
-                    self.state = State::AttributeValueUnquoted;
-                }
-                State::AttributeValueDoubleQuoted => {
-                    if c == '"' {
-                        self.state = State::AfterAttributeValueQuoted;
-                        continue;
+                    None => {
+                        self.error(ParseErrorKind::EofInDoctype);
+                        self.state = State::Data;
+                        return self.emit_quirks_doctype();
                     }
-
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                },
+                State::AfterDoctypePublicKeyword
+                | State::BeforeDoctypePublicIdentifier
+                | State::AfterDoctypeSystemKeyword
+                | State::BeforeDoctypeSystemIdentifier => {
+                    let is_public = self.state == State::AfterDoctypePublicKeyword
+                        || self.state == State::BeforeDoctypePublicIdentifier;
+                    let after_keyword = self.state == State::AfterDoctypePublicKeyword
+                        || self.state == State::AfterDoctypeSystemKeyword;
+                    match c {
+                        Some(c) if is_whitespace(c) => {
+                            self.state = if is_public {
+                                State::BeforeDoctypePublicIdentifier
+                            } else {
+                                State::BeforeDoctypeSystemIdentifier
+                            };
+                        }
+                        Some(quote) if quote == '"' || quote == '\'' => {
+                            if after_keyword {
+                                self.error(if is_public {
+                                    ParseErrorKind::MissingWhitespaceAfterDoctypePublicKeyword
+                                } else {
+                                    ParseErrorKind::MissingWhitespaceAfterDoctypeSystemKeyword
+                                });
+                            }
+                            self.start_doctype_identifier(is_public);
+                            self.state = match (is_public, quote == '"') {
+                                (true, true) => State::DoctypePublicIdentifierDoubleQuoted,
+                                (true, false) => State::DoctypePublicIdentifierSingleQuoted,
+                                (false, true) => State::DoctypeSystemIdentifierDoubleQuoted,
+                                (false, false) => State::DoctypeSystemIdentifierSingleQuoted,
+                            };
+                        }
+                        Some('>') => {
+                            self.error(if is_public {
+                                ParseErrorKind::MissingDoctypePublicIdentifier
+                            } else {
+                                ParseErrorKind::MissingDoctypeSystemIdentifier
+                            });
+                            self.state = State::Data;
+                            return self.emit_quirks_doctype();
+                        }
+                        Some(_) => {
+                            self.error(if is_public {
+                                ParseErrorKind::MissingQuoteBeforeDoctypePublicIdentifier
+                            } else {
+                                ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier
+                            });
+                            self.set_force_quirks();
+                            self.re_consume = true;
+                            self.state = State::BogusDoctype;
+                        }
+                        None => {
+                            self.error(ParseErrorKind::EofInDoctype);
+                            self.state = State::Data;
+                            return self.emit_quirks_doctype();
+                        }
                     }
-
-                    self.append_attribute_char(c, false);
                 }
-                State::AttributeValueSingleQuoted => {
-                    if c == '\'' {
-                        self.state = State::AfterAttributeValueQuoted;
-                        continue;
+                State::DoctypePublicIdentifierDoubleQuoted
+                | State::DoctypePublicIdentifierSingleQuoted
+                | State::DoctypeSystemIdentifierDoubleQuoted
+                | State::DoctypeSystemIdentifierSingleQuoted => {
+                    let (is_public, quote) = match self.state {
+                        State::DoctypePublicIdentifierDoubleQuoted => (true, '"'),
+                        State::DoctypePublicIdentifierSingleQuoted => (true, '\''),
+                        State::DoctypeSystemIdentifierDoubleQuoted => (false, '"'),
+                        _ => (false, '\''),
+                    };
+                    match c {
+                        Some(c) if c == quote => {
+                            self.state = if is_public {
+                                State::AfterDoctypePublicIdentifier
+                            } else {
+                                State::AfterDoctypeSystemIdentifier
+                            };
+                        }
+                        Some('\0') => {
+                            self.error(ParseErrorKind::UnexpectedNullCharacter);
+                            self.append_doctype_identifier('\u{FFFD}', is_public);
+                        }
+                        Some('>') => {
+                            self.error(if is_public {
+                                ParseErrorKind::AbruptDoctypePublicIdentifier
+                            } else {
+                                ParseErrorKind::AbruptDoctypeSystemIdentifier
+                            });
+                            self.state = State::Data;
+                            return self.emit_quirks_doctype();
+                        }
+                        Some(c) => self.append_doctype_identifier(c, is_public),
+                        None => {
+                            self.error(ParseErrorKind::EofInDoctype);
+                            self.state = State::Data;
+                            return self.emit_quirks_doctype();
+                        }
                     }
-
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
-                    }
-
-                    self.append_attribute_char(c, false);
                 }
-                State::AttributeValueUnquoted => {
-                    if c == ' ' {
-                        self.state = State::BeforeAttributeName;
-                        continue;
+                State::AfterDoctypePublicIdentifier
+                | State::BetweenDoctypePublicAndSystemIdentifiers => match c {
+                    Some(c) if is_whitespace(c) => {
+                        self.state = State::BetweenDoctypePublicAndSystemIdentifiers
                     }
-
-                    if c == '>' {
+                    Some('>') => {
                         self.state = State::Data;
-                        return Some(self.take_last_token());
+                        return self.emit_last_token();
                     }
-
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                    Some(quote) if quote == '"' || quote == '\'' => {
+                        if self.state == State::AfterDoctypePublicIdentifier {
+                            self.error(
+                                ParseErrorKind::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+                            );
+                        }
+                        self.start_doctype_identifier(false);
+                        self.state = if quote == '"' {
+                            State::DoctypeSystemIdentifierDoubleQuoted
+                        } else {
+                            State::DoctypeSystemIdentifierSingleQuoted
+                        };
                     }
-
-                    self.append_attribute_char(c, false);
-                }
-                State::AfterAttributeValueQuoted => {
-                    if c == ' ' {
-                        self.state = State::BeforeAttributeName;
-                        continue;
+                    Some(_) => {
+                        self.error(ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier);
+                        self.set_force_quirks();
+                        self.re_consume = true;
+                        self.state = State::BogusDoctype;
                     }
-
-                    if c == '/' {
-                        self.state = State::SelfClosingStartTag;
-                        continue;
+                    None => {
+                        self.error(ParseErrorKind::EofInDoctype);
+                        self.state = State::Data;
+                        return self.emit_quirks_doctype();
                     }
-
-                    if c == '>' {
+                },
+                State::AfterDoctypeSystemIdentifier => match c {
+                    Some(c) if is_whitespace(c) => {}
+                    Some('>') => {
                         self.state = State::Data;
-                        return Some(self.take_last_token());
+                        return self.emit_last_token();
                     }
-
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                    Some(_) => {
+                        // unlike the other errors, this doesn't set quirks mode
+                        self.error(ParseErrorKind::UnexpectedCharacterAfterDoctypeSystemIdentifier);
+                        self.re_consume = true;
+                        self.state = State::BogusDoctype;
                     }
-
+                    None => {
+                        self.error(ParseErrorKind::EofInDoctype);
+                        self.state = State::Data;
+                        return self.emit_quirks_doctype();
+                    }
+                },
+                State::CharacterReference => match c {
+                    Some(c) if c.is_ascii_alphanumeric() => {
+                        // The name ends at the first character that can't be
+                        // in one, which has to be seen first.
+                        let start = self.pos - 1;
+                        let name_len = self.input[start..]
+                            .iter()
+                            .take(entities::LONGEST_NAME_LENGTH)
+                            .take_while(|c| c.is_ascii_alphanumeric())
+                            .count();
+                        if name_len < entities::LONGEST_NAME_LENGTH
+                            && self.waits_for_input(start, name_len + 1)
+                        {
+                            self.re_consume = true;
+                            return None;
+                        }
+
+                        if self.consume_named_character_reference() {
+                            self.flush_character_reference();
+                        } else {
+                            self.re_consume = true;
+                            self.flush_character_reference();
+                            self.state = State::AmbiguousAmpersand;
+                        }
+                    }
+                    Some('#') => {
+                        self.temporary_buffer.push('#');
+                        self.state = State::NumericCharacterReference;
+                    }
+                    _ => {
+                        self.re_consume = true;
+                        self.flush_character_reference();
+                    }
+                },
+                State::AmbiguousAmpersand => match c {
+                    Some(c) if c.is_ascii_alphanumeric() => {
+                        if self.is_in_attribute_value() {
+                            self.append_attribute_char(c, false);
+                        } else {
+                            return self.emit(HtmlToken::Char(c));
+                        }
+                    }
+                    _ => {
+                        if c == Some(';') {
+                            self.error(ParseErrorKind::UnknownNamedCharacterReference);
+                        }
+                        self.re_consume = true;
+                        self.state = self.return_state;
+                    }
+                },
+                State::NumericCharacterReference => {
+                    self.character_reference_code = 0;
+                    match c {
+                        Some(c) if c == 'x' || c == 'X' => {
+                            self.temporary_buffer.push(c);
+                            self.state = State::HexadecimalCharacterReferenceStart;
+                        }
+                        _ => {
+                            self.re_consume = true;
+                            self.state = State::DecimalCharacterReferenceStart;
+                        }
+                    }
+                }
+                State::HexadecimalCharacterReferenceStart => {
+                    self.re_consume = true;
+                    match c {
+                        Some(c) if c.is_ascii_hexdigit() => {
+                            self.state = State::HexadecimalCharacterReference
+                        }
+                        // no digits, the reference is text
+                        _ => {
+                            self.error(ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                            self.flush_character_reference();
+                        }
+                    }
+                }
+                State::DecimalCharacterReferenceStart => {
                     self.re_consume = true;
-                    self.state = State::BeforeAttributeName;
+                    match c {
+                        Some(c) if c.is_ascii_digit() => {
+                            self.state = State::DecimalCharacterReference
+                        }
+                        _ => {
+                            self.error(ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                            self.flush_character_reference();
+                        }
+                    }
                 }
-                State::SelfClosingStartTag => {
-                    if c == '>' {
-                        self.set_self_closing_flag();
-                        self.state = State::Data;
-                        return Some(self.take_last_token());
+                State::HexadecimalCharacterReference | State::DecimalCharacterReference => {
+                    let radix = if self.state == State::HexadecimalCharacterReference {
+                        16
+                    } else {
+                        10
+                    };
+                    match c.and_then(|c| c.to_digit(radix)) {
+                        Some(digit) => {
+                            // Saturate so that huge references don't overflow;
+                            // anything above 0x10FFFF is replaced anyway.
+                            self.character_reference_code = self
+                                .character_reference_code
+                                .saturating_mul(radix)
+                                .saturating_add(digit)
+                                .min(0x110000);
+                        }
+                        None => {
+                            if c != Some(';') {
+                                // the semicolon is optional
+                                self.error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
+                                self.re_consume = true;
+                            }
+                            self.state = State::NumericCharacterReferenceEnd;
+                        }
                     }
                 }
+                State::NumericCharacterReferenceEnd => unreachable!("handled before consuming"),
+                State::BogusDoctype => match c {
+                    Some('>') | None => {
+                        self.state = State::Data;
+                        return self.emit_last_token();
+                    }
+                    Some('\0') => self.error(ParseErrorKind::UnexpectedNullCharacter),
+                    Some(_) => {}
+                },
             }
         }
     }
 }
 
+impl Iterator for HtmlTokenizer {
+    type Item = HtmlToken;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_spanned().map(|t| t.token())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use alloc::string::ToString;
+    use alloc::{format, string::ToString};
 
     use super::*;
 
@@ -557,4 +2096,334 @@ mod tests {
         );
         assert_eq!(tokenizer.next(), None);
     }
+
+    fn comment(data: &str) -> Option<HtmlToken> {
+        Some(HtmlToken::Comment(data.to_string()))
+    }
+
+    #[test]
+    fn parse_comments() {
+        let html = "<!-- a -- b --><!----><!--><!-x-><?php ?>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(tokenizer.next(), comment(" a -- b "));
+        assert_eq!(tokenizer.next(), comment(""));
+        assert_eq!(tokenizer.next(), comment(""));
+        assert_eq!(tokenizer.next(), comment("-x-"));
+        assert_eq!(tokenizer.next(), comment("?php ?"));
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn comment_does_not_leak_into_tags() {
+        let html = "<p><!-- <b>not a tag</b> --></p>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(
+            tokenizer.next(),
+            Some(HtmlToken::StartTag {
+                tag: "p".to_string(),
+                self_closing: false,
+                attributes: Vec::new()
+            })
+        );
+        assert_eq!(tokenizer.next(), comment(" <b>not a tag</b> "));
+        assert_eq!(
+            tokenizer.next(),
+            Some(HtmlToken::EndTag {
+                tag: "p".to_string()
+            })
+        );
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn unterminated_comment_ends_at_eof() {
+        let mut tokenizer = HtmlTokenizer::new("<!-- open".to_string());
+        assert_eq!(tokenizer.next(), comment(" open"));
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn parse_doctype() {
+        let html = "<!DOCTYPE html>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(
+            tokenizer.next(),
+            Some(HtmlToken::Doctype {
+                name: Some("html".to_string()),
+                public_id: None,
+                system_id: None,
+                force_quirks: false,
+            })
+        );
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn parse_doctype_with_identifiers() {
+        let html = "<!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\" 'http://www.w3.org/TR/html4/strict.dtd'>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(
+            tokenizer.next(),
+            Some(HtmlToken::Doctype {
+                name: Some("html".to_string()),
+                public_id: Some("-//W3C//DTD HTML 4.01//EN".to_string()),
+                system_id: Some("http://www.w3.org/TR/html4/strict.dtd".to_string()),
+                force_quirks: false,
+            })
+        );
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn malformed_doctypes_force_quirks() {
+        for html in ["<!DOCTYPE>", "<!DOCTYPE html", "<!DOCTYPE html bogus>"] {
+            let mut tokenizer = HtmlTokenizer::new(html.to_string());
+            match tokenizer.next() {
+                Some(HtmlToken::Doctype { force_quirks, .. }) => assert!(force_quirks, "{}", html),
+                token => panic!("unexpected token {:?} for {}", token, html),
+            }
+            assert_eq!(tokenizer.next(), None);
+        }
+    }
+
+    #[test]
+    fn stray_less_than_sign_is_text() {
+        let mut tokenizer = HtmlTokenizer::new("a < b</".to_string());
+        let tokens: Vec<HtmlToken> = tokenizer.by_ref().collect();
+        assert_eq!(
+            tokens,
+            "a < b</".chars().map(HtmlToken::Char).collect::<Vec<_>>()
+        );
+    }
+
+    fn text(tokenizer: HtmlTokenizer) -> String {
+        tokenizer
+            .map(|t| match t {
+                HtmlToken::Char(c) => c,
+                t => panic!("unexpected token {:?}", t),
+            })
+            .collect()
+    }
+
+    fn attribute_value(html: &str) -> String {
+        match HtmlTokenizer::new(html.to_string()).next() {
+            Some(HtmlToken::StartTag { attributes, .. }) => attributes[0].value(),
+            t => panic!("unexpected token {:?}", t),
+        }
+    }
+
+    #[test]
+    fn decode_named_character_references() {
+        let html = "&lt;p&gt; &amp;&nbsp;&NotEqualTilde; &copy 2024 &notit; &bogus; &".to_string();
+        assert_eq!(
+            text(HtmlTokenizer::new(html)),
+            "<p> &\u{A0}\u{2242}\u{338} \u{A9} 2024 \u{AC}it; &bogus; &".to_string()
+        );
+    }
+
+    #[test]
+    fn decode_numeric_character_references() {
+        let html = "&#x3042;&#12354;&#X41&#0;&#x110000;&#xD800;&#128;&#x9d;&#;&#x;".to_string();
+        assert_eq!(
+            text(HtmlTokenizer::new(html)),
+            "\u{3042}\u{3042}A\u{FFFD}\u{FFFD}\u{FFFD}\u{20AC}\u{9D}&#;&#x;".to_string()
+        );
+    }
+
+    #[test]
+    fn decode_references_in_attribute_values() {
+        assert_eq!(
+            attribute_value("<a href=\"?a=1&amp;b=2\">"),
+            "?a=1&b=2".to_string()
+        );
+        assert_eq!(attribute_value("<a title='&#x41;&lt'>"), "A<".to_string());
+        assert_eq!(
+            attribute_value("<a href=?x=1&lt=2>"),
+            "?x=1&lt=2".to_string()
+        );
+        assert_eq!(
+            attribute_value("<a href=\"&copy=1&notit\">"),
+            "&copy=1&notit".to_string()
+        );
+    }
+
+    /// Returns the text up to the first tag, and the tag.
+    fn text_until_tag(tokenizer: &mut HtmlTokenizer) -> (String, Option<HtmlToken>) {
+        let mut text = String::new();
+        for token in tokenizer.by_ref() {
+            match token {
+                HtmlToken::Char(c) => text.push(c),
+                token => return (text, Some(token)),
+            }
+        }
+        (text, None)
+    }
+
+    fn end_tag(tag: &str) -> Option<HtmlToken> {
+        Some(HtmlToken::EndTag {
+            tag: tag.to_string(),
+        })
+    }
+
+    #[test]
+    fn rawtext_ends_only_at_its_end_tag() {
+        let mut tokenizer = HtmlTokenizer::new("<style>a < b</p><!-- &amp;</STYLE >".to_string());
+        tokenizer.next();
+        tokenizer.switch_to(TokenizerState::Rawtext);
+
+        assert_eq!(
+            text_until_tag(&mut tokenizer),
+            ("a < b</p><!-- &amp;".to_string(), end_tag("style"))
+        );
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn rcdata_decodes_character_references() {
+        let mut tokenizer =
+            HtmlTokenizer::new("<textarea>&lt;p&gt;</p></textareax></textarea>".to_string());
+        tokenizer.next();
+        tokenizer.switch_to(TokenizerState::Rcdata);
+
+        assert_eq!(
+            text_until_tag(&mut tokenizer),
+            ("<p></p></textareax>".to_string(), end_tag("textarea"))
+        );
+    }
+
+    #[test]
+    fn script_data_escapes() {
+        let script = "if (a<b) { x = '<!--<script>'; y = '</script>'; } // -->";
+        let mut tokenizer = HtmlTokenizer::new(format!("<script>{}</script>", script));
+        tokenizer.next();
+        tokenizer.switch_to(TokenizerState::ScriptData);
+
+        assert_eq!(
+            text_until_tag(&mut tokenizer),
+            (script.to_string(), end_tag("script"))
+        );
+    }
+
+    #[test]
+    fn plaintext_never_ends() {
+        let mut tokenizer = HtmlTokenizer::new("<plaintext></plaintext><p>".to_string());
+        tokenizer.next();
+        tokenizer.switch_to(TokenizerState::Plaintext);
+
+        assert_eq!(
+            text_until_tag(&mut tokenizer),
+            ("</plaintext><p>".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn cdata_sections_only_in_foreign_content() {
+        let html = "<![CDATA[a]b]]c<p>]]]></p>";
+        let mut tokenizer = HtmlTokenizer::new(html.to_string());
+        tokenizer.set_cdata_allowed(true);
+        assert_eq!(
+            text_until_tag(&mut tokenizer),
+            ("a]b]]c<p>]".to_string(), end_tag("p"))
+        );
+
+        // in HTML it is a bogus comment
+        let mut tokenizer = HtmlTokenizer::new(html.to_string());
+        assert_eq!(tokenizer.next(), comment("[CDATA[a]b]]c<p"));
+    }
+
+    fn errors(html: &str) -> Vec<(&'static str, usize, usize)> {
+        let mut tokenizer = HtmlTokenizer::new(html.to_string());
+        while tokenizer.next().is_some() {}
+        tokenizer
+            .errors()
+            .iter()
+            .map(|e| (e.code(), e.position().line(), e.position().column()))
+            .collect()
+    }
+
+    #[test]
+    fn report_parse_errors_with_positions() {
+        assert_eq!(
+            errors("<div>\n<a id=x id=y></a/>\n&#0;<"),
+            Vec::from([
+                ("duplicate-attribute", 2, 11),
+                ("end-tag-with-trailing-solidus", 2, 18),
+                ("null-character-reference", 3, 4),
+                ("eof-before-tag-name", 3, 6),
+            ])
+        );
+        assert_eq!(
+            errors("<!DOCTYPE html PUBLIC\"x\"><p\0 a=`"),
+            Vec::from([
+                ("missing-whitespace-after-doctype-public-keyword", 1, 22),
+                ("unexpected-null-character", 1, 28),
+                ("unexpected-character-in-unquoted-attribute-value", 1, 32),
+                ("eof-in-tag", 1, 33),
+            ])
+        );
+        assert_eq!(errors("<p>a &amp; b</p>"), Vec::new());
+    }
+
+    #[test]
+    fn duplicate_attributes_are_dropped() {
+        let mut tokenizer = HtmlTokenizer::new("<a href=1 HREF=2>".to_string());
+        let mut expected = Attribute::new();
+        for c in "href".chars() {
+            expected.add_name_char(c);
+        }
+        expected.add_value_char('1');
+        assert_eq!(
+            tokenizer.next(),
+            Some(HtmlToken::StartTag {
+                tag: "a".to_string(),
+                self_closing: false,
+                attributes: Vec::from([expected]),
+            })
+        );
+    }
+
+    #[test]
+    fn tokens_carry_source_spans() {
+        let mut tokenizer = HtmlTokenizer::new("<p class=a>x&amp;\r\n<!--c-->".to_string());
+        let mut spans = Vec::new();
+        while let Some(t) = tokenizer.next_spanned() {
+            let (start, end) = (t.span().start(), t.span().end());
+            spans.push((
+                t.token(),
+                (start.line(), start.column()),
+                (end.line(), end.column()),
+            ));
+        }
+
+        assert_eq!(spans.len(), 5);
+        assert_eq!((spans[0].1, spans[0].2), ((1, 1), (1, 12)));
+        assert_eq!(spans[1], (HtmlToken::Char('x'), (1, 12), (1, 13)));
+        assert_eq!(spans[2], (HtmlToken::Char('&'), (1, 13), (1, 18)));
+        assert_eq!(spans[3], (HtmlToken::Char('\n'), (1, 18), (2, 1)));
+        assert_eq!(
+            spans[4],
+            (HtmlToken::Comment("c".to_string()), (2, 1), (2, 9))
+        );
+    }
+
+    #[test]
+    fn tokenize_input_fed_one_byte_at_a_time() {
+        let html =
+            "<!DOCTYPE html>\r\n<p class=\"a&amp;b\">x &notin; \u{3042}<!-- c --></p>\r\n&#x41";
+        let mut tokenizer = HtmlTokenizer::new(html.to_string());
+        let whole: Vec<SpannedToken> = core::iter::from_fn(|| tokenizer.next_spanned()).collect();
+
+        let mut tokenizer = HtmlTokenizer::streaming();
+        let mut chunked = Vec::new();
+        for b in html.as_bytes() {
+            tokenizer.feed_bytes(&[*b]);
+            chunked.extend(core::iter::from_fn(|| tokenizer.next_spanned()));
+        }
+        tokenizer.finish();
+        chunked.extend(core::iter::from_fn(|| tokenizer.next_spanned()));
+
+        assert_eq!(chunked, whole);
+        assert_eq!(chunked.last().unwrap().token(), HtmlToken::Char('A'));
+        assert_eq!(tokenizer.errors().len(), 1);
+    }
 }
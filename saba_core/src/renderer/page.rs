@@ -3,18 +3,36 @@ use core::cell::RefCell;
 use alloc::{
     rc::{Rc, Weak},
     string::{String, ToString},
+    vec::Vec,
 };
 
-use crate::{browser::Browser, http::HttpResponse, utils::convert_dom_to_string};
+use crate::{
+    browser::Browser,
+    error::Error,
+    http::{HttpRequest, HttpResponse},
+    referrer::ReferrerPolicy,
+    url::Url,
+    utils::convert_dom_to_string,
+};
 
 use super::{
-    dom::node::Window,
-    html::{parser::HtmlParser, token::HtmlTokenizer},
+    dom::node::{ElementKind, Node, Window},
+    html::{
+        form::{
+            attribute, elements_in_tree_order, form_of, implicit_submission, is_submit_button,
+            submit, toggle,
+        },
+        parser::HtmlParser,
+        serializer::serialize_children,
+        token::HtmlTokenizer,
+    },
 };
 
 #[derive(Debug, Clone)]
 pub struct Page {
     browser: Weak<RefCell<Browser>>,
+    url: Option<Url>,
+    referrer_policy: ReferrerPolicy,
     frame: Option<Rc<RefCell<Window>>>,
 }
 
@@ -22,6 +40,8 @@ impl Page {
     pub fn new() -> Self {
         Self {
             browser: Weak::new(),
+            url: None,
+            referrer_policy: ReferrerPolicy::default(),
             frame: None,
         }
     }
@@ -30,14 +50,52 @@ impl Page {
         self.browser = browser;
     }
 
-    fn create_frame(&mut self, html: String) {
-        let html_tokenizer = HtmlTokenizer::new(html);
-        let frame = HtmlParser::new(html_tokenizer).construct_tree();
-        self.frame = Some(frame);
+    pub fn url(&self) -> Option<Url> {
+        self.url.clone()
     }
 
-    pub fn receive_response(&mut self, response: HttpResponse) -> String {
-        self.create_frame(response.body());
+    pub fn referrer_policy(&self) -> ReferrerPolicy {
+        self.referrer_policy
+    }
+
+    /// Applies `<meta name="referrer">`. The last valid one wins.
+    fn apply_meta_referrer(&mut self) {
+        for meta in self.elements_of_kind(ElementKind::Meta) {
+            let is_referrer =
+                attribute(&meta, "name").is_some_and(|name| name.eq_ignore_ascii_case("referrer"));
+            if !is_referrer {
+                continue;
+            }
+            if let Some(policy) =
+                attribute(&meta, "content").and_then(|c| ReferrerPolicy::from_meta_content(&c))
+            {
+                self.referrer_policy = policy;
+            }
+        }
+    }
+
+    /// Parses the whole body of `response` and makes it the document of this
+    /// page.
+    pub fn receive_response(&mut self, url: Url, response: HttpResponse) -> String {
+        let frame = HtmlParser::new(HtmlTokenizer::new(response.body())).construct_tree();
+        self.receive_document(url, &response, frame)
+    }
+
+    /// Makes `frame`, parsed from the body of `response` as it arrives, the
+    /// document of this page. It is called again once parsing has finished,
+    /// so that what the rest of the document says is taken into account.
+    pub fn receive_document(
+        &mut self,
+        url: Url,
+        response: &HttpResponse,
+        frame: Rc<RefCell<Window>>,
+    ) -> String {
+        self.url = Some(url);
+        self.referrer_policy =
+            ReferrerPolicy::from_header(&response.header_values("Referrer-Policy").join(","))
+                .unwrap_or_default();
+        self.frame = Some(frame);
+        self.apply_meta_referrer();
 
         if let Some(frame) = &self.frame {
             let dom = frame.borrow().document().clone();
@@ -48,5 +106,250 @@ impl Page {
         "".to_string()
     }
 
+    fn elements(&self) -> Vec<Rc<RefCell<Node>>> {
+        let frame = match &self.frame {
+            Some(frame) => frame,
+            None => return Vec::new(),
+        };
+
+        let mut elements = Vec::new();
+        elements_in_tree_order(&frame.borrow().document(), &mut elements);
+        elements
+    }
+
+    fn elements_of_kind(&self, kind: ElementKind) -> Vec<Rc<RefCell<Node>>> {
+        self.elements()
+            .into_iter()
+            .filter(|e| e.borrow().element_kind() == Some(kind))
+            .collect()
+    }
+
+    /// Returns the HTML of the document as it is now, for saving the page.
+    pub fn html(&self) -> Option<String> {
+        self.frame
+            .as_ref()
+            .map(|frame| serialize_children(&frame.borrow().document()))
+    }
+
+    /// Returns every `<form>` in the document in tree order.
+    pub fn forms(&self) -> Vec<Rc<RefCell<Node>>> {
+        self.elements_of_kind(ElementKind::Form)
+    }
+
+    /// Returns the elements of the document the user can interact with, links
+    /// and form controls, in tree order.
+    pub fn interactive_elements(&self) -> Vec<Rc<RefCell<Node>>> {
+        self.elements()
+            .into_iter()
+            .filter(|e| {
+                let kind = e.borrow().element_kind();
+                match kind {
+                    Some(ElementKind::A) => attribute(e, "href").is_some(),
+                    Some(ElementKind::Input) => {
+                        !attribute(e, "type").is_some_and(|t| t.eq_ignore_ascii_case("hidden"))
+                    }
+                    Some(ElementKind::Button) | Some(ElementKind::Textarea) => true,
+                    _ => false,
+                }
+            })
+            .collect()
+    }
+
+    /// Activates `element` as if the user clicked it. Returns the request to
+    /// navigate to when that follows a link or submits a form.
+    pub fn activate(&self, element: &Rc<RefCell<Node>>) -> Result<Option<HttpRequest>, Error> {
+        if element.borrow().element_kind() == Some(ElementKind::A) {
+            return self.follow_link(element).map(Some);
+        }
+        if attribute(element, "disabled").is_some() {
+            return Ok(None);
+        }
+        if !is_submit_button(element) {
+            toggle(element);
+            return Ok(None);
+        }
+
+        match form_of(element) {
+            Some(form) => self.submit_form(&form, Some(element)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Handles the user pressing Enter in the form field `field`. Returns the
+    /// request to navigate to when that submits its form.
+    pub fn submit_implicitly(
+        &self,
+        field: &Rc<RefCell<Node>>,
+    ) -> Result<Option<HttpRequest>, Error> {
+        match implicit_submission(field) {
+            Some(button) if is_submit_button(&button) => self.activate(&button),
+            Some(form) => self.submit_form(&form, None).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn document_url(&self) -> Result<Url, Error> {
+        match &self.url {
+            Some(url) => Ok(url.clone()),
+            None => Err(Error::Other(
+                "no page is loaded to start a request from".to_string(),
+            )),
+        }
+    }
+
+    /// Sets this page as the referrer of `request`. `initiator` is the link
+    /// or form that started the request; its `rel=noreferrer` and
+    /// `referrerpolicy` override the page's policy.
+    fn apply_referrer(&self, request: &mut HttpRequest, initiator: &Rc<RefCell<Node>>) {
+        let no_referrer = attribute(initiator, "rel").is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|r| r.eq_ignore_ascii_case("noreferrer"))
+        });
+        if no_referrer {
+            request.set_referrer(None, ReferrerPolicy::NoReferrer);
+            return;
+        }
+        let policy = attribute(initiator, "referrerpolicy")
+            .and_then(|p| ReferrerPolicy::from_token(&p))
+            .unwrap_or(self.referrer_policy);
+
+        request.set_referrer(self.url.clone(), policy);
+    }
+
+    /// Builds the request for following the hyperlink `anchor`, to be passed
+    /// to the navigation layer.
+    pub fn follow_link(&self, anchor: &Rc<RefCell<Node>>) -> Result<HttpRequest, Error> {
+        let href = match attribute(anchor, "href") {
+            Some(href) => href,
+            None => return Err(Error::UnexpectedInput("link has no href".to_string())),
+        };
+        let url = match self.document_url()?.resolve(&href) {
+            Ok(url) => url,
+            Err(e) => return Err(Error::UnexpectedInput(e)),
+        };
+
+        let mut request = HttpRequest::new("GET", url);
+        self.apply_referrer(&mut request, anchor);
+        Ok(request)
+    }
+
+    /// Builds the request for a subresource (stylesheet, image, ...) that
+    /// this page refers to. It carries the page's own referrer policy.
+    pub fn subresource_request(&self, reference: &str) -> Result<HttpRequest, Error> {
+        let url = match self.document_url()?.resolve(reference) {
+            Ok(url) => url,
+            Err(e) => return Err(Error::UnexpectedInput(e)),
+        };
+
+        let mut request = HttpRequest::new("GET", url);
+        request.set_referrer(self.url.clone(), self.referrer_policy);
+        Ok(request)
+    }
+
+    /// Builds the request for submitting `form`, to be passed to the
+    /// navigation layer.
+    pub fn submit_form(
+        &self,
+        form: &Rc<RefCell<Node>>,
+        submitter: Option<&Rc<RefCell<Node>>>,
+    ) -> Result<HttpRequest, Error> {
+        let mut request = submit(form, submitter, &self.document_url()?)?;
+        self.apply_referrer(&mut request, form);
+        Ok(request)
+    }
+
     pub fn x(self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::referrer::referrer_for;
+
+    fn load(html: &str, headers: &str) -> Page {
+        let mut page = Page::new();
+        let url = Url::new("http://intranet.local/app/index.html".to_string())
+            .parse()
+            .unwrap();
+        let raw = alloc::format!("HTTP/1.1 200 OK\n{}\n\n{}", headers, html);
+        page.receive_response(url, HttpResponse::new(raw).unwrap());
+        page
+    }
+
+    fn links(page: &Page) -> Vec<Rc<RefCell<Node>>> {
+        page.elements_of_kind(ElementKind::A)
+    }
+
+    #[test]
+    fn meta_referrer_overrides_header() {
+        let page = load(
+            "<html><head><meta name=\"referrer\" content=\"origin\"></head><body></body></html>",
+            "Referrer-Policy: unsafe-url",
+        );
+
+        assert_eq!(page.referrer_policy(), ReferrerPolicy::Origin);
+    }
+
+    #[test]
+    fn last_valid_policy_across_headers_wins() {
+        let page = load(
+            "<html><head></head><body></body></html>",
+            "Referrer-Policy: origin\nReferrer-Policy: no-referrer, bogus",
+        );
+
+        assert_eq!(page.referrer_policy(), ReferrerPolicy::NoReferrer);
+    }
+
+    #[test]
+    fn links_carry_referrer_unless_noreferrer() {
+        let page = load(
+            "<html><head></head><body><a href=\"/a\"></a><a href=\"b\" rel=\"external noreferrer\"></a><a href=\"c\" referrerpolicy=\"no-referrer\"></a></body></html>",
+            "Referrer-Policy: same-origin",
+        );
+        let links = links(&page);
+
+        let first = page.follow_link(&links[0]).unwrap();
+        assert_eq!(first.url().url(), "http://intranet.local/a".to_string());
+        assert_eq!(first.referrer(), page.url());
+        assert_eq!(first.referrer_policy(), ReferrerPolicy::SameOrigin);
+
+        let second = page.follow_link(&links[1]).unwrap();
+        assert_eq!(second.referrer(), None);
+
+        let third = page.follow_link(&links[2]).unwrap();
+        assert_eq!(third.referrer_policy(), ReferrerPolicy::NoReferrer);
+    }
+
+    #[test]
+    fn cross_origin_subresource_gets_origin_only() {
+        let page = load(
+            "<html><head></head><body></body></html>",
+            "Referrer-Policy: strict-origin-when-cross-origin",
+        );
+
+        let request = page
+            .subresource_request("http://cdn.example.com/lib.js")
+            .unwrap();
+        assert_eq!(
+            request.referrer_policy(),
+            ReferrerPolicy::StrictOriginWhenCrossOrigin
+        );
+        let referer = referrer_for(
+            request.referrer_policy(),
+            &request.referrer().unwrap(),
+            &request.url(),
+        );
+        assert_eq!(referer, Some("http://intranet.local/".to_string()));
+
+        let same_origin = page.subresource_request("style.css").unwrap();
+        let referer = referrer_for(
+            same_origin.referrer_policy(),
+            &same_origin.referrer().unwrap(),
+            &same_origin.url(),
+        );
+        assert_eq!(
+            referer,
+            Some("http://intranet.local/app/index.html".to_string())
+        );
+    }
+}
@@ -0,0 +1,454 @@
+//! The interface between navigation and the network, and two transports that
+//! record exchanges into an archive and serve them back without a network.
+//!
+//! An archive is plain text so that it can be checked in next to the tests
+//! that use it. It starts with a version line and then lists each exchange as
+//! a request record followed by a response record. Each record is a kind and
+//! a length in bytes on one line, then exactly that many bytes:
+//!
+//! ```text
+//! saba-archive 1
+//! request 25
+//! GET http://example.com/
+//!
+//!
+//! response 22
+//! HTTP/1.1 200 OK
+//!
+//! hello
+//! ```
+//!
+//! A request record holds the method and URL, the headers and, after an empty
+//! line, the body. A response record is the response as it came off the wire.
+//!
+//! With the `std` feature, archives can be saved to and loaded from files on
+//! the host.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    error::Error,
+    http::{Header, HttpRequest, HttpResponse},
+    url::Url,
+};
+
+const ARCHIVE_VERSION_LINE: &str = "saba-archive 1";
+
+pub trait HttpTransport {
+    fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, Error>;
+
+    /// Sends `request` like `send`, and hands the body of the response to
+    /// `on_body` in chunks as it arrives. `on_body` is given the response
+    /// too, for its status and headers; its body may not be complete yet. A
+    /// transport that streams does not keep the body, so the response it
+    /// returns may have none. A transport that cannot stream hands over the
+    /// whole body at once.
+    fn send_streaming(
+        &mut self,
+        request: &HttpRequest,
+        on_body: &mut dyn FnMut(&HttpResponse, &[u8]),
+    ) -> Result<HttpResponse, Error> {
+        let response = self.send(request)?;
+        on_body(&response, response.body().as_bytes());
+        Ok(response)
+    }
+
+    /// Returns the archive of every exchange recorded so far, for transports
+    /// that record them.
+    fn recorded_archive(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<F> HttpTransport for F
+where
+    F: FnMut(&HttpRequest) -> Result<HttpResponse, Error>,
+{
+    fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, Error> {
+        self(request)
+    }
+}
+
+fn serialize_headers(headers: &[Header]) -> String {
+    let mut result = String::new();
+    for h in headers {
+        result.push_str(&h.name());
+        result.push_str(": ");
+        result.push_str(&h.value());
+        result.push('\n');
+    }
+    result
+}
+
+fn serialize_request(request: &HttpRequest) -> String {
+    format!(
+        "{} {}\n{}\n{}",
+        request.method(),
+        request.url().url(),
+        serialize_headers(&request.headers()),
+        request.body()
+    )
+}
+
+fn parse_request(raw: &str) -> Result<HttpRequest, Error> {
+    let (head, body) = raw.split_once("\n\n").unwrap_or((raw, ""));
+    let mut lines = head.split('\n');
+    let request_line = lines.next().unwrap_or("");
+    let (method, url) = match request_line.split_once(' ') {
+        Some((m, u)) => (m, u),
+        None => {
+            return Err(Error::UnexpectedInput(format!(
+                "invalid request line in archive: {}",
+                request_line
+            )))
+        }
+    };
+    let url = match Url::new(url.to_string()).parse() {
+        Ok(url) => url,
+        Err(e) => return Err(Error::UnexpectedInput(e)),
+    };
+
+    let mut request = HttpRequest::new(method, url);
+    for line in lines.filter(|l| !l.is_empty()) {
+        match line.split_once(':') {
+            Some((name, value)) => request.set_header(name.trim(), value.trim().to_string()),
+            None => {
+                return Err(Error::UnexpectedInput(format!(
+                    "invalid header in archive: {}",
+                    line
+                )))
+            }
+        }
+    }
+    request.set_body(body.to_string());
+
+    Ok(request)
+}
+
+fn push_record(archive: &mut String, kind: &str, content: &str) {
+    archive.push_str(&format!("{} {}\n", kind, content.len()));
+    archive.push_str(content);
+    archive.push('\n');
+}
+
+/// Reads the record of `kind` at the start of `input` and returns its content
+/// along with the rest of the input.
+fn read_record<'a>(input: &'a str, kind: &str) -> Result<(&'a str, &'a str), Error> {
+    let invalid = |reason: &str| Error::UnexpectedInput(format!("invalid archive: {}", reason));
+
+    let (header, rest) = input
+        .split_once('\n')
+        .ok_or_else(|| invalid("truncated record header"))?;
+    let len = match header.split_once(' ') {
+        Some((k, len)) if k == kind => len
+            .parse::<usize>()
+            .map_err(|_| invalid("record length is not a number"))?,
+        _ => return Err(invalid(&format!("expected a {} record", kind))),
+    };
+
+    let content = rest
+        .get(..len)
+        .ok_or_else(|| invalid("record is shorter than its length"))?;
+    let rest = &rest[len..];
+    let rest = rest
+        .strip_prefix('\n')
+        .ok_or_else(|| invalid("record is longer than its length"))?;
+
+    Ok((content, rest))
+}
+
+/// Sends requests through `inner` and keeps every exchange so that it can be
+/// saved as an archive.
+pub struct RecordingTransport<T: HttpTransport> {
+    inner: T,
+    archive: String,
+}
+
+impl<T: HttpTransport> RecordingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        let mut archive = String::from(ARCHIVE_VERSION_LINE);
+        archive.push('\n');
+        Self { inner, archive }
+    }
+
+    /// Records the exchange, with the response as it came off the wire: its
+    /// head as received followed by the body.
+    fn record(&mut self, request: &HttpRequest, response: &HttpResponse, body: &str) {
+        push_record(&mut self.archive, "request", &serialize_request(request));
+        push_record(
+            &mut self.archive,
+            "response",
+            &format!("{}{}", response.head(), body),
+        );
+    }
+
+    /// Returns the archive of every successful exchange so far.
+    pub fn archive(&self) -> String {
+        self.archive.clone()
+    }
+
+    /// Writes the archive of every successful exchange so far to `path`.
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &std::path::Path) -> Result<(), Error> {
+        std::fs::write(path, &self.archive).map_err(|e| {
+            Error::Other(format!(
+                "failed to save the archive to {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+}
+
+impl<T: HttpTransport> HttpTransport for RecordingTransport<T> {
+    fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, Error> {
+        let response = self.inner.send(request)?;
+        self.record(request, &response, &response.body());
+        Ok(response)
+    }
+
+    fn send_streaming(
+        &mut self,
+        request: &HttpRequest,
+        on_body: &mut dyn FnMut(&HttpResponse, &[u8]),
+    ) -> Result<HttpResponse, Error> {
+        let mut body = Vec::new();
+        let response = self.inner.send_streaming(request, &mut |head, chunk| {
+            body.extend_from_slice(chunk);
+            on_body(head, chunk);
+        })?;
+        self.record(request, &response, &String::from_utf8_lossy(&body));
+        Ok(response)
+    }
+
+    fn recorded_archive(&self) -> Option<String> {
+        Some(self.archive())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Exchange {
+    request: HttpRequest,
+    raw_response: String,
+    replayed: bool,
+}
+
+/// Answers requests from an archive. A request matches a recorded one with the
+/// same method, URL and body; headers are ignored because they depend on state
+/// such as cached credentials. When the same request was recorded several
+/// times, the recorded responses are served in order and the last one is
+/// repeated after that.
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    exchanges: Vec<Exchange>,
+}
+
+impl ReplayTransport {
+    pub fn new(archive: &str) -> Result<Self, Error> {
+        let mut rest = match archive.split_once('\n') {
+            Some((version, rest)) if version == ARCHIVE_VERSION_LINE => rest,
+            _ => {
+                return Err(Error::UnexpectedInput(
+                    "invalid archive: unsupported version".to_string(),
+                ))
+            }
+        };
+
+        let mut exchanges = Vec::new();
+        while !rest.is_empty() {
+            let (request, remaining) = read_record(rest, "request")?;
+            let (response, remaining) = read_record(remaining, "response")?;
+            exchanges.push(Exchange {
+                request: parse_request(request)?,
+                raw_response: response.to_string(),
+                replayed: false,
+            });
+            rest = remaining;
+        }
+
+        Ok(Self { exchanges })
+    }
+
+    /// Reads the archive at `path`.
+    #[cfg(feature = "std")]
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(archive) => Self::new(&archive),
+            Err(e) => Err(Error::Other(format!(
+                "failed to load the archive from {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+}
+
+impl HttpTransport for ReplayTransport {
+    fn send(&mut self, request: &HttpRequest) -> Result<HttpResponse, Error> {
+        let matches = |e: &Exchange| {
+            e.request.method() == request.method()
+                && e.request.url() == request.url()
+                && e.request.body() == request.body()
+        };
+
+        let index = match self
+            .exchanges
+            .iter()
+            .position(|e| matches(e) && !e.replayed)
+        {
+            Some(i) => i,
+            None => match self.exchanges.iter().rposition(matches) {
+                Some(i) => i,
+                None => {
+                    return Err(Error::Network(format!(
+                        "no recorded response for {} {}",
+                        request.method(),
+                        request.url().url()
+                    )))
+                }
+            },
+        };
+
+        self.exchanges[index].replayed = true;
+        HttpResponse::new(self.exchanges[index].raw_response.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{CredentialCache, Credentials},
+        navigation::navigate,
+        network_log::NetworkLog,
+    };
+
+    fn server(request: &HttpRequest) -> Result<HttpResponse, Error> {
+        match request.url().path().as_str() {
+            "old" => HttpResponse::new("HTTP/1.1 302 Found\nLocation: /private/\n\n".to_string()),
+            _ => match request.header_value("Authorization") {
+                Some(_) => HttpResponse::new(format!(
+                    "HTTP/1.1 200 OK\nContent-Type: text/html\n\n<p>{}</p>\n\n",
+                    request.method()
+                )),
+                None => HttpResponse::new(
+                    "HTTP/1.1 401 Unauthorized\nWWW-Authenticate: Basic realm=\"r\"\n\n"
+                        .to_string(),
+                ),
+            },
+        }
+    }
+
+    fn credentials() -> Option<Credentials> {
+        Some(Credentials::new("user".to_string(), "pass".to_string()))
+    }
+
+    #[test]
+    fn replay_recorded_navigation() {
+        let mut recorder = RecordingTransport::new(server);
+        let request = HttpRequest::get("http://example.com/old".to_string()).unwrap();
+        let (recorded_url, recorded) = navigate(
+            request.clone(),
+            &mut recorder,
+            &mut CredentialCache::new(),
+            &mut NetworkLog::new(),
+            &mut |_| credentials(),
+        )
+        .unwrap();
+
+        let mut replay = ReplayTransport::new(&recorder.archive()).unwrap();
+        let mut log = NetworkLog::new();
+        let (url, response) = navigate(
+            request,
+            &mut replay,
+            &mut CredentialCache::new(),
+            &mut log,
+            &mut |_| credentials(),
+        )
+        .unwrap();
+
+        assert_eq!(url, recorded_url);
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), recorded.body());
+        assert_eq!(response.headers(), recorded.headers());
+        let statuses: Vec<u32> = log.entries().iter().map(|e| e.status()).collect();
+        assert_eq!(statuses, Vec::from([302, 401, 200]));
+    }
+
+    #[test]
+    fn reason_phrase_survives_replay() {
+        let mut recorder = RecordingTransport::new(|_: &HttpRequest| {
+            HttpResponse::new("HTTP/1.1 301 Moved Permanently\nLocation: /new\n\n".to_string())
+        });
+        let request = HttpRequest::get("http://example.com/old".to_string()).unwrap();
+        recorder.send(&request).unwrap();
+
+        let mut replay = ReplayTransport::new(&recorder.archive()).unwrap();
+        let response = replay.send(&request).unwrap();
+        assert_eq!(response.reason(), "Moved Permanently".to_string());
+    }
+
+    #[test]
+    fn response_is_recorded_as_received() {
+        let raw = "HTTP/1.1 200 OK\r\ncontent-type:  text/html\r\nX-B: 1\r\nX-A: 2\r\n\r\nhi";
+        let mut recorder =
+            RecordingTransport::new(|_: &HttpRequest| HttpResponse::new(raw.to_string()));
+        let request = HttpRequest::get("http://example.com/".to_string()).unwrap();
+        recorder.send(&request).unwrap();
+        recorder.send_streaming(&request, &mut |_, _| {}).unwrap();
+
+        let archive = recorder.archive();
+        let expected = format!("response {}\n{}\n", raw.len(), raw);
+        assert_eq!(archive.matches(&expected).count(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn save_and_load_archive_file() {
+        let mut recorder = RecordingTransport::new(server);
+        let request = HttpRequest::get("http://example.com/old".to_string()).unwrap();
+        recorder.send(&request).unwrap();
+
+        let path = std::env::temp_dir().join(format!("saba-archive-{}", std::process::id()));
+        recorder.save(&path).unwrap();
+        let mut replay = ReplayTransport::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(replay.send(&request).unwrap().status_code(), 302);
+        assert!(ReplayTransport::load(&path).is_err());
+    }
+
+    #[test]
+    fn repeated_requests_are_served_in_order() {
+        let archive = "saba-archive 1\n\
+            request 25\nGET http://example.com/\n\n\n\
+            response 22\nHTTP/1.1 200 OK\n\nfirst\n\
+            request 25\nGET http://example.com/\n\n\n\
+            response 23\nHTTP/1.1 200 OK\n\nsecond\n";
+        let mut replay = ReplayTransport::new(archive).unwrap();
+        let request = HttpRequest::get("http://example.com/".to_string()).unwrap();
+
+        assert_eq!(replay.send(&request).unwrap().body(), "first".to_string());
+        assert_eq!(replay.send(&request).unwrap().body(), "second".to_string());
+        assert_eq!(replay.send(&request).unwrap().body(), "second".to_string());
+    }
+
+    #[test]
+    fn unknown_request_is_an_error() {
+        let mut replay = ReplayTransport::new("saba-archive 1\n").unwrap();
+        let request = HttpRequest::get("http://example.com/".to_string()).unwrap();
+
+        assert!(replay.send(&request).is_err());
+    }
+
+    #[test]
+    fn reject_malformed_archive() {
+        assert!(ReplayTransport::new("").is_err());
+        assert!(ReplayTransport::new("saba-archive 1\nrequest 100\nGET").is_err());
+        assert!(ReplayTransport::new("saba-archive 1\nresponse 0\n\n").is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use alloc::{
+    format,
     string::{String, ToString},
     vec::Vec,
 };
@@ -116,6 +117,93 @@ impl Url {
     pub fn search_part(&self) -> String {
         self.search_part.clone()
     }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Returns the serialized origin, e.g. `http://example.com:8080`. The port
+    /// is omitted when it is the default one.
+    pub fn origin(&self) -> String {
+        if self.port == "80" {
+            format!("http://{}", self.host)
+        } else {
+            format!("http://{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Returns the path and the query as they appear in an HTTP request line.
+    pub fn request_target(&self) -> String {
+        if self.search_part.is_empty() {
+            format!("/{}", self.path)
+        } else {
+            format!("/{}?{}", self.path, self.search_part)
+        }
+    }
+
+    /// Resolves `reference` (e.g. a `Location` header or an `href`) against
+    /// this URL.
+    pub fn resolve(&self, reference: &str) -> Result<Self, String> {
+        let reference = reference.trim();
+
+        if reference.contains("://") {
+            return Url::new(reference.to_string()).parse();
+        }
+
+        if let Some(rest) = reference.strip_prefix("//") {
+            return Url::new(format!("http://{}", rest)).parse();
+        }
+
+        if reference.is_empty() {
+            return Ok(self.clone());
+        }
+
+        if reference.starts_with('?') {
+            return Url::new(format!("{}/{}{}", self.origin(), self.path, reference)).parse();
+        }
+
+        let path = if let Some(absolute) = reference.strip_prefix('/') {
+            absolute.to_string()
+        } else {
+            match self.path.rfind('/') {
+                Some(index) => format!("{}{}", &self.path[..index + 1], reference),
+                None => reference.to_string(),
+            }
+        };
+
+        Url::new(format!("{}/{}", self.origin(), remove_dot_segments(&path))).parse()
+    }
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let (path, search_part) = match path.split_once('?') {
+        Some((p, s)) => (p, Some(s)),
+        None => (path, None),
+    };
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut ends_with_slash = false;
+    for segment in path.split('/') {
+        ends_with_slash = false;
+        match segment {
+            "." => ends_with_slash = true,
+            ".." => {
+                segments.pop();
+                ends_with_slash = true;
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut result = segments.join("/");
+    if ends_with_slash && !result.is_empty() {
+        result.push('/');
+    }
+    if let Some(s) = search_part {
+        result.push('?');
+        result.push_str(s);
+    }
+    result
 }
 
 #[cfg(test)]
@@ -237,4 +325,59 @@ mod tests {
             "page=2&order=asc".to_string()
         );
     }
+
+    #[test]
+    fn request_target_contains_path_and_search_part() {
+        let url = Url::new("http://example.com:8080/foo/bar?page=2".to_string())
+            .parse()
+            .unwrap();
+        assert_eq!(url.request_target(), "/foo/bar?page=2".to_string());
+        assert_eq!(url.origin(), "http://example.com:8080".to_string());
+    }
+
+    mod resolve {
+        use super::*;
+
+        fn base() -> Url {
+            Url::new("http://example.com/dir/page?x=1".to_string())
+                .parse()
+                .unwrap()
+        }
+
+        #[test]
+        fn absolute_url_replaces_base() {
+            assert_eq!(
+                base().resolve("http://other.com:81/a").unwrap().url(),
+                "http://other.com:81/a".to_string()
+            );
+        }
+
+        #[test]
+        fn absolute_path_keeps_origin() {
+            assert_eq!(
+                base().resolve("/login?next=1").unwrap().url(),
+                "http://example.com/login?next=1".to_string()
+            );
+        }
+
+        #[test]
+        fn relative_path_is_resolved_against_directory() {
+            assert_eq!(
+                base().resolve("other").unwrap().url(),
+                "http://example.com/dir/other".to_string()
+            );
+            assert_eq!(
+                base().resolve("../up/./file").unwrap().url(),
+                "http://example.com/up/file".to_string()
+            );
+        }
+
+        #[test]
+        fn query_only_keeps_path() {
+            assert_eq!(
+                base().resolve("?q=rust").unwrap().url(),
+                "http://example.com/dir/page?q=rust".to_string()
+            );
+        }
+    }
 }
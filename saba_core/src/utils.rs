@@ -1,28 +1,44 @@
 use core::cell::RefCell;
 
-use alloc::{format, rc::Rc, string::String};
+use alloc::{format, rc::Rc, string::String, vec, vec::Vec};
 
-use crate::renderer::dom::node::Node;
+use crate::renderer::dom::{node::Node, traversal::children};
 
-fn convert_dom_to_string_internal(
-    node: &Option<Rc<RefCell<Node>>>,
-    depth: usize,
-    result: &mut String,
-) {
-    match node {
-        Some(n) => {
+pub fn convert_dom_to_string(root: &Option<Rc<RefCell<Node>>>) -> String {
+    let mut result = String::from("\n");
+    if let Some(root) = root {
+        // nodes still to print in tree order, last first, with their depth
+        let mut stack = vec![(root.clone(), 0)];
+        while let Some((n, depth)) = stack.pop() {
+            let mut kids: Vec<_> = children(&n).map(|c| (c, depth + 1)).collect();
+            kids.reverse();
+            stack.append(&mut kids);
             result.push_str(&"  ".repeat(depth));
             result.push_str(&format!("{:?}", n.borrow().kind));
             result.push('\n');
-            convert_dom_to_string_internal(&n.borrow().first_child(), depth + 1, result);
-            convert_dom_to_string_internal(&n.borrow().next_sibling(), depth, result);
         }
-        None => (),
     }
+    result
 }
 
-pub fn convert_dom_to_string(root: &Option<Rc<RefCell<Node>>>) -> String {
-    let mut result = String::from("\n");
-    convert_dom_to_string_internal(root, 0, &mut result);
-    result
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::string::ToString;
+    use crate::renderer::html::{parser::HtmlParser, token::HtmlTokenizer};
+
+    #[test]
+    fn test_convert_dom_to_string_indents_by_depth() {
+        let html = "<html><head></head><body><p>a<b>b</b></p><p>c</p></body></html>";
+        let window = HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree();
+        let document = window.borrow().document();
+        let dump = convert_dom_to_string(&Some(document));
+        let depths: Vec<usize> = dump
+            .lines()
+            .skip(1)
+            .map(|l| (l.len() - l.trim_start().len()) / 2)
+            .collect();
+        // document, html, head, body, p, "a", b, "b", p, "c"
+        assert_eq!(depths, [0, 1, 2, 2, 3, 4, 4, 5, 3, 4]);
+    }
 }
@@ -0,0 +1,750 @@
+//! Runs the html5lib-tests files in `tests/html5lib` against the tokenizer
+//! and the tree builder, and prints how many cases of each file pass.
+//!
+//! Cases the parser is known to fail are listed in
+//! `tests/html5lib/known-failures.txt`. The test fails when any other case
+//! fails, or when a listed case starts to pass so that the list can be
+//! trimmed. Run `cargo test --test html5lib -- --nocapture` to see the
+//! report, and set `HTML5LIB_VERBOSE` to also see the failing cases.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use saba_core::renderer::{
+    dom::node::{Element, Namespace, Node, NodeKind},
+    html::{
+        parser::HtmlParser,
+        token::{HtmlToken, HtmlTokenizer, TokenizerState},
+    },
+};
+
+#[derive(Default)]
+struct Report {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+    /// Cases whose outcome doesn't agree with the known failures.
+    unexpected: Vec<String>,
+}
+
+enum Outcome {
+    Pass,
+    /// Holds what was expected and what came out.
+    Fail(String),
+    /// The case needs something the parser doesn't support.
+    Skip,
+}
+
+impl Report {
+    fn print(&self, file: &str) {
+        println!(
+            "{}: {} passed, {} failed, {} skipped",
+            file, self.passed, self.failed, self.skipped
+        );
+    }
+
+    /// Counts the outcome of the case `id`, which is described by `name`.
+    fn add(&mut self, id: &str, name: &str, outcome: Outcome, known_failures: &BTreeSet<String>) {
+        let known = known_failures.contains(id);
+        match outcome {
+            Outcome::Pass => {
+                self.passed += 1;
+                if known {
+                    self.unexpected.push(format!("{} passes now", id));
+                }
+            }
+            Outcome::Fail(detail) => {
+                self.failed += 1;
+                if std::env::var_os("HTML5LIB_VERBOSE").is_some() {
+                    println!("FAIL {} {}\n{}", id, name, detail);
+                }
+                if !known {
+                    self.unexpected
+                        .push(format!("{} fails: {}\n{}", id, name, detail));
+                }
+            }
+            Outcome::Skip => self.skipped += 1,
+        }
+    }
+
+    fn merge(&mut self, other: Report) {
+        self.passed += other.passed;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+        self.unexpected.extend(other.unexpected);
+    }
+
+    /// Prints the totals and fails if any case had an unexpected outcome.
+    fn finish(self, kind: &str) {
+        self.print(&format!("all {} tests", kind));
+        assert!(
+            self.unexpected.is_empty(),
+            "{} cases don't agree with known-failures.txt:\n{}",
+            self.unexpected.len(),
+            self.unexpected.join("\n")
+        );
+    }
+}
+
+/// Reads the ids of the cases that are known to fail, one per line, such as
+/// `tests1.dat:12`. Lines starting with `#` are comments.
+fn known_failures() -> BTreeSet<String> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/html5lib/known-failures.txt");
+    let list = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", path.display(), e));
+    list.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Runs a case, counting a panic as a failure.
+fn run_case(case: impl FnOnce() -> Outcome) -> Outcome {
+    match catch_unwind(AssertUnwindSafe(case)) {
+        Ok(outcome) => outcome,
+        Err(_) => Outcome::Fail("panicked".to_string()),
+    }
+}
+
+/// Returns the files in `tests/html5lib/<dir>` with the extension `ext`.
+fn test_files(dir: &str, ext: &str) -> Vec<PathBuf> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/html5lib")
+        .join(dir);
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", dir.display(), e))
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == ext))
+        .collect();
+    files.sort();
+    files
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+#[test]
+fn tokenizer() {
+    let known_failures = known_failures();
+    let mut total = Report::default();
+    for path in test_files("tokenizer", "test") {
+        let file = file_name(&path);
+        let source = fs::read_to_string(&path).unwrap();
+        let json = Json::parse(&source)
+            .unwrap_or_else(|e| panic!("{} is not valid JSON: {}", path.display(), e));
+
+        let mut report = Report::default();
+        let tests = json.get("tests").and_then(Json::as_array).unwrap_or(&[]);
+        for (i, test) in tests.iter().enumerate() {
+            let description = test.get("description").and_then(Json::as_str).unwrap_or("");
+            let states: Vec<&str> = match test.get("initialStates").and_then(Json::as_array) {
+                Some(states) => states.iter().filter_map(Json::as_str).collect(),
+                None => vec!["Data state"],
+            };
+            for state in states.iter() {
+                let id = match states.len() {
+                    1 => format!("{}:{}", file, i + 1),
+                    _ => format!("{}:{}:{}", file, i + 1, state),
+                };
+                let name = format!("{} ({})", description, state);
+                let outcome = run_case(|| run_tokenizer_test(test, state));
+                report.add(&id, &name, outcome, &known_failures);
+            }
+        }
+        // these expect the output of a tokenizer that makes XML out of HTML,
+        // which this one doesn't do
+        if let Some(tests) = json.get("xmlViolationTests").and_then(Json::as_array) {
+            report.skipped += tests.len();
+        }
+        report.print(&file);
+        total.merge(report);
+    }
+    total.finish("tokenizer");
+}
+
+fn run_tokenizer_test(test: &Json, state: &str) -> Outcome {
+    let state = match state {
+        "Data state" => TokenizerState::Data,
+        "PLAINTEXT state" => TokenizerState::Plaintext,
+        "RCDATA state" => TokenizerState::Rcdata,
+        "RAWTEXT state" => TokenizerState::Rawtext,
+        "Script data state" => TokenizerState::ScriptData,
+        "CDATA section state" => TokenizerState::CdataSection,
+        _ => return Outcome::Skip,
+    };
+    let double_escaped = test.get("doubleEscaped") == Some(&Json::Bool(true));
+    let unescape = |s: &str| {
+        if double_escaped {
+            unescape_double_escaped(s)
+        } else {
+            s.to_string()
+        }
+    };
+
+    let input = unescape(test.get("input").and_then(Json::as_str).unwrap_or(""));
+    let mut t = HtmlTokenizer::new(input);
+    t.switch_to(state);
+    if let Some(tag) = test.get("lastStartTag").and_then(Json::as_str) {
+        t.set_last_start_tag(tag);
+    }
+
+    let mut tokens = Vec::new();
+    for token in t.by_ref() {
+        push_token(&mut tokens, token);
+    }
+    let actual = Json::Array(tokens);
+
+    let expected = match test.get("output") {
+        Some(output) => output.map_strings(&unescape),
+        None => Json::Array(Vec::new()),
+    };
+    let (expected, inline_errors) = take_inline_errors(expected);
+    if actual != expected {
+        return Outcome::Fail(format!("expected {:?}\nactual   {:?}", expected, actual));
+    }
+
+    // older files only tell how many errors there are
+    if test.get("errors").is_none() {
+        let errors = t.errors();
+        if errors.len() != inline_errors {
+            return Outcome::Fail(format!(
+                "expected {} errors\nactual errors {:?}",
+                inline_errors,
+                errors.iter().map(|e| e.code()).collect::<Vec<&str>>()
+            ));
+        }
+        return Outcome::Pass;
+    }
+
+    let mut expected_errors: Vec<&str> = test
+        .get("errors")
+        .and_then(Json::as_array)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|e| e.get("code").and_then(Json::as_str))
+        .collect();
+    let errors = t.errors();
+    let mut actual_errors: Vec<&str> = errors.iter().map(|e| e.code()).collect();
+    expected_errors.sort();
+    actual_errors.sort();
+    if actual_errors != expected_errors {
+        return Outcome::Fail(format!(
+            "expected errors {:?}\nactual errors   {:?}",
+            expected_errors, actual_errors
+        ));
+    }
+
+    Outcome::Pass
+}
+
+/// Removes the `"ParseError"` entries that older files put among the
+/// expected tokens, and returns the tokens left along with how many there
+/// were. Characters on both sides of an error make up a single token.
+fn take_inline_errors(output: Json) -> (Json, usize) {
+    let mut errors = 0;
+    let mut tokens: Vec<Json> = Vec::new();
+    for token in output.as_array().unwrap_or(&[]) {
+        if token.as_str() == Some("ParseError") {
+            errors += 1;
+            continue;
+        }
+        if let (Some(Json::Array(last)), Some(chars)) = (tokens.last_mut(), token.as_array()) {
+            let is_character = |t: &[Json]| t.first().and_then(Json::as_str) == Some("Character");
+            if is_character(last) && is_character(chars) {
+                if let (Some(Json::String(s)), Some(more)) =
+                    (last.get_mut(1), chars.get(1).and_then(Json::as_str))
+                {
+                    s.push_str(more);
+                    continue;
+                }
+            }
+        }
+        tokens.push(token.clone());
+    }
+    (Json::Array(tokens), errors)
+}
+
+/// Adds a token in the html5lib format, where adjacent characters make up a
+/// single token.
+fn push_token(tokens: &mut Vec<Json>, token: HtmlToken) {
+    let string = |s: &str| Json::String(s.to_string());
+    let optional = |s: Option<String>| s.map(Json::String).unwrap_or(Json::Null);
+    let json = match token {
+        HtmlToken::Char(c) => {
+            if let Some(Json::Array(last)) = tokens.last_mut() {
+                if last.first() == Some(&string("Character")) {
+                    if let Some(Json::String(ref mut s)) = last.get_mut(1) {
+                        s.push(c);
+                        return;
+                    }
+                }
+            }
+            vec![string("Character"), Json::String(c.to_string())]
+        }
+        HtmlToken::StartTag {
+            tag,
+            self_closing,
+            attributes,
+        } => {
+            let attributes = attributes
+                .iter()
+                .map(|a| (a.name(), Json::String(a.value())))
+                .collect();
+            let mut json = vec![
+                string("StartTag"),
+                Json::String(tag),
+                Json::Object(attributes),
+            ];
+            if self_closing {
+                json.push(Json::Bool(true));
+            }
+            json
+        }
+        HtmlToken::EndTag { tag } => vec![string("EndTag"), Json::String(tag)],
+        HtmlToken::Comment(data) => vec![string("Comment"), Json::String(data)],
+        HtmlToken::Doctype {
+            name,
+            public_id,
+            system_id,
+            force_quirks,
+        } => vec![
+            string("DOCTYPE"),
+            optional(name),
+            optional(public_id),
+            optional(system_id),
+            Json::Bool(!force_quirks),
+        ],
+        HtmlToken::Eof => return,
+    };
+    tokens.push(Json::Array(json));
+}
+
+/// Decodes the `\uXXXX` escapes of a test marked `doubleEscaped`.
+fn unescape_double_escaped(s: &str) -> String {
+    let mut units = Vec::new();
+    let mut result = String::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        if let Some(hex) = rest.strip_prefix("\\u").and_then(|r| r.get(..4)) {
+            if let Ok(unit) = u16::from_str_radix(hex, 16) {
+                units.push(unit);
+                rest = &rest[6..];
+                continue;
+            }
+        }
+        result.push_str(&String::from_utf16_lossy(&units));
+        units.clear();
+        let c = rest.chars().next().unwrap();
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    result.push_str(&String::from_utf16_lossy(&units));
+    result
+}
+
+#[test]
+fn tree_construction() {
+    let known_failures = known_failures();
+    let mut total = Report::default();
+    for path in test_files("tree-construction", "dat") {
+        let file = file_name(&path);
+        let source = fs::read_to_string(&path).unwrap();
+
+        let mut report = Report::default();
+        for (i, test) in parse_dat(&source).iter().enumerate() {
+            let id = format!("{}:{}", file, i + 1);
+            let outcome = run_case(|| run_tree_construction_test(test));
+            report.add(&id, &test.data, outcome, &known_failures);
+        }
+        report.print(&file);
+        total.merge(report);
+    }
+    total.finish("tree construction");
+}
+
+#[derive(Default)]
+struct TreeTest {
+    data: String,
+    document: String,
+    fragment: Option<String>,
+    scripting: bool,
+}
+
+/// Splits a `.dat` file into its tests. Each test is a `#data` section
+/// followed by others such as `#errors` and `#document`.
+fn parse_dat(source: &str) -> Vec<TreeTest> {
+    let mut tests: Vec<TreeTest> = Vec::new();
+    let mut section = "";
+    let mut lines: Vec<&str> = Vec::new();
+
+    for line in source.lines().chain(["#data"]) {
+        let is_header = matches!(
+            line,
+            "#data"
+                | "#errors"
+                | "#new-errors"
+                | "#document"
+                | "#document-fragment"
+                | "#script-on"
+                | "#script-off"
+        );
+        if !is_header {
+            lines.push(line);
+            continue;
+        }
+
+        if let Some(test) = tests.last_mut() {
+            match section {
+                "#data" => test.data = lines.join("\n"),
+                "#document" => {
+                    // a blank line separates the tests
+                    while lines.last() == Some(&"") {
+                        lines.pop();
+                    }
+                    test.document = lines.join("\n");
+                }
+                "#document-fragment" => test.fragment = Some(lines.join("\n")),
+                _ => {}
+            }
+        }
+        lines.clear();
+
+        match line {
+            "#data" => tests.push(TreeTest::default()),
+            "#script-on" => {
+                if let Some(test) = tests.last_mut() {
+                    test.scripting = true;
+                }
+            }
+            _ => {}
+        }
+        section = line;
+    }
+
+    // the extra `#data` at the end starts an empty test
+    tests.pop();
+    tests
+}
+
+fn run_tree_construction_test(test: &TreeTest) -> Outcome {
+    // the parser doesn't run scripts
+    if test.scripting {
+        return Outcome::Skip;
+    }
+
+    let nodes = match test.fragment {
+        Some(ref context) => {
+            // a context in another namespace is written as `svg path`
+            let (namespace, name) = match context.split_once(' ') {
+                Some(("svg", name)) => (Namespace::Svg, name),
+                Some(("math", name)) => (Namespace::MathMl, name),
+                Some(_) => return Outcome::Skip,
+                None => (Namespace::Html, context.as_str()),
+            };
+            let context = Rc::new(RefCell::new(Node::new(NodeKind::Element(
+                Element::new_in_namespace(namespace, name, Vec::new()),
+            ))));
+            HtmlParser::parse_fragment(&context, test.data.clone())
+        }
+        None => {
+            let t = HtmlTokenizer::new(test.data.clone());
+            let window = HtmlParser::new(t).construct_tree();
+            let document = window.borrow().document();
+            let mut nodes = Vec::new();
+            let mut child = document.borrow().first_child();
+            while let Some(node) = child {
+                child = node.borrow().next_sibling();
+                nodes.push(node);
+            }
+            nodes
+        }
+    };
+
+    let mut lines = Vec::new();
+    for node in nodes.iter() {
+        describe(node, 0, &mut lines);
+    }
+    let actual = lines.join("\n");
+
+    if actual == test.document {
+        Outcome::Pass
+    } else {
+        Outcome::Fail(format!("expected\n{}\nactual\n{}", test.document, actual))
+    }
+}
+
+/// Writes `node` and its descendants in the html5lib tree format.
+fn describe(node: &Rc<RefCell<Node>>, depth: usize, lines: &mut Vec<String>) {
+    let indent = format!("| {}", "  ".repeat(depth));
+    match node.borrow().kind {
+        NodeKind::Document(_) => {}
+        NodeKind::DocumentType {
+            ref name,
+            ref public_id,
+            ref system_id,
+        } => {
+            if public_id.is_empty() && system_id.is_empty() {
+                lines.push(format!("{}<!DOCTYPE {}>", indent, name));
+            } else {
+                lines.push(format!(
+                    "{}<!DOCTYPE {} \"{}\" \"{}\">",
+                    indent, name, public_id, system_id
+                ));
+            }
+        }
+        NodeKind::Element(ref e) => {
+            let prefix = match e.namespace() {
+                Namespace::Html => "",
+                Namespace::Svg => "svg ",
+                Namespace::MathMl => "math ",
+            };
+            lines.push(format!("{}<{}{}>", indent, prefix, e.local_name()));
+
+            // attributes such as `xlink:href` are written with their
+            // namespace, as `xlink href`
+            let mut attributes: Vec<(String, String)> = e
+                .attribute_iter()
+                .map(|a| {
+                    let name = match a.name().split_once(':') {
+                        Some((prefix, local)) if e.namespace() != Namespace::Html => {
+                            format!("{} {}", prefix, local)
+                        }
+                        _ => a.name(),
+                    };
+                    (name, a.value())
+                })
+                .collect();
+            attributes.sort();
+            for (name, value) in attributes {
+                lines.push(format!("{}  {}=\"{}\"", indent, name, value));
+            }
+
+            if let Some(content) = e.template_content() {
+                lines.push(format!("{}  content", indent));
+                let mut child = content.borrow().first_child();
+                while let Some(c) = child {
+                    describe(&c, depth + 2, lines);
+                    child = c.borrow().next_sibling();
+                }
+            }
+        }
+        NodeKind::DocumentFragment => {}
+        NodeKind::Text(ref s) => lines.push(format!("{}\"{}\"", indent, s)),
+        NodeKind::Comment(ref s) => lines.push(format!("{}<!-- {} -->", indent, s)),
+    }
+
+    let mut child = node.borrow().first_child();
+    while let Some(c) = child {
+        describe(&c, depth + 1, lines);
+        child = c.borrow().next_sibling();
+    }
+}
+
+/// Just enough JSON for the tokenizer tests.
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn parse(source: &str) -> Result<Json, String> {
+        let mut parser = JsonParser {
+            chars: source.chars().collect(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.chars.len() {
+            return Err(format!("unexpected input at {}", parser.pos));
+        }
+        Ok(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Applies `f` to every string in the value, keys included.
+    fn map_strings(&self, f: &dyn Fn(&str) -> String) -> Json {
+        match self {
+            Json::String(s) => Json::String(f(s)),
+            Json::Array(a) => Json::Array(a.iter().map(|v| v.map_strings(f)).collect()),
+            Json::Object(map) => {
+                Json::Object(map.iter().map(|(k, v)| (f(k), v.map_strings(f))).collect())
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl JsonParser {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.chars.get(self.pos) != Some(&c) {
+            return Err(format!("expected '{}' at {}", c, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.chars.get(self.pos) {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.chars.get(self.pos) {
+                        Some(',') => self.pos += 1,
+                        Some(']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(values));
+                        }
+                        _ => return Err(format!("expected ',' or ']' at {}", self.pos)),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut map = BTreeMap::new();
+                self.skip_whitespace();
+                if self.chars.get(self.pos) == Some(&'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(map));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    let value = self.value()?;
+                    map.insert(key, value);
+                    self.skip_whitespace();
+                    match self.chars.get(self.pos) {
+                        Some(',') => self.pos += 1,
+                        Some('}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(map));
+                        }
+                        _ => return Err(format!("expected ',' or '}}' at {}", self.pos)),
+                    }
+                }
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+                {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| format!("invalid number at {}", start))
+            }
+            _ => Err(format!("unexpected input at {}", self.pos)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        // kept as UTF-16 so that an escaped surrogate pair makes up one
+        // character, while a lone surrogate becomes U+FFFD
+        let mut units: Vec<u16> = Vec::new();
+        loop {
+            let c = match self.chars.get(self.pos) {
+                Some(c) => *c,
+                None => return Err("unterminated string".to_string()),
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(String::from_utf16_lossy(&units)),
+                '\\' => {
+                    let escaped = self.chars.get(self.pos).copied();
+                    self.pos += 1;
+                    let unit = match escaped {
+                        Some('n') => '\n' as u16,
+                        Some('t') => '\t' as u16,
+                        Some('r') => '\r' as u16,
+                        Some('b') => 0x08,
+                        Some('f') => 0x0C,
+                        Some('u') => {
+                            let hex: String = self.chars.iter().skip(self.pos).take(4).collect();
+                            self.pos += 4;
+                            u16::from_str_radix(&hex, 16)
+                                .map_err(|_| format!("invalid escape at {}", self.pos))?
+                        }
+                        Some(c) => c as u16,
+                        None => return Err("unterminated string".to_string()),
+                    };
+                    units.push(unit);
+                }
+                c => {
+                    let mut buf = [0; 2];
+                    units.extend_from_slice(c.encode_utf16(&mut buf));
+                }
+            }
+        }
+    }
+}
@@ -3,74 +3,39 @@
 
 extern crate alloc;
 
-use alloc::{format, rc::Rc, string::String};
+use alloc::rc::Rc;
 use core::cell::RefCell;
 use net_wasabi::http::HttpClient;
 use noli::prelude::{entry_point, println};
-use saba_core::{browser::Browser, error::Error, http::HttpResponse, url::Url};
+#[cfg(feature = "record")]
+use saba_core::transport::RecordingTransport;
+use saba_core::{browser::Browser, transport::ReplayTransport};
 use ui_wasabi::app::WasabiUI;
 
-fn handle_url(url: String) -> Result<HttpResponse, Error> {
-    let parsed_url = match Url::new(url.clone()).parse() {
-        Ok(url) => url,
-        Err(e) => {
-            return Err(Error::UnexpectedInput(format!(
-                "input html is not supported: {:?}",
-                e
-            )))
-        }
-    };
-
-    let client = HttpClient::new();
-    let response = match client.get(
-        parsed_url.host(),
-        parsed_url.port().parse::<u16>().expect(&format!(
-            "port number should be u16 but got {}",
-            parsed_url.port()
-        )),
-        parsed_url.path(),
-    ) {
-        Ok(res) => {
-            if res.status_code() == 302 {
-                let location = match res.header_value("Location") {
-                    Ok(value) => value,
-                    Err(_) => return Ok(res),
-                };
-                let redirect_parsed_url = Url::new(location);
-
-                let redirect_res = match client.get(
-                    redirect_parsed_url.host(),
-                    redirect_parsed_url.port().parse::<u16>().expect(&format!(
-                        "port number should be u16 but get {}",
-                        parsed_url.port()
-                    )),
-                    redirect_parsed_url.path(),
-                ) {
-                    Ok(res) => res,
-                    Err(e) => return Err(Error::Network(format!("{:?}", e))),
-                };
-
-                redirect_res
-            } else {
-                res
-            }
-        }
-        Err(e) => {
-            return Err(Error::Network(format!(
-                "failed to get http response: {:?}",
-                e
-            )));
-        }
-    };
-
-    Ok(response)
-}
+/// The archive embedded by build.rs when `SABA_REPLAY_ARCHIVE` is set at
+/// build time, or empty.
+const REPLAY_ARCHIVE: &str = include_str!(concat!(env!("OUT_DIR"), "/replay_archive"));
 
 fn main() -> u64 {
     let browser = Browser::new();
     let ui = Rc::new(RefCell::new(WasabiUI::new(browser)));
 
-    match ui.borrow_mut().start(handle_url) {
+    let result = if REPLAY_ARCHIVE.is_empty() {
+        // With the `record` feature, every exchange is kept so that the UI can
+        // dump it as an archive.
+        #[cfg(feature = "record")]
+        let mut transport = RecordingTransport::new(HttpClient::new());
+        #[cfg(not(feature = "record"))]
+        let mut transport = HttpClient::new();
+        ui.borrow_mut().start(&mut transport)
+    } else {
+        match ReplayTransport::new(REPLAY_ARCHIVE) {
+            Ok(mut replay) => ui.borrow_mut().start(&mut replay),
+            Err(e) => Err(e),
+        }
+    };
+
+    match result {
         Ok(_) => {}
         Err(e) => {
             println!("browser fails to start {:?}", e);
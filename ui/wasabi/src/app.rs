@@ -1,7 +1,8 @@
 use crate::{
     constants::{
-        ADDRESS_BAR_HEIGHT, BLACK, DARK_GRAY, GRAY, LIGHT_GRAY, TITLE_BAR_HEIGHT, TOOLBAR_HEIGHT,
-        WHITE, WINDOW_HEIGHT, WINDOW_INIT_X_POS, WINDOW_INIT_Y_POS, WINDOW_WIDTH,
+        ADDRESS_BAR_HEIGHT, BLACK, CHAR_WIDTH, DARK_GRAY, GRAY, LIGHT_GRAY, LINE_HEIGHT,
+        TITLE_BAR_HEIGHT, TOOLBAR_HEIGHT, WHITE, WINDOW_HEIGHT, WINDOW_INIT_X_POS,
+        WINDOW_INIT_Y_POS, WINDOW_PADDING, WINDOW_WIDTH,
     },
     cursor::Cursor,
 };
@@ -9,6 +10,7 @@ use alloc::{
     format,
     rc::Rc,
     string::{String, ToString},
+    vec::Vec,
 };
 use core::cell::RefCell;
 use noli::{
@@ -19,12 +21,25 @@ use noli::{
     sys::{api::MouseEvent, wasabi::Api},
     window::{StringSize, Window},
 };
-use saba_core::{browser::Browser, error::Error, http::HttpResponse};
+use saba_core::{
+    auth::{Credentials, ProtectionSpace},
+    browser::Browser,
+    error::Error,
+    http::HttpRequest,
+    navigation::load,
+    renderer::dom::{
+        node::{ElementKind, Node, NodeKind},
+        traversal::descendants,
+    },
+    transport::HttpTransport,
+};
 
 #[derive(PartialEq)]
 enum InputMode {
     Normal,
     Editing,
+    /// Typing into the focused form field.
+    Typing,
 }
 
 pub struct WasabiUI {
@@ -33,6 +48,10 @@ pub struct WasabiUI {
     input_mode: InputMode,
     window: Window,
     cursor: Cursor,
+    /// The elements of the page the user can interact with, in the order
+    /// they are listed in the content area.
+    elements: Vec<Rc<RefCell<Node>>>,
+    focused: Option<Rc<RefCell<Node>>>,
 }
 
 impl WasabiUI {
@@ -51,6 +70,8 @@ impl WasabiUI {
             )
             .unwrap(),
             cursor: Cursor::new(),
+            elements: Vec::new(),
+            focused: None,
         }
     }
 
@@ -101,7 +122,7 @@ impl WasabiUI {
         Ok(())
     }
 
-    fn handle_mouse_input(&mut self) -> Result<(), Error> {
+    fn handle_mouse_input(&mut self, transport: &mut dyn HttpTransport) -> Result<(), Error> {
         if let Some(MouseEvent { button, position }) = Api::get_mouse_cursor_info() {
             let relative_pos = (
                 position.x - WINDOW_INIT_X_POS,
@@ -140,6 +161,18 @@ impl WasabiUI {
                 }
 
                 self.input_mode = InputMode::Normal;
+                self.focused = None;
+
+                // click on an element listed in the content area
+                let line = (relative_pos.1 - TITLE_BAR_HEIGHT - content_top() - WINDOW_PADDING)
+                    .div_euclid(LINE_HEIGHT);
+                if let Some(element) = usize::try_from(line)
+                    .ok()
+                    .and_then(|i| self.elements.get(i))
+                    .cloned()
+                {
+                    self.click(transport, element)?;
+                }
             }
         }
 
@@ -147,6 +180,11 @@ impl WasabiUI {
     }
 
     fn update_address_bar(&mut self) -> Result<(), Error> {
+        let text = self.input_url.clone();
+        self.draw_address_bar(&text)
+    }
+
+    fn draw_address_bar(&mut self, text: &str) -> Result<(), Error> {
         if self
             .window
             .fill_rect(WHITE, 72, 4, WINDOW_WIDTH - 76, ADDRESS_BAR_HEIGHT - 2)
@@ -159,7 +197,7 @@ impl WasabiUI {
 
         if self
             .window
-            .draw_string(BLACK, 74, 6, &self.input_url, StringSize::Medium, false)
+            .draw_string(BLACK, 74, 6, text, StringSize::Medium, false)
             .is_err()
         {
             return Err(Error::InvalidUI(
@@ -204,35 +242,269 @@ impl WasabiUI {
         Ok(())
     }
 
+    /// Reads a line typed into the address bar after `label`. Returns `None`
+    /// when the user presses escape.
+    fn read_line(&mut self, label: &str, masked: bool) -> Result<Option<String>, Error> {
+        let mut input = String::new();
+
+        loop {
+            let shown = if masked {
+                "*".repeat(input.chars().count())
+            } else {
+                input.clone()
+            };
+            self.draw_address_bar(&format!("{} {}", label, shown))?;
+
+            let c = loop {
+                if let Some(c) = Api::read_key() {
+                    break c;
+                }
+            };
+
+            // enter
+            if c == 0x0A as char {
+                return Ok(Some(input));
+            } else if c == 0x1B as char {
+                // escape
+                return Ok(None);
+            } else if c == 0x7F as char || c == 0x08 as char {
+                // delete or backspace
+                input.pop();
+            } else {
+                input.push(c);
+            }
+        }
+    }
+
+    fn prompt_credentials(&mut self, space: &ProtectionSpace) -> Option<Credentials> {
+        let label = format!("{} ({})", space.realm(), space.host());
+        let username = match self.read_line(&format!("User for {}:", label), false) {
+            Ok(Some(username)) => username,
+            _ => return None,
+        };
+        let password = match self.read_line("Password:", true) {
+            Ok(Some(password)) => password,
+            _ => return None,
+        };
+
+        if self.clear_address_bar().is_err() {
+            println!("failed to clear an address bar after a credential prompt");
+        }
+
+        Some(Credentials::new(username, password))
+    }
+
     fn start_navigation(
         &mut self,
-        handle_url: fn(String) -> Result<HttpResponse, Error>,
+        transport: &mut dyn HttpTransport,
         destination: String,
     ) -> Result<(), Error> {
-        match handle_url(destination) {
-            Ok(response) => {
-                let page = self.browser.borrow().current_page();
-                page.borrow_mut().receive_response(response);
-            }
-            Err(e) => return Err(e),
+        let request = HttpRequest::get(destination)?;
+        self.load_request(transport, request)
+    }
+
+    fn load_request(
+        &mut self,
+        transport: &mut dyn HttpTransport,
+        request: HttpRequest,
+    ) -> Result<(), Error> {
+        let browser = self.browser.clone();
+        // the page is redrawn as it arrives, and the user may be asked for
+        // credentials in between; neither happens while the other is going on
+        let ui = RefCell::new(&mut *self);
+        load(
+            &browser,
+            request,
+            transport,
+            &mut |space| ui.borrow_mut().prompt_credentials(space),
+            &mut |page| {
+                let mut ui = ui.borrow_mut();
+                ui.elements = page.interactive_elements();
+                ui.focused = None;
+                // a failure to draw shows up again once the page has loaded
+                let _ = ui.draw_page();
+            },
+        )?;
+
+        self.elements = browser
+            .borrow()
+            .current_page()
+            .borrow()
+            .interactive_elements();
+        self.focused = None;
+        self.draw_page()
+    }
+
+    /// Activates `element` of the page. A text field gets focused for typing,
+    /// a link is followed and a submit button submits its form.
+    fn click(
+        &mut self,
+        transport: &mut dyn HttpTransport,
+        element: Rc<RefCell<Node>>,
+    ) -> Result<(), Error> {
+        if is_text_field(&element) {
+            self.focused = Some(element);
+            self.input_mode = InputMode::Typing;
+            return self.draw_page();
         }
 
-        Ok(())
+        let page = self.browser.borrow().current_page();
+        let request = page.borrow().activate(&element)?;
+        match request {
+            Some(request) => self.load_request(transport, request),
+            None => self.draw_page(),
+        }
     }
 
-    fn handle_key_input(
+    /// Handles a key typed into the focused form field. Enter submits the
+    /// form from a single-line field.
+    fn type_into_field(
         &mut self,
-        handle_url: fn(String) -> Result<HttpResponse, Error>,
+        transport: &mut dyn HttpTransport,
+        field: Rc<RefCell<Node>>,
+        c: char,
     ) -> Result<(), Error> {
-        match self.input_mode {
-            InputMode::Normal => {
-                let _ = Api::read_key();
+        let is_textarea = field.borrow().element_kind() == Some(ElementKind::Textarea);
+        let mut value = value_of(&field);
+
+        // enter
+        if c == 0x0A as char && !is_textarea {
+            self.input_mode = InputMode::Normal;
+            let page = self.browser.borrow().current_page();
+            let request = page.borrow().submit_implicitly(&field)?;
+            return match request {
+                Some(request) => self.load_request(transport, request),
+                None => self.draw_page(),
+            };
+        } else if c == 0x1B as char {
+            // escape
+            self.input_mode = InputMode::Normal;
+            self.focused = None;
+            return self.draw_page();
+        } else if c == 0x7F as char || c == 0x08 as char {
+            // delete or backspace
+            value.pop();
+        } else {
+            value.push(c);
+        }
+
+        if let NodeKind::Element(ref mut element) = field.borrow_mut().kind {
+            element.set_value(value);
+        }
+        self.draw_page()
+    }
+
+    fn clear_content_area(&mut self) -> Result<(), Error> {
+        if self
+            .window
+            .fill_rect(
+                WHITE,
+                0,
+                content_top(),
+                WINDOW_WIDTH,
+                WINDOW_HEIGHT - TITLE_BAR_HEIGHT - content_top(),
+            )
+            .is_err()
+        {
+            return Err(Error::InvalidUI(
+                "failed to clear the content area".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Draws `lines` in the content area from the top, as many as fit.
+    fn draw_lines(&mut self, lines: &[String]) -> Result<(), Error> {
+        let max_chars = ((WINDOW_WIDTH - WINDOW_PADDING * 2) / CHAR_WIDTH) as usize;
+        for (i, line) in lines.iter().take(max_lines()).enumerate() {
+            let line: String = line.chars().take(max_chars).collect();
+            if self
+                .window
+                .draw_string(
+                    BLACK,
+                    WINDOW_PADDING,
+                    content_top() + WINDOW_PADDING + LINE_HEIGHT * i as i64,
+                    &line,
+                    StringSize::Medium,
+                    false,
+                )
+                .is_err()
+            {
+                return Err(Error::InvalidUI(
+                    "failed to draw the content area".to_string(),
+                ));
             }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the elements of the page the user can interact with in the
+    /// content area, one per line, so that they can be clicked.
+    fn draw_page(&mut self) -> Result<(), Error> {
+        self.clear_content_area()?;
+
+        let lines: Vec<String> = self
+            .elements
+            .iter()
+            .map(|e| {
+                let focused = self.focused.as_ref().is_some_and(|f| Rc::ptr_eq(f, e));
+                format!("{}{}", if focused { "> " } else { "" }, describe(e))
+            })
+            .collect();
+        self.draw_lines(&lines)?;
+
+        self.window.flush();
+        Ok(())
+    }
+
+    /// Lists every request made so far in the content area, most recent last.
+    fn show_network_log(&mut self) -> Result<(), Error> {
+        self.clear_content_area()?;
+
+        let entries = self.browser.borrow().network_log().entries();
+        let skipped = entries.len().saturating_sub(max_lines());
+
+        let mut lines = Vec::new();
+        for entry in entries.iter().skip(skipped) {
+            let status = match entry.error() {
+                Some(_) => "ERR".to_string(),
+                None => entry.status().to_string(),
+            };
+            lines.push(format!(
+                "{} {} {} {}B {}ms",
+                status,
+                entry.method(),
+                entry.url(),
+                entry.response_body_size(),
+                entry.timings().total()
+            ));
+        }
+        self.draw_lines(&lines)?;
+
+        self.window.flush();
+        Ok(())
+    }
+
+    fn handle_key_input(&mut self, transport: &mut dyn HttpTransport) -> Result<(), Error> {
+        match self.input_mode {
+            InputMode::Normal => match Api::read_key() {
+                Some('n') => self.show_network_log()?,
+                // export the log as HAR to the console
+                Some('h') => println!("{}", self.browser.borrow().network_log().to_har()),
+                // dump the recorded archive to the console, to be replayed later
+                Some('a') => match transport.recorded_archive() {
+                    Some(archive) => println!("{}", archive),
+                    None => println!("this build does not record archives"),
+                },
+                _ => {}
+            },
             InputMode::Editing => {
                 if let Some(c) = Api::read_key() {
                     // enter
                     if c == 0x0A as char {
-                        self.start_navigation(handle_url, self.input_url.clone())?;
+                        self.start_navigation(transport, self.input_url.clone())?;
                         self.input_url = String::new();
                         self.input_mode = InputMode::Normal;
                     } else if c == 0x7F as char || c == 0x08 as char
@@ -246,27 +518,114 @@ impl WasabiUI {
                     }
                 }
             }
+            InputMode::Typing => {
+                if let (Some(c), Some(field)) = (Api::read_key(), self.focused.clone()) {
+                    self.type_into_field(transport, field, c)?;
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn run_app(
-        &mut self,
-        handle_url: fn(String) -> Result<HttpResponse, Error>,
-    ) -> Result<(), Error> {
+    fn run_app(&mut self, transport: &mut dyn HttpTransport) -> Result<(), Error> {
         loop {
-            self.handle_mouse_input()?;
-            self.handle_key_input(handle_url)?;
+            self.handle_mouse_input(transport)?;
+            self.handle_key_input(transport)?;
         }
     }
 
-    pub fn start(
-        &mut self,
-        handle_url: fn(String) -> Result<HttpResponse, Error>,
-    ) -> Result<(), Error> {
+    pub fn start(&mut self, transport: &mut dyn HttpTransport) -> Result<(), Error> {
         self.setup()?;
-        self.run_app(handle_url)?;
+        self.run_app(transport)?;
         Ok(())
     }
 }
+
+/// Returns the top of the content area, below the toolbar.
+fn content_top() -> i64 {
+    TOOLBAR_HEIGHT + 2
+}
+
+fn max_lines() -> usize {
+    ((WINDOW_HEIGHT - TITLE_BAR_HEIGHT - content_top() - WINDOW_PADDING) / LINE_HEIGHT) as usize
+}
+
+fn attribute(node: &Rc<RefCell<Node>>, name: &str) -> Option<String> {
+    match node.borrow().kind {
+        NodeKind::Element(ref element) => element.get_attribute(name),
+        _ => None,
+    }
+}
+
+fn input_type(node: &Rc<RefCell<Node>>) -> String {
+    attribute(node, "type")
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn text_of(node: &Rc<RefCell<Node>>) -> String {
+    descendants(node)
+        .filter_map(|n| match n.borrow().kind {
+            NodeKind::Text(ref s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns true for a form control the user types text into.
+fn is_text_field(node: &Rc<RefCell<Node>>) -> bool {
+    let kind = node.borrow().element_kind();
+    match kind {
+        Some(ElementKind::Textarea) => true,
+        Some(ElementKind::Input) => !matches!(
+            input_type(node).as_str(),
+            "checkbox" | "radio" | "submit" | "image" | "button" | "reset" | "file" | "hidden"
+        ),
+        _ => false,
+    }
+}
+
+/// Returns the current value of a form control.
+fn value_of(node: &Rc<RefCell<Node>>) -> String {
+    let (kind, dirty) = match node.borrow().kind {
+        NodeKind::Element(ref element) => (element.kind(), element.value()),
+        _ => return String::new(),
+    };
+    match (dirty, kind) {
+        (Some(value), _) => value,
+        (None, ElementKind::Textarea) => text_of(node),
+        (None, _) => attribute(node, "value").unwrap_or_default(),
+    }
+}
+
+/// Returns the line that stands for `node` in the content area.
+fn describe(node: &Rc<RefCell<Node>>) -> String {
+    let name = attribute(node, "name").unwrap_or_default();
+    let kind = node.borrow().element_kind();
+    match kind {
+        Some(ElementKind::A) => format!(
+            "{} ({})",
+            text_of(node).trim(),
+            attribute(node, "href").unwrap_or_default()
+        ),
+        Some(ElementKind::Button) => format!("<{}>", text_of(node).trim()),
+        Some(ElementKind::Input) => match input_type(node).as_str() {
+            "checkbox" | "radio" => {
+                let checked = match node.borrow().kind {
+                    NodeKind::Element(ref element) => element.checked(),
+                    _ => None,
+                };
+                let checked = checked.unwrap_or_else(|| attribute(node, "checked").is_some());
+                format!("[{}] {}", if checked { "x" } else { " " }, name)
+            }
+            "submit" | "image" | "button" | "reset" => {
+                let label = attribute(node, "value").unwrap_or("Submit".to_string());
+                format!("<{}>", label)
+            }
+            "password" => format!("{}: {}", name, "*".repeat(value_of(node).chars().count())),
+            _ => format!("{}: {}", name, value_of(node)),
+        },
+        _ => format!("{}: {}", name, value_of(node)),
+    }
+}
@@ -14,5 +14,8 @@ pub static WINDOW_PADDING: i64 = 5;
 pub static TOOLBAR_HEIGHT: i64 = 20;
 pub static ADDRESS_BAR_HEIGHT: i64 = 20;
 
+pub static CHAR_WIDTH: i64 = 8;
+pub static LINE_HEIGHT: i64 = 18;
+
 // defined in WasabiOS
 pub static TITLE_BAR_HEIGHT: i64 = 24;